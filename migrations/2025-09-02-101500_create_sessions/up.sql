@@ -0,0 +1,22 @@
+-- Your SQL goes here
+
+CREATE TABLE IF NOT EXISTS sessions(
+    id UUID PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    create_date TIMESTAMP NOT NULL,
+    expiration_date TIMESTAMP NOT NULL,
+    revocation_date TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS sessions_user_id_idx ON sessions(user_id);
+
+CREATE TABLE IF NOT EXISTS refresh_tokens(
+    id UUID PRIMARY KEY,
+    session_id UUID NOT NULL REFERENCES sessions(id) ON DELETE CASCADE,
+    token_hash VARCHAR(64) NOT NULL UNIQUE,
+    create_date TIMESTAMP NOT NULL,
+    expiration_date TIMESTAMP NOT NULL,
+    used_date TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS refresh_tokens_session_id_idx ON refresh_tokens(session_id);
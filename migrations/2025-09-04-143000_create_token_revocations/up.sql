@@ -0,0 +1,13 @@
+-- Your SQL goes here
+
+CREATE TABLE IF NOT EXISTS revoked_tokens(
+    jti UUID PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    expiration_date TIMESTAMP NOT NULL,
+    revocation_date TIMESTAMP NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS user_token_revocations(
+    user_id UUID PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    revoked_before TIMESTAMP NOT NULL
+);
@@ -0,0 +1,7 @@
+-- Your SQL goes here
+
+-- `user_type` used to be whatever the client sent on registration
+UPDATE users SET user_type = 'customer' WHERE user_type NOT IN ('customer', 'support', 'admin');
+
+ALTER TABLE users ADD CONSTRAINT users_user_type_check
+    CHECK (user_type IN ('customer', 'support', 'admin'));
@@ -0,0 +1,20 @@
+-- Your SQL goes here
+
+CREATE TABLE IF NOT EXISTS user_mfa(
+    user_id UUID PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    secret_ciphertext BYTEA NOT NULL,
+    secret_nonce BYTEA NOT NULL,
+    last_used_step BIGINT,
+    create_date TIMESTAMP NOT NULL,
+    enabled_date TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS mfa_recovery_codes(
+    id UUID PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    code_hash VARCHAR(64) NOT NULL,
+    create_date TIMESTAMP NOT NULL,
+    used_date TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS mfa_recovery_codes_user_id_idx ON mfa_recovery_codes(user_id);
@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE IF EXISTS oidc_auth_requests;
+DROP TABLE IF EXISTS oauth_identities;
+
+ALTER TABLE users ALTER COLUMN birthdate SET NOT NULL;
+ALTER TABLE users ALTER COLUMN document SET NOT NULL;
@@ -0,0 +1,23 @@
+-- Your SQL goes here
+
+-- Accounts created through a social login have no document or birthdate.
+ALTER TABLE users ALTER COLUMN document DROP NOT NULL;
+ALTER TABLE users ALTER COLUMN birthdate DROP NOT NULL;
+
+CREATE TABLE IF NOT EXISTS oauth_identities(
+    id UUID PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    provider VARCHAR(16) NOT NULL,
+    subject VARCHAR(255) NOT NULL,
+    create_date TIMESTAMP NOT NULL,
+    UNIQUE (provider, subject)
+);
+
+CREATE TABLE IF NOT EXISTS oidc_auth_requests(
+    state_hash VARCHAR(64) PRIMARY KEY,
+    provider VARCHAR(16) NOT NULL,
+    code_verifier VARCHAR(128) NOT NULL,
+    nonce VARCHAR(64) NOT NULL,
+    create_date TIMESTAMP NOT NULL,
+    expiration_date TIMESTAMP NOT NULL
+);
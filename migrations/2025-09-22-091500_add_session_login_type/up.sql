@@ -0,0 +1,3 @@
+-- Your SQL goes here
+
+ALTER TABLE sessions ADD COLUMN IF NOT EXISTS login_type VARCHAR(16) NOT NULL DEFAULT 'password';
@@ -0,0 +1,23 @@
+-- Your SQL goes here
+
+CREATE TABLE IF NOT EXISTS webauthn_credentials(
+    id UUID PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    credential_id BYTEA NOT NULL UNIQUE,
+    public_key BYTEA NOT NULL,
+    sign_count BIGINT NOT NULL,
+    name VARCHAR(64) NOT NULL,
+    create_date TIMESTAMP NOT NULL,
+    last_used_date TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS webauthn_credentials_user_id_idx ON webauthn_credentials(user_id);
+
+-- Registrations are bound to the signed-in user; logins start without one.
+CREATE TABLE IF NOT EXISTS webauthn_challenges(
+    challenge_hash VARCHAR(64) PRIMARY KEY,
+    ceremony VARCHAR(16) NOT NULL,
+    user_id UUID REFERENCES users(id) ON DELETE CASCADE,
+    create_date TIMESTAMP NOT NULL,
+    expiration_date TIMESTAMP NOT NULL
+);
@@ -0,0 +1,18 @@
+-- Your SQL goes here
+
+CREATE TABLE IF NOT EXISTS api_keys(
+    id UUID PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    name VARCHAR(64) NOT NULL,
+    -- Public part of the key, used to find the row before comparing the hash.
+    prefix VARCHAR(16) NOT NULL UNIQUE,
+    key_hash VARCHAR(64) NOT NULL,
+    -- Space separated; NULL means the key can do anything its owner can.
+    scopes TEXT,
+    create_date TIMESTAMP NOT NULL,
+    expiration_date TIMESTAMP,
+    last_used_date TIMESTAMP,
+    revocation_date TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS api_keys_user_id_idx ON api_keys(user_id);
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE IF EXISTS role_permissions;
+DROP TABLE IF EXISTS permissions;
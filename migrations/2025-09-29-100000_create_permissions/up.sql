@@ -0,0 +1,35 @@
+-- Your SQL goes here
+
+CREATE TABLE IF NOT EXISTS permissions(
+    name VARCHAR(64) PRIMARY KEY,
+    description VARCHAR(255) NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS role_permissions(
+    role VARCHAR(16) NOT NULL CHECK (role IN ('customer', 'support', 'admin')),
+    permission VARCHAR(64) NOT NULL REFERENCES permissions(name) ON DELETE CASCADE,
+    PRIMARY KEY (role, permission)
+);
+
+INSERT INTO permissions(name, description) VALUES
+    ('profile:read', 'Read your own account, passkeys and API keys'),
+    ('profile:write', 'Change your own account, password, second factors and API keys'),
+    ('images:upload', 'Upload images'),
+    ('users:read', 'Read the accounts of other users'),
+    ('users:write', 'Manage the accounts of other users')
+ON CONFLICT DO NOTHING;
+
+INSERT INTO role_permissions(role, permission) VALUES
+    ('customer', 'profile:read'),
+    ('customer', 'profile:write'),
+    ('customer', 'images:upload'),
+    ('support', 'profile:read'),
+    ('support', 'profile:write'),
+    ('support', 'images:upload'),
+    ('support', 'users:read'),
+    ('admin', 'profile:read'),
+    ('admin', 'profile:write'),
+    ('admin', 'images:upload'),
+    ('admin', 'users:read'),
+    ('admin', 'users:write')
+ON CONFLICT DO NOTHING;
@@ -0,0 +1,14 @@
+-- Your SQL goes here
+
+CREATE TABLE IF NOT EXISTS audit_events(
+    id UUID PRIMARY KEY,
+    -- Who did it and to whom. Kept when the users are deleted, so the trail outlives them.
+    actor_id UUID REFERENCES users(id) ON DELETE SET NULL,
+    target_user_id UUID REFERENCES users(id) ON DELETE SET NULL,
+    action VARCHAR(64) NOT NULL,
+    details JSONB,
+    create_date TIMESTAMP NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS audit_events_actor_id_idx ON audit_events(actor_id);
+CREATE INDEX IF NOT EXISTS audit_events_target_user_id_idx ON audit_events(target_user_id);
@@ -0,0 +1,10 @@
+-- Your SQL goes here
+
+ALTER TABLE sessions ADD COLUMN IF NOT EXISTS ip_address VARCHAR(45);
+ALTER TABLE sessions ADD COLUMN IF NOT EXISTS user_agent VARCHAR(512);
+ALTER TABLE sessions ADD COLUMN IF NOT EXISTS last_seen_date TIMESTAMP;
+UPDATE sessions SET last_seen_date = create_date WHERE last_seen_date IS NULL;
+ALTER TABLE sessions ALTER COLUMN last_seen_date SET NOT NULL;
+
+-- Sessions revoked in the last hour are loaded into the access token revocation cache.
+CREATE INDEX IF NOT EXISTS sessions_revocation_date_idx ON sessions(revocation_date);
@@ -0,0 +1,21 @@
+-- Your SQL goes here
+
+-- Accounts whose e-mails differ only by case can't be merged safely here, as they may belong to
+-- different people; they must be resolved by hand first (see "Social login" in the readme).
+DO $$
+DECLARE
+    duplicates BIGINT;
+BEGIN
+    SELECT count(*) INTO duplicates
+    FROM (
+        SELECT lower(email) FROM users GROUP BY lower(email) HAVING count(*) > 1
+    ) AS duplicated_emails;
+
+    IF duplicates > 0 THEN
+        RAISE EXCEPTION '% e-mail(s) are used by more than one account when ignoring case', duplicates
+            USING HINT = 'Change or delete the duplicated accounts as described under "Social login" in the readme, then run the migration again.';
+    END IF;
+END $$;
+
+-- E-mails are looked up ignoring case, so two accounts can't differ only by it.
+CREATE UNIQUE INDEX IF NOT EXISTS users_lower_email_idx ON users(lower(email));
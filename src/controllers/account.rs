@@ -0,0 +1,195 @@
+use std::{env, net::SocketAddr, time::Duration};
+
+use axum::{
+    Json,
+    extract::{ConnectInfo, State},
+};
+use diesel_async::{AsyncPgConnection, pooled_connection::deadpool::Pool};
+use dotenvy::dotenv;
+use hyper::StatusCode;
+
+use crate::{
+    controllers::{
+        audit::record_audit_event,
+        auth::CurrentUser,
+        hasher::{verify_dummy_password, verify_user_password},
+        login_throttle::{check_login_allowed, register_login_failure, register_login_success},
+        session::end_all_sessions,
+        utils::get_conn,
+    },
+    models::{
+        self,
+        audit::{ACCOUNT_DELETED, ACCOUNT_RESTORED},
+        error::ApiError,
+        user::{DeleteAccountInput, LoginUser},
+    },
+};
+
+/// Longest grace period accepted, so the deletion cutoff stays a valid date.
+const MAX_DELETION_GRACE_DAYS: i64 = 3650;
+
+/// Days a deleted account can still be restored, from `ACCOUNT_DELETION_GRACE_DAYS`, capped at
+/// [`MAX_DELETION_GRACE_DAYS`].
+pub fn get_deletion_grace_days() -> i64 {
+    dotenv().ok();
+
+    env::var("ACCOUNT_DELETION_GRACE_DAYS")
+        .ok()
+        .and_then(|value| value.trim().parse::<i64>().ok())
+        .filter(|days| *days >= 0)
+        .map(|days| days.min(MAX_DELETION_GRACE_DAYS))
+        .unwrap_or(30)
+}
+
+/// Minutes between runs of the purge job, from `ACCOUNT_PURGE_INTERVAL_MINUTES`.
+fn get_purge_interval_minutes() -> u64 {
+    dotenv().ok();
+
+    env::var("ACCOUNT_PURGE_INTERVAL_MINUTES")
+        .ok()
+        .and_then(|value| value.trim().parse::<u64>().ok())
+        .filter(|minutes| *minutes > 0)
+        .unwrap_or(60)
+}
+
+/// Deletes the accounts whose grace period is over.
+pub async fn purge_deleted_accounts(
+    pool: &Pool<AsyncPgConnection>,
+) -> Result<usize, (StatusCode, Json<String>)> {
+    let conn = &mut get_conn(pool).await?;
+
+    let cutoff = chrono::Utc::now().naive_utc() - chrono::Duration::days(get_deletion_grace_days());
+
+    match models::user::purge_deleted_users(conn, cutoff).await {
+        Ok(purged) => Ok(purged),
+        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, Json(e.to_string()))),
+    }
+}
+
+/// Runs [`purge_deleted_accounts`] in the background for as long as the server is up.
+pub fn spawn_account_purge_job(pool: Pool<AsyncPgConnection>) {
+    let period = Duration::from_secs(get_purge_interval_minutes() * 60);
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            if let Err((_, Json(e))) = purge_deleted_accounts(&pool).await {
+                tracing::error!("Failed to purge deleted accounts: {}", e);
+            }
+        }
+    });
+}
+
+/// Schedules the signed-in user's account for deletion and signs it out everywhere. It can be
+/// restored at `/user/restore` until the grace period is over, after which it is deleted for good.
+#[utoipa::path(delete, path = "/user/me", request_body = DeleteAccountInput, responses((status = NO_CONTENT), (status = FORBIDDEN)), security(("bearer_auth" = ["profile:write"])))]
+pub async fn api_delete_account(
+    State(pool): State<Pool<AsyncPgConnection>>,
+    CurrentUser { user, .. }: CurrentUser,
+    input: Json<DeleteAccountInput>,
+) -> Result<StatusCode, (StatusCode, Json<String>)> {
+    if input.password.is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ApiError::InvalidData.to_string()),
+        ));
+    }
+
+    if !verify_user_password(&input.password, &user.password)
+        .await?
+        .valid
+    {
+        return Err((
+            StatusCode::FORBIDDEN,
+            Json(ApiError::InvalidPassword.to_string()),
+        ));
+    }
+
+    let conn = &mut get_conn(&pool).await?;
+
+    let now = chrono::Utc::now().naive_utc();
+    if let Err(e) = models::user::set_deletion_date(conn, &user.id, Some(now)).await {
+        return Err((StatusCode::INTERNAL_SERVER_ERROR, Json(e.to_string())));
+    }
+
+    end_all_sessions(conn, &user.id).await?;
+
+    record_audit_event(conn, user.id, ACCOUNT_DELETED, Some(user.id), None).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Cancels the deletion of an account within its grace period. Takes the same credentials as
+/// `/user/login` and is throttled the same way; the user signs in afterwards as usual.
+#[utoipa::path(post, path = "/user/restore", request_body = LoginUser, responses((status = OK), (status = GONE)))]
+pub async fn api_restore_account(
+    State(pool): State<Pool<AsyncPgConnection>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    input: Json<LoginUser>,
+) -> Result<StatusCode, (StatusCode, Json<String>)> {
+    let mut input = input.0;
+
+    match input.validate_fields() {
+        Ok(_) => {}
+        Err(e) => return Err((StatusCode::BAD_REQUEST, Json(e))),
+    }
+    input.parse_fields();
+
+    let ip = addr.ip();
+    let conn = &mut get_conn(&pool).await?;
+
+    check_login_allowed(conn, &input.email, ip).await?;
+
+    let invalid_credentials = || {
+        (
+            StatusCode::UNAUTHORIZED,
+            Json(ApiError::InvalidCredentials.to_string()),
+        )
+    };
+
+    let user = match models::user::find_user_by_email(conn, &input.email).await {
+        Ok(user) => user,
+        Err(_) => {
+            verify_dummy_password(&input.password).await;
+            register_login_failure(conn, &input.email, ip).await?;
+            return Err(invalid_credentials());
+        }
+    };
+
+    if !verify_user_password(&input.password, &user.password)
+        .await?
+        .valid
+    {
+        register_login_failure(conn, &input.email, ip).await?;
+        return Err(invalid_credentials());
+    }
+
+    register_login_success(conn, &input.email).await?;
+
+    let deletion_date = match user.deletion_date {
+        Some(date) => date,
+        None => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                Json(ApiError::AccountNotDeleted.to_string()),
+            ));
+        }
+    };
+
+    let now = chrono::Utc::now().naive_utc();
+    if deletion_date + chrono::Duration::days(get_deletion_grace_days()) <= now {
+        return Err((
+            StatusCode::GONE,
+            Json(ApiError::RestorePeriodExpired.to_string()),
+        ));
+    }
+
+    if let Err(e) = models::user::set_deletion_date(conn, &user.id, None).await {
+        return Err((StatusCode::INTERNAL_SERVER_ERROR, Json(e.to_string())));
+    }
+
+    record_audit_event(conn, user.id, ACCOUNT_RESTORED, Some(user.id), None).await?;
+
+    Ok(StatusCode::OK)
+}
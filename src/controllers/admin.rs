@@ -0,0 +1,463 @@
+use axum::{
+    Json,
+    extract::{Path, State},
+};
+use diesel_async::{AsyncPgConnection, pooled_connection::deadpool::Pool};
+use hyper::StatusCode;
+use uuid::Uuid;
+
+use crate::{
+    controllers::{
+        audit::record_audit_event,
+        auth::AuthUser,
+        authorization::role_scopes,
+        hasher::hash_password,
+        jwt::{IMPERSONATION_TOKEN_EXPIRATION_SECONDS, generate_impersonation_jwt},
+        login_throttle::unlock_account,
+        pagination::{CursorPagination, Filters, OffsetPagination, date_range, search_pattern},
+        revocation::revoke_all_user_tokens,
+        session::{end_all_sessions, generate_opaque_token},
+        user::request_password_reset,
+        utils::get_conn,
+    },
+    models::{
+        self,
+        audit::{
+            AuditEventFilter, AuditEventInfo, AuditEventKey, IMPERSONATION_STARTED, USER_ACTIVATED,
+            USER_DEACTIVATED, USER_PASSWORD_RESET_FORCED, USER_RESTORED, USER_ROLE_CHANGED,
+            USER_UNLOCKED,
+        },
+        error::ApiError,
+        jwt::Actor,
+        pagination::{CursorParams, OffsetParams, Page, Sort, SortParams},
+        role::UserRole,
+        session::{ImpersonateInput, ImpersonationToken},
+        user::{
+            AdminUserFilter, AdminUserResponse, ChangeRoleInput, User, UserAuthInfo, UserFilter,
+            UserSortField,
+        },
+    },
+};
+
+const MAX_IMPERSONATION_REASON_LENGTH: usize = 500;
+
+async fn find_target_user(
+    conn: &mut AsyncPgConnection,
+    id: &Uuid,
+) -> Result<User, (StatusCode, Json<String>)> {
+    match models::user::find_user_by_id(conn, id).await {
+        Ok(user) => Ok(user),
+        Err(_) => Err((
+            StatusCode::NOT_FOUND,
+            Json(ApiError::UserNotFound.to_string()),
+        )),
+    }
+}
+
+/// Admins can't lock themselves out by deactivating their own account or dropping their own role.
+fn reject_self(claims_id: &Uuid, user: &User) -> Result<(), (StatusCode, Json<String>)> {
+    if *claims_id == user.id {
+        return Err((
+            StatusCode::FORBIDDEN,
+            Json(ApiError::CannotModifySelf.to_string()),
+        ));
+    }
+    Ok(())
+}
+
+/// Lists the accounts, newest first by default, filtered by role, login type, status, creation
+/// date and a name or e-mail search. Sortable by `create_date`, `name`, `email` and `public_id`.
+#[utoipa::path(
+    get,
+    path = "/admin/users",
+    params(OffsetParams, SortParams, AdminUserFilter),
+    responses((status = OK, body = Page<AdminUserResponse>), (status = BAD_REQUEST)),
+    security(("bearer_auth" = ["users:read"]), ("api_key" = ["users:read"]))
+)]
+pub async fn api_list_users(
+    State(pool): State<Pool<AsyncPgConnection>>,
+    pagination: OffsetPagination,
+    sort: Sort<UserSortField>,
+    Filters(query): Filters<AdminUserFilter>,
+) -> Result<(StatusCode, Json<Page<AdminUserResponse>>), (StatusCode, Json<String>)> {
+    let created = date_range(query.created_from, query.created_to)?;
+
+    let filter = UserFilter {
+        user_type: query.user_type,
+        login_type: query.login_type,
+        is_active: query.is_active,
+        deleted: query.deleted,
+        created_from: created.start,
+        created_to: created.end,
+        search: search_pattern(query.search),
+    };
+
+    let conn = &mut get_conn(&pool).await?;
+
+    match models::user::list_users(conn, &filter, sort, pagination.limit(), pagination.offset())
+        .await
+    {
+        Ok((users, total)) => Ok((
+            StatusCode::OK,
+            Json(
+                Page::offset(users, pagination.page, pagination.per_page, total)
+                    .map(AdminUserResponse::from),
+            ),
+        )),
+        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, Json(e.to_string()))),
+    }
+}
+
+/// Lists the audit trail, newest first, filtered by who acted, on whom and the action.
+#[utoipa::path(
+    get,
+    path = "/admin/audit-events",
+    params(CursorParams, AuditEventFilter),
+    responses((status = OK, body = Page<AuditEventInfo>), (status = BAD_REQUEST)),
+    security(("bearer_auth" = ["users:read"]), ("api_key" = ["users:read"]))
+)]
+pub async fn api_list_audit_events(
+    State(pool): State<Pool<AsyncPgConnection>>,
+    pagination: CursorPagination<AuditEventKey>,
+    Filters(filter): Filters<AuditEventFilter>,
+) -> Result<(StatusCode, Json<Page<AuditEventInfo>>), (StatusCode, Json<String>)> {
+    let conn = &mut get_conn(&pool).await?;
+
+    match models::audit::list_audit_events(
+        conn,
+        &filter,
+        pagination.after.as_ref(),
+        pagination.limit(),
+    )
+    .await
+    {
+        Ok(events) => Ok((
+            StatusCode::OK,
+            Json(
+                Page::cursor(events, pagination.per_page, |event| {
+                    AuditEventKey::from(event)
+                })
+                .map(AuditEventInfo::from),
+            ),
+        )),
+        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, Json(e.to_string()))),
+    }
+}
+
+/// Fetches an account by its `id` or its numeric `public_id`.
+#[utoipa::path(
+    get,
+    path = "/admin/users/{id}",
+    params(("id" = String, Path, description = "User id or public id")),
+    responses((status = OK, body = AdminUserResponse), (status = NOT_FOUND)),
+    security(("bearer_auth" = ["users:read"]), ("api_key" = ["users:read"]))
+)]
+pub async fn api_get_user(
+    State(pool): State<Pool<AsyncPgConnection>>,
+    Path(id): Path<String>,
+) -> Result<(StatusCode, Json<AdminUserResponse>), (StatusCode, Json<String>)> {
+    let conn = &mut get_conn(&pool).await?;
+
+    let user = if let Ok(id) = Uuid::parse_str(&id) {
+        models::user::find_user_by_id(conn, &id).await
+    } else if let Ok(public_id) = id.parse::<i32>() {
+        models::user::find_user_by_public_id(conn, public_id).await
+    } else {
+        Err(ApiError::UserNotFound.to_string())
+    };
+
+    match user {
+        Ok(user) => Ok((StatusCode::OK, Json(AdminUserResponse::from(user)))),
+        Err(_) => Err((
+            StatusCode::NOT_FOUND,
+            Json(ApiError::UserNotFound.to_string()),
+        )),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/users/{id}/activate",
+    params(("id" = String, Path, description = "User id")),
+    responses((status = OK), (status = NOT_FOUND)),
+    security(("bearer_auth" = ["users:write"]), ("api_key" = ["users:write"]))
+)]
+pub async fn api_activate_user(
+    State(pool): State<Pool<AsyncPgConnection>>,
+    AuthUser(claims): AuthUser,
+    Path(id): Path<Uuid>,
+) -> Result<StatusCode, (StatusCode, Json<String>)> {
+    let conn = &mut get_conn(&pool).await?;
+
+    let user = find_target_user(conn, &id).await?;
+
+    if let Err(e) = models::user::set_active(conn, &user.id, true).await {
+        return Err((StatusCode::INTERNAL_SERVER_ERROR, Json(e.to_string())));
+    }
+
+    record_audit_event(conn, claims.id, USER_ACTIVATED, Some(user.id), None).await?;
+
+    Ok(StatusCode::OK)
+}
+
+/// Blocks the account and signs it out everywhere.
+#[utoipa::path(
+    post,
+    path = "/admin/users/{id}/deactivate",
+    params(("id" = String, Path, description = "User id")),
+    responses((status = OK), (status = FORBIDDEN), (status = NOT_FOUND)),
+    security(("bearer_auth" = ["users:write"]), ("api_key" = ["users:write"]))
+)]
+pub async fn api_deactivate_user(
+    State(pool): State<Pool<AsyncPgConnection>>,
+    AuthUser(claims): AuthUser,
+    Path(id): Path<Uuid>,
+) -> Result<StatusCode, (StatusCode, Json<String>)> {
+    let conn = &mut get_conn(&pool).await?;
+
+    let user = find_target_user(conn, &id).await?;
+    reject_self(&claims.id, &user)?;
+
+    if let Err(e) = models::user::set_active(conn, &user.id, false).await {
+        return Err((StatusCode::INTERNAL_SERVER_ERROR, Json(e.to_string())));
+    }
+
+    end_all_sessions(conn, &user.id).await?;
+
+    record_audit_event(conn, claims.id, USER_DEACTIVATED, Some(user.id), None).await?;
+
+    Ok(StatusCode::OK)
+}
+
+/// E-mails the user a password reset link, then makes the current password unusable and signs the
+/// account out everywhere.
+#[utoipa::path(
+    post,
+    path = "/admin/users/{id}/reset-password",
+    params(("id" = String, Path, description = "User id")),
+    responses((status = OK), (status = BAD_REQUEST), (status = NOT_FOUND)),
+    security(("bearer_auth" = ["users:write"]), ("api_key" = ["users:write"]))
+)]
+pub async fn api_force_password_reset(
+    State(pool): State<Pool<AsyncPgConnection>>,
+    AuthUser(claims): AuthUser,
+    Path(id): Path<Uuid>,
+) -> Result<StatusCode, (StatusCode, Json<String>)> {
+    let conn = &mut get_conn(&pool).await?;
+
+    let user = find_target_user(conn, &id).await?;
+
+    if !user.is_active || user.deletion_date.is_some() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ApiError::NotActiveUser.to_string()),
+        ));
+    }
+
+    let password = hash_password(&generate_opaque_token()).await?;
+
+    // The link goes out first: if it can't be sent, the user keeps a password they can sign in
+    // with instead of being locked out.
+    request_password_reset(conn, &user).await?;
+
+    if let Err(e) = models::user::update_password(conn, &user.id, &password).await {
+        return Err((StatusCode::INTERNAL_SERVER_ERROR, Json(e.to_string())));
+    }
+
+    end_all_sessions(conn, &user.id).await?;
+
+    record_audit_event(
+        conn,
+        claims.id,
+        USER_PASSWORD_RESET_FORCED,
+        Some(user.id),
+        None,
+    )
+    .await?;
+
+    Ok(StatusCode::OK)
+}
+
+/// Changes the account's role. Its access tokens are revoked so the new scopes apply on the next
+/// refresh.
+#[utoipa::path(
+    patch,
+    path = "/admin/users/{id}/role",
+    params(("id" = String, Path, description = "User id")),
+    request_body = ChangeRoleInput,
+    responses((status = OK), (status = FORBIDDEN), (status = NOT_FOUND)),
+    security(("bearer_auth" = ["users:write"]), ("api_key" = ["users:write"]))
+)]
+pub async fn api_change_user_role(
+    State(pool): State<Pool<AsyncPgConnection>>,
+    AuthUser(claims): AuthUser,
+    Path(id): Path<Uuid>,
+    input: Json<ChangeRoleInput>,
+) -> Result<StatusCode, (StatusCode, Json<String>)> {
+    let conn = &mut get_conn(&pool).await?;
+
+    let user = find_target_user(conn, &id).await?;
+    reject_self(&claims.id, &user)?;
+
+    if user.user_type == input.user_type {
+        return Ok(StatusCode::OK);
+    }
+
+    if let Err(e) = models::user::set_user_type(conn, &user.id, input.user_type).await {
+        return Err((StatusCode::INTERNAL_SERVER_ERROR, Json(e.to_string())));
+    }
+
+    revoke_all_user_tokens(conn, &user.id).await?;
+
+    record_audit_event(
+        conn,
+        claims.id,
+        USER_ROLE_CHANGED,
+        Some(user.id),
+        Some(serde_json::json!({
+            "from": user.user_type,
+            "to": input.user_type,
+        })),
+    )
+    .await?;
+
+    Ok(StatusCode::OK)
+}
+
+/// Cancels the deletion of an account scheduled for deletion, even after the period in which the
+/// user could restore it themselves, as long as it hasn't been purged yet.
+#[utoipa::path(
+    post,
+    path = "/admin/users/{id}/restore",
+    params(("id" = String, Path, description = "User id")),
+    responses((status = OK), (status = BAD_REQUEST), (status = NOT_FOUND)),
+    security(("bearer_auth" = ["users:write"]), ("api_key" = ["users:write"]))
+)]
+pub async fn api_restore_user(
+    State(pool): State<Pool<AsyncPgConnection>>,
+    AuthUser(claims): AuthUser,
+    Path(id): Path<Uuid>,
+) -> Result<StatusCode, (StatusCode, Json<String>)> {
+    let conn = &mut get_conn(&pool).await?;
+
+    let user = find_target_user(conn, &id).await?;
+
+    if user.deletion_date.is_none() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ApiError::AccountNotDeleted.to_string()),
+        ));
+    }
+
+    if let Err(e) = models::user::set_deletion_date(conn, &user.id, None).await {
+        return Err((StatusCode::INTERNAL_SERVER_ERROR, Json(e.to_string())));
+    }
+
+    record_audit_event(conn, claims.id, USER_RESTORED, Some(user.id), None).await?;
+
+    Ok(StatusCode::OK)
+}
+
+/// Lifts a login lockout on the account before it expires by itself.
+#[utoipa::path(
+    post,
+    path = "/admin/users/{id}/unlock",
+    params(("id" = String, Path, description = "User id")),
+    responses((status = OK), (status = NOT_FOUND)),
+    security(("bearer_auth" = ["users:write"]), ("api_key" = ["users:write"]))
+)]
+pub async fn api_unlock_user(
+    State(pool): State<Pool<AsyncPgConnection>>,
+    AuthUser(claims): AuthUser,
+    Path(id): Path<Uuid>,
+) -> Result<StatusCode, (StatusCode, Json<String>)> {
+    let conn = &mut get_conn(&pool).await?;
+
+    let user = find_target_user(conn, &id).await?;
+
+    unlock_account(conn, &user.email).await?;
+
+    record_audit_event(conn, claims.id, USER_UNLOCKED, Some(user.id), None).await?;
+
+    Ok(StatusCode::OK)
+}
+
+/// Issues a short-lived access token to act as the user, e.g. to see what a customer sees. The
+/// token carries an `act` claim naming the admin, can't reach the account security endpoints and
+/// is recorded in the audit trail. Admins can't be impersonated.
+#[utoipa::path(
+    post,
+    path = "/admin/users/{id}/impersonate",
+    params(("id" = String, Path, description = "User id")),
+    request_body = ImpersonateInput,
+    responses((status = OK, body = ImpersonationToken), (status = FORBIDDEN), (status = NOT_FOUND)),
+    security(("bearer_auth" = ["users:write"]))
+)]
+pub async fn api_impersonate_user(
+    State(pool): State<Pool<AsyncPgConnection>>,
+    AuthUser(claims): AuthUser,
+    Path(id): Path<Uuid>,
+    input: Json<ImpersonateInput>,
+) -> Result<(StatusCode, Json<ImpersonationToken>), (StatusCode, Json<String>)> {
+    // Impersonating has to be a deliberate, signed-in action; a leaked key can't be used for it.
+    if claims.api_key_id.is_some() || claims.impersonated {
+        return Err((StatusCode::FORBIDDEN, Json(ApiError::Forbidden.to_string())));
+    }
+
+    let reason = input.0.reason.trim().to_string();
+    if reason.is_empty() || reason.chars().count() > MAX_IMPERSONATION_REASON_LENGTH {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ApiError::InvalidData.to_string()),
+        ));
+    }
+
+    let conn = &mut get_conn(&pool).await?;
+
+    let user = find_target_user(conn, &id).await?;
+
+    if user.id == claims.id || user.user_type == UserRole::Admin {
+        return Err((
+            StatusCode::FORBIDDEN,
+            Json(ApiError::CannotImpersonate.to_string()),
+        ));
+    }
+
+    if !user.is_active || user.deletion_date.is_some() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ApiError::NotActiveUser.to_string()),
+        ));
+    }
+
+    let scopes = role_scopes(conn, user.user_type).await?;
+    let actor = Actor {
+        sub: claims.id,
+        email: claims.email.clone(),
+    };
+    let (access_token, token_claims) =
+        generate_impersonation_jwt(UserAuthInfo::from(user.clone()), scopes, actor)?;
+
+    record_audit_event(
+        conn,
+        claims.id,
+        IMPERSONATION_STARTED,
+        Some(user.id),
+        Some(serde_json::json!({
+            "token_id": token_claims.jti,
+            "reason": reason,
+            "expires_at": token_claims.exp,
+        })),
+    )
+    .await?;
+
+    Ok((
+        StatusCode::OK,
+        Json(ImpersonationToken {
+            access_token,
+            token_type: "Bearer".to_string(),
+            expires_in: IMPERSONATION_TOKEN_EXPIRATION_SECONDS,
+            impersonated: true,
+        }),
+    ))
+}
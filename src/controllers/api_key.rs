@@ -0,0 +1,298 @@
+use axum::{
+    Json,
+    extract::{Path, State},
+};
+use diesel_async::{AsyncPgConnection, pooled_connection::deadpool::Pool};
+use hyper::{HeaderMap, StatusCode};
+use rand::Rng;
+use uuid::Uuid;
+
+use crate::{
+    controllers::{
+        auth::CurrentUser,
+        authorization::role_scopes,
+        jwt::ACCESS_TOKEN_EXPIRATION_SECONDS,
+        session::{generate_opaque_token, hash_token},
+        utils::get_conn,
+    },
+    models::{
+        self,
+        api_key::{ApiKey, ApiKeyInfo, CreateApiKeyInput, CreatedApiKey},
+        error::ApiError,
+        jwt::Claims,
+    },
+};
+
+pub const API_KEY_HEADER: &str = "X-API-Key";
+
+/// Keys look like `rbt_<lookup>_<secret>`; `rbt_<lookup>` is the prefix stored in plain text.
+const API_KEY_PREFIX: &str = "rbt";
+const API_KEY_LOOKUP_BYTES: usize = 6;
+const MAX_API_KEY_NAME_LENGTH: usize = 64;
+const MAX_SCOPE_LENGTH: usize = 64;
+const MAX_API_KEY_EXPIRATION_DAYS: i64 = 3650;
+/// `last_used_date` is written at most once per this many seconds for each key.
+const LAST_USED_RESOLUTION_SECONDS: i64 = 60;
+
+/// Returns the prefix and the full key.
+fn generate_api_key() -> (String, String) {
+    let mut lookup = [0u8; API_KEY_LOOKUP_BYTES];
+    rand::thread_rng().fill(&mut lookup);
+    let lookup: String = lookup.iter().map(|b| format!("{:02x}", b)).collect();
+
+    let prefix = format!("{}_{}", API_KEY_PREFIX, lookup);
+    let key = format!("{}_{}", prefix, generate_opaque_token());
+    (prefix, key)
+}
+
+/// Reads a key sent as `Authorization: ApiKey <key>` or `X-API-Key: <key>`.
+pub fn extract_api_key(headers: &HeaderMap) -> Option<&str> {
+    let authorization = headers
+        .get("Authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|header| header.strip_prefix("ApiKey "));
+
+    let key = match authorization {
+        Some(key) => key,
+        None => headers.get(API_KEY_HEADER)?.to_str().ok()?,
+    };
+
+    Some(key.trim())
+}
+
+/// Resolves an API key to the same identity a JWT of its owner would carry, limited to the key's
+/// scopes. `jti` and `api_key_id` hold the key id. The scopes are checked against the owner's
+/// current role on every request, so a demoted user's keys lose what the role no longer grants.
+///
+/// Keys don't belong to a session, so the revocation list doesn't apply to them: logging out of
+/// every device or changing the password leaves them working. They stop on their own revocation,
+/// expiration, or when the owner is deactivated or deleted.
+pub async fn authenticate_api_key(
+    pool: &Pool<AsyncPgConnection>,
+    key: &str,
+) -> Result<Claims, (StatusCode, Json<String>)> {
+    let invalid_key = || {
+        (
+            StatusCode::UNAUTHORIZED,
+            Json(ApiError::InvalidApiKey.to_string()),
+        )
+    };
+
+    let prefix = match key.rsplit_once('_') {
+        Some((prefix, _)) if prefix.starts_with(API_KEY_PREFIX) => prefix,
+        _ => return Err(invalid_key()),
+    };
+
+    let conn = &mut get_conn(pool).await?;
+
+    let api_key = match models::api_key::find_api_key_by_prefix(conn, prefix).await {
+        Ok(Some(api_key)) => api_key,
+        Ok(None) => return Err(invalid_key()),
+        Err(e) => return Err((StatusCode::INTERNAL_SERVER_ERROR, Json(e.to_string()))),
+    };
+
+    let now = chrono::Utc::now().naive_utc();
+    if api_key.key_hash != hash_token(key)
+        || api_key.revocation_date.is_some()
+        || api_key.expiration_date.is_some_and(|date| date < now)
+    {
+        return Err(invalid_key());
+    }
+
+    let user = match models::user::find_user_by_id(conn, &api_key.user_id).await {
+        Ok(user) => user,
+        Err(_) => return Err(invalid_key()),
+    };
+
+    if !user.is_active || user.deletion_date.is_some() {
+        return Err((
+            StatusCode::UNAUTHORIZED,
+            Json(ApiError::NotActiveUser.to_string()),
+        ));
+    }
+
+    let since = now - chrono::Duration::seconds(LAST_USED_RESOLUTION_SECONDS);
+    if let Err(e) = models::api_key::touch_api_key(conn, &api_key.id, since).await {
+        tracing::error!("Failed to record the API key usage: {}", e);
+    }
+
+    let mut scopes = role_scopes(conn, user.user_type).await?;
+    if let Some(key_scopes) = api_key.scope_list() {
+        scopes.retain(|scope| key_scopes.contains(scope));
+    }
+
+    let exp = api_key
+        .expiration_date
+        .unwrap_or(now + chrono::Duration::seconds(ACCESS_TOKEN_EXPIRATION_SECONDS));
+
+    Ok(Claims {
+        id: user.id,
+        public_id: user.public_id,
+        user_type: user.user_type,
+        email: user.email,
+        exp: exp.and_utc().timestamp() as usize,
+        iat: api_key.create_date.and_utc().timestamp() as usize,
+        iat_micros: Some(api_key.create_date.and_utc().timestamp_micros()),
+        jti: api_key.id,
+        sid: None,
+        api_key_id: Some(api_key.id),
+        scopes,
+        act: None,
+        impersonated: false,
+    })
+}
+
+/// Keys can't list keys either; creating and revoking them is already behind `forbid_api_keys`.
+fn reject_api_key_caller(claims: &Claims) -> Result<(), (StatusCode, Json<String>)> {
+    match claims.api_key_id {
+        Some(_) => Err((
+            StatusCode::FORBIDDEN,
+            Json(ApiError::ApiKeyForbidden.to_string()),
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Validates the requested scopes, which must all be granted to the user's role.
+fn parse_scopes(
+    scopes: Option<Vec<String>>,
+    granted: &[String],
+) -> Result<Option<String>, (StatusCode, Json<String>)> {
+    let Some(scopes) = scopes else {
+        return Ok(None);
+    };
+
+    let mut parsed: Vec<String> = Vec::new();
+    for scope in scopes {
+        let scope = scope.trim();
+        if scope.is_empty()
+            || scope.len() > MAX_SCOPE_LENGTH
+            || scope.chars().any(char::is_whitespace)
+        {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                Json(ApiError::InvalidData.to_string()),
+            ));
+        }
+        if !parsed.iter().any(|existing| existing == scope) {
+            parsed.push(scope.to_string());
+        }
+    }
+
+    if parsed.is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ApiError::InvalidData.to_string()),
+        ));
+    }
+
+    let not_granted: Vec<String> = parsed
+        .iter()
+        .filter(|scope| !granted.contains(scope))
+        .cloned()
+        .collect();
+    if !not_granted.is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ApiError::InvalidScopes(not_granted).to_string()),
+        ));
+    }
+
+    Ok(Some(parsed.join(" ")))
+}
+
+/// Creates a key for the signed-in user. The key is only returned here; afterwards only its
+/// prefix can be seen.
+#[utoipa::path(post, path = "/user/api-keys", request_body = CreateApiKeyInput, responses((status = CREATED, body = CreatedApiKey)), security(("bearer_auth" = ["profile:write"])))]
+pub async fn api_create_api_key(
+    State(pool): State<Pool<AsyncPgConnection>>,
+    CurrentUser { user, .. }: CurrentUser,
+    input: Json<CreateApiKeyInput>,
+) -> Result<(StatusCode, Json<CreatedApiKey>), (StatusCode, Json<String>)> {
+    let input = input.0;
+    let name = input.name.trim().to_string();
+    if name.is_empty()
+        || name.chars().count() > MAX_API_KEY_NAME_LENGTH
+        || input
+            .expires_in_days
+            .is_some_and(|days| !(1..=MAX_API_KEY_EXPIRATION_DAYS).contains(&days))
+    {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ApiError::InvalidData.to_string()),
+        ));
+    }
+
+    let conn = &mut get_conn(&pool).await?;
+
+    let granted = role_scopes(conn, user.user_type).await?;
+    let scopes = parse_scopes(input.scopes, &granted)?;
+
+    let now = chrono::Utc::now().naive_utc();
+    let (prefix, key) = generate_api_key();
+    let api_key = ApiKey {
+        id: Uuid::new_v4(),
+        user_id: user.id,
+        name,
+        prefix,
+        key_hash: hash_token(&key),
+        scopes,
+        create_date: now,
+        expiration_date: input
+            .expires_in_days
+            .map(|days| now + chrono::Duration::days(days)),
+        last_used_date: None,
+        revocation_date: None,
+    };
+
+    if let Err(e) = models::api_key::create_api_key(conn, &api_key).await {
+        return Err((StatusCode::INTERNAL_SERVER_ERROR, Json(e.to_string())));
+    }
+
+    Ok((
+        StatusCode::CREATED,
+        Json(CreatedApiKey {
+            id: api_key.id,
+            scopes: api_key.scope_list(),
+            name: api_key.name,
+            key,
+            expiration_date: api_key.expiration_date,
+        }),
+    ))
+}
+
+#[utoipa::path(get, path = "/user/api-keys", responses((status = OK, body = Vec<ApiKeyInfo>)), security(("bearer_auth" = ["profile:read"])))]
+pub async fn api_list_api_keys(
+    State(pool): State<Pool<AsyncPgConnection>>,
+    CurrentUser { claims, user }: CurrentUser,
+) -> Result<(StatusCode, Json<Vec<ApiKeyInfo>>), (StatusCode, Json<String>)> {
+    reject_api_key_caller(&claims)?;
+
+    let conn = &mut get_conn(&pool).await?;
+
+    match models::api_key::list_api_keys(conn, &user.id).await {
+        Ok(keys) => Ok((
+            StatusCode::OK,
+            Json(keys.into_iter().map(ApiKeyInfo::from).collect()),
+        )),
+        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, Json(e.to_string()))),
+    }
+}
+
+#[utoipa::path(delete, path = "/user/api-keys/{id}", params(("id" = Uuid, Path)), responses((status = NO_CONTENT), (status = NOT_FOUND)), security(("bearer_auth" = ["profile:write"])))]
+pub async fn api_revoke_api_key(
+    State(pool): State<Pool<AsyncPgConnection>>,
+    CurrentUser { user, .. }: CurrentUser,
+    Path(id): Path<Uuid>,
+) -> Result<StatusCode, (StatusCode, Json<String>)> {
+    let conn = &mut get_conn(&pool).await?;
+
+    match models::api_key::revoke_api_key(conn, &user.id, &id).await {
+        Ok(true) => Ok(StatusCode::NO_CONTENT),
+        Ok(false) => Err((
+            StatusCode::NOT_FOUND,
+            Json(ApiError::ApiKeyNotFound.to_string()),
+        )),
+        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, Json(e.to_string()))),
+    }
+}
@@ -0,0 +1,30 @@
+use axum::Json;
+use diesel_async::AsyncPgConnection;
+use hyper::StatusCode;
+use uuid::Uuid;
+
+use crate::models::{self, audit::AuditEvent};
+
+/// Writes an entry to the audit trail. `details` holds whatever else is needed to make sense of
+/// the action later, e.g. the reason given for it.
+pub async fn record_audit_event(
+    conn: &mut AsyncPgConnection,
+    actor_id: Uuid,
+    action: &str,
+    target_user_id: Option<Uuid>,
+    details: Option<serde_json::Value>,
+) -> Result<(), (StatusCode, Json<String>)> {
+    let event = AuditEvent {
+        id: Uuid::new_v4(),
+        actor_id: Some(actor_id),
+        target_user_id,
+        action: action.to_string(),
+        details,
+        create_date: chrono::Utc::now().naive_utc(),
+    };
+
+    match models::audit::create_audit_event(conn, &event).await {
+        Ok(_) => Ok(()),
+        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, Json(e.to_string()))),
+    }
+}
@@ -0,0 +1,99 @@
+use axum::{
+    Json,
+    extract::{FromRef, FromRequestParts},
+    http::request::Parts,
+};
+use diesel_async::{AsyncPgConnection, pooled_connection::deadpool::Pool};
+use hyper::StatusCode;
+
+use crate::{
+    controllers::{api_key::API_KEY_HEADER, jwt::authenticate, utils::get_conn},
+    models::{self, error::ApiError, jwt::Claims, user::User},
+};
+
+/// Claims of the authenticated caller, read from the request extensions where `identify_caller` or
+/// `jwt_auth` stored them. Without them, e.g. for an invalid token, the token is validated here.
+pub struct AuthUser(pub Claims);
+
+/// Like [`AuthUser`], but `None` when the request has no `Authorization` or `X-API-Key` header.
+/// A header carrying an invalid token is still rejected.
+pub struct OptionalAuthUser(pub Option<Claims>);
+
+/// The authenticated caller together with their `users` row.
+pub struct CurrentUser {
+    pub claims: Claims,
+    pub user: User,
+}
+
+impl<S> FromRequestParts<S> for AuthUser
+where
+    Pool<AsyncPgConnection>: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = (StatusCode, Json<String>);
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        if let Some(claims) = parts.extensions.get::<Claims>() {
+            return Ok(AuthUser(claims.clone()));
+        }
+
+        let pool = Pool::<AsyncPgConnection>::from_ref(state);
+        let claims = authenticate(&pool, &parts.headers).await?;
+        parts.extensions.insert(claims.clone());
+
+        Ok(AuthUser(claims))
+    }
+}
+
+impl<S> FromRequestParts<S> for OptionalAuthUser
+where
+    Pool<AsyncPgConnection>: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = (StatusCode, Json<String>);
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        if !parts.headers.contains_key("Authorization")
+            && !parts.headers.contains_key(API_KEY_HEADER)
+        {
+            return Ok(OptionalAuthUser(None));
+        }
+
+        let AuthUser(claims) = AuthUser::from_request_parts(parts, state).await?;
+        Ok(OptionalAuthUser(Some(claims)))
+    }
+}
+
+impl<S> FromRequestParts<S> for CurrentUser
+where
+    Pool<AsyncPgConnection>: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = (StatusCode, Json<String>);
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let AuthUser(claims) = AuthUser::from_request_parts(parts, state).await?;
+
+        let pool = Pool::<AsyncPgConnection>::from_ref(state);
+        let conn = &mut get_conn(&pool).await?;
+
+        let user = match models::user::find_user_by_id(conn, &claims.id).await {
+            Ok(user) => user,
+            Err(_) => {
+                return Err((
+                    StatusCode::UNAUTHORIZED,
+                    Json(ApiError::UserNotFound.to_string()),
+                ));
+            }
+        };
+
+        if !user.is_active || user.deletion_date.is_some() {
+            return Err((
+                StatusCode::UNAUTHORIZED,
+                Json(ApiError::NotActiveUser.to_string()),
+            ));
+        }
+
+        Ok(CurrentUser { claims, user })
+    }
+}
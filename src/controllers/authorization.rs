@@ -0,0 +1,180 @@
+use std::marker::PhantomData;
+
+use axum::{
+    Json,
+    body::Body,
+    extract::{FromRef, FromRequestParts, Request, State},
+    http::request::Parts,
+    middleware::Next,
+    response::Response,
+};
+use diesel_async::{AsyncPgConnection, pooled_connection::deadpool::Pool};
+use hyper::StatusCode;
+
+use crate::{
+    controllers::auth::AuthUser,
+    models::{self, error::ApiError, jwt::Claims, role::UserRole},
+};
+
+pub fn check_role(claims: &Claims, required: UserRole) -> Result<(), (StatusCode, Json<String>)> {
+    if claims.user_type.satisfies(required) {
+        return Ok(());
+    }
+
+    Err((StatusCode::FORBIDDEN, Json(ApiError::Forbidden.to_string())))
+}
+
+/// Middleware that only lets requests through when the token's role satisfies the one given as
+/// state, e.g. `.route_layer(middleware::from_fn_with_state(UserRole::Admin, require_role))`.
+/// It must be layered inside `jwt_auth`.
+pub async fn require_role(
+    State(required): State<UserRole>,
+    req: Request<Body>,
+    next: Next,
+) -> Result<Response, (StatusCode, Json<String>)> {
+    let claims = match req.extensions().get::<Claims>() {
+        Some(claims) => claims,
+        None => {
+            return Err((
+                StatusCode::UNAUTHORIZED,
+                Json(ApiError::InvalidAuthorizationToken.to_string()),
+            ));
+        }
+    };
+    check_role(claims, required)?;
+    Ok(next.run(req).await)
+}
+
+pub trait RequiredRole {
+    const ROLE: UserRole;
+}
+
+pub struct Admin;
+
+impl RequiredRole for Admin {
+    const ROLE: UserRole = UserRole::Admin;
+}
+
+pub struct Support;
+
+impl RequiredRole for Support {
+    const ROLE: UserRole = UserRole::Support;
+}
+
+/// Extractor version of [`require_role`], for handlers that check the role themselves by taking
+/// e.g. `admin: RequireRole<Admin>` as an argument.
+pub struct RequireRole<R: RequiredRole> {
+    pub claims: Claims,
+    role: PhantomData<R>,
+}
+
+impl<S, R> FromRequestParts<S> for RequireRole<R>
+where
+    Pool<AsyncPgConnection>: FromRef<S>,
+    S: Send + Sync,
+    R: RequiredRole,
+{
+    type Rejection = (StatusCode, Json<String>);
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let AuthUser(claims) = AuthUser::from_request_parts(parts, state).await?;
+        check_role(&claims, R::ROLE)?;
+        Ok(RequireRole {
+            claims,
+            role: PhantomData,
+        })
+    }
+}
+
+/// Scopes granted to the role, embedded in the access tokens it is issued.
+pub async fn role_scopes(
+    conn: &mut AsyncPgConnection,
+    role: UserRole,
+) -> Result<Vec<String>, (StatusCode, Json<String>)> {
+    match models::permission::list_role_scopes(conn, role).await {
+        Ok(scopes) => Ok(scopes),
+        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, Json(e.to_string()))),
+    }
+}
+
+pub fn check_scope(claims: &Claims, required: &str) -> Result<(), (StatusCode, Json<String>)> {
+    if claims.scopes.iter().any(|scope| scope == required) {
+        return Ok(());
+    }
+
+    Err((
+        StatusCode::FORBIDDEN,
+        Json(ApiError::MissingScope(required.to_string()).to_string()),
+    ))
+}
+
+/// Middleware that only lets requests through when the token carries the scope given as state,
+/// e.g. `.route_layer(middleware::from_fn_with_state(USERS_WRITE, require_scope))`. Like
+/// [`require_role`], it must be layered inside `jwt_auth`.
+pub async fn require_scope(
+    State(required): State<&'static str>,
+    req: Request<Body>,
+    next: Next,
+) -> Result<Response, (StatusCode, Json<String>)> {
+    let claims = match req.extensions().get::<Claims>() {
+        Some(claims) => claims,
+        None => {
+            return Err((
+                StatusCode::UNAUTHORIZED,
+                Json(ApiError::InvalidAuthorizationToken.to_string()),
+            ));
+        }
+    };
+    check_scope(claims, required)?;
+    Ok(next.run(req).await)
+}
+
+/// Middleware that turns impersonation tokens away, for the routes that secure the account itself
+/// (password, MFA, passkeys, API keys). Only the account owner may use those. It must be layered
+/// inside `jwt_auth`.
+pub async fn forbid_impersonation(
+    req: Request<Body>,
+    next: Next,
+) -> Result<Response, (StatusCode, Json<String>)> {
+    let claims = match req.extensions().get::<Claims>() {
+        Some(claims) => claims,
+        None => {
+            return Err((
+                StatusCode::UNAUTHORIZED,
+                Json(ApiError::InvalidAuthorizationToken.to_string()),
+            ));
+        }
+    };
+    if claims.impersonated {
+        return Err((
+            StatusCode::FORBIDDEN,
+            Json(ApiError::ImpersonationForbidden.to_string()),
+        ));
+    }
+    Ok(next.run(req).await)
+}
+
+/// Middleware that turns API keys away from the routes that secure the account, so a leaked key
+/// can't add a password, passkey, second factor or key that outlives it. It must be layered inside
+/// `jwt_auth`.
+pub async fn forbid_api_keys(
+    req: Request<Body>,
+    next: Next,
+) -> Result<Response, (StatusCode, Json<String>)> {
+    let claims = match req.extensions().get::<Claims>() {
+        Some(claims) => claims,
+        None => {
+            return Err((
+                StatusCode::UNAUTHORIZED,
+                Json(ApiError::InvalidAuthorizationToken.to_string()),
+            ));
+        }
+    };
+    if claims.api_key_id.is_some() {
+        return Err((
+            StatusCode::FORBIDDEN,
+            Json(ApiError::ApiKeyForbidden.to_string()),
+        ));
+    }
+    Ok(next.run(req).await)
+}
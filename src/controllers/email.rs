@@ -0,0 +1,299 @@
+use std::{env, sync::LazyLock};
+
+use axum::Json;
+use dotenvy::dotenv;
+use hyper::StatusCode;
+use lettre::{
+    AsyncFileTransport, AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+    message::{Mailbox, MultiPart},
+    transport::smtp::authentication::Credentials,
+};
+
+use crate::{controllers::utils::get_frontend_url_from_env, models::error::ApiError};
+
+pub const EMAIL_VERIFICATION_EXPIRATION_HOURS: i64 = 24;
+pub const PASSWORD_RESET_EXPIRATION_MINUTES: i64 = 60;
+pub const MAGIC_LINK_EXPIRATION_MINUTES: i64 = 15;
+
+pub struct EmailContent {
+    pub subject: String,
+    pub text: String,
+    pub html: String,
+}
+
+enum MailTransport {
+    Smtp(AsyncSmtpTransport<Tokio1Executor>),
+    File(AsyncFileTransport<Tokio1Executor>),
+    Stdout,
+}
+
+struct Mailer {
+    from: Mailbox,
+    transport: MailTransport,
+}
+
+static MAILER: LazyLock<Result<Mailer, String>> = LazyLock::new(load_mailer);
+
+/// Builds the mailer from the environment.
+///
+/// - `MAIL_FROM`: sender address, e.g. `Rust Backend <no-reply@example.com>`.
+/// - `MAIL_TRANSPORT`: `smtp`, `file` or `stdout` (default), which writes e-mails to the log.
+/// - `SMTP_HOST`, `SMTP_PORT`, `SMTP_USERNAME`, `SMTP_PASSWORD` and `SMTP_TLS` (`starttls`,
+///   `tls` or `none`, the latter for local stand-ins such as MailHog or Mailpit).
+/// - `MAIL_FILE_DIR`: directory `.eml` files are written to by the `file` transport.
+fn load_mailer() -> Result<Mailer, String> {
+    dotenv().ok();
+
+    let from = env::var("MAIL_FROM").unwrap_or_else(|_| "no-reply@localhost".to_string());
+    let from = from
+        .parse::<Mailbox>()
+        .map_err(|e| format!("Invalid MAIL_FROM: {}", e))?;
+
+    let transport = match env::var("MAIL_TRANSPORT")
+        .unwrap_or_else(|_| "stdout".to_string())
+        .as_str()
+    {
+        "smtp" => MailTransport::Smtp(load_smtp_transport()?),
+        "file" => {
+            let dir = env::var("MAIL_FILE_DIR").unwrap_or_else(|_| "./emails".to_string());
+            std::fs::create_dir_all(&dir).map_err(|e| format!("Invalid MAIL_FILE_DIR: {}", e))?;
+            MailTransport::File(AsyncFileTransport::<Tokio1Executor>::new(dir))
+        }
+        "stdout" => MailTransport::Stdout,
+        other => return Err(format!("Unknown MAIL_TRANSPORT: {}", other)),
+    };
+
+    Ok(Mailer { from, transport })
+}
+
+fn load_smtp_transport() -> Result<AsyncSmtpTransport<Tokio1Executor>, String> {
+    let host = env::var("SMTP_HOST").map_err(|_| "Missing SMTP_HOST".to_string())?;
+
+    let mut builder = match env::var("SMTP_TLS")
+        .unwrap_or_else(|_| "starttls".to_string())
+        .as_str()
+    {
+        "starttls" => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&host)
+            .map_err(|e| e.to_string())?,
+        "tls" => AsyncSmtpTransport::<Tokio1Executor>::relay(&host).map_err(|e| e.to_string())?,
+        "none" => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&host),
+        other => return Err(format!("Unknown SMTP_TLS: {}", other)),
+    };
+
+    if let Ok(port) = env::var("SMTP_PORT") {
+        let port = port
+            .parse::<u16>()
+            .map_err(|e| format!("Invalid SMTP_PORT: {}", e))?;
+        builder = builder.port(port);
+    }
+
+    if let (Ok(username), Ok(password)) = (env::var("SMTP_USERNAME"), env::var("SMTP_PASSWORD")) {
+        builder = builder.credentials(Credentials::new(username, password));
+    }
+
+    Ok(builder.build())
+}
+
+pub async fn send_email(to: &str, content: EmailContent) -> Result<(), (StatusCode, Json<String>)> {
+    let mailer = match MAILER.as_ref() {
+        Ok(mailer) => mailer,
+        Err(e) => {
+            return Err((
+                StatusCode::SERVICE_UNAVAILABLE,
+                Json(ApiError::Mailer(e.to_string()).to_string()),
+            ));
+        }
+    };
+
+    let send_error = |e: String| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiError::SendEmail(e).to_string()),
+        )
+    };
+
+    let to = to
+        .parse::<Mailbox>()
+        .map_err(|e| send_error(e.to_string()))?;
+
+    let message = Message::builder()
+        .from(mailer.from.clone())
+        .to(to)
+        .subject(content.subject)
+        .multipart(MultiPart::alternative_plain_html(
+            content.text,
+            content.html,
+        ))
+        .map_err(|e| send_error(e.to_string()))?;
+
+    match &mailer.transport {
+        MailTransport::Smtp(transport) => transport
+            .send(message)
+            .await
+            .map(|_| ())
+            .map_err(|e| send_error(e.to_string())),
+        MailTransport::File(transport) => transport
+            .send(message)
+            .await
+            .map(|_| ())
+            .map_err(|e| send_error(e.to_string())),
+        MailTransport::Stdout => {
+            tracing::info!("{}", String::from_utf8_lossy(&message.formatted()));
+            Ok(())
+        }
+    }
+}
+
+/// Fills the `{{key}}` placeholders of a template. Values are HTML escaped when `html` is set.
+fn render_template(template: &str, values: &[(&str, &str)], html: bool) -> String {
+    values
+        .iter()
+        .fold(template.to_string(), |output, (key, value)| {
+            let value = if html {
+                escape_html(value)
+            } else {
+                value.to_string()
+            };
+            output.replace(&format!("{{{{{}}}}}", key), &value)
+        })
+}
+
+fn escape_html(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+fn frontend_link(path: &str, token: &str) -> Result<String, (StatusCode, Json<String>)> {
+    let frontend_url = get_frontend_url_from_env()?;
+    Ok(format!(
+        "{}/{}?token={}",
+        frontend_url.trim_end_matches('/'),
+        path,
+        token
+    ))
+}
+
+pub async fn send_verification_email(
+    name: &str,
+    email: &str,
+    token: &str,
+) -> Result<(), (StatusCode, Json<String>)> {
+    let link = frontend_link("verify-email", token)?;
+    let hours = EMAIL_VERIFICATION_EXPIRATION_HOURS.to_string();
+    let values = [
+        ("name", name),
+        ("link", link.as_str()),
+        ("hours", hours.as_str()),
+    ];
+
+    let content = EmailContent {
+        subject: "Confirm your e-mail address".to_string(),
+        text: render_template(
+            include_str!("../../templates/emails/verify_email.txt"),
+            &values,
+            false,
+        ),
+        html: render_template(
+            include_str!("../../templates/emails/verify_email.html"),
+            &values,
+            true,
+        ),
+    };
+
+    send_email(email, content).await
+}
+
+/// Sent to the new address of an e-mail change; the account keeps its e-mail until it is confirmed.
+pub async fn send_email_change_email(
+    name: &str,
+    email: &str,
+    token: &str,
+) -> Result<(), (StatusCode, Json<String>)> {
+    let link = frontend_link("confirm-email", token)?;
+    let hours = EMAIL_VERIFICATION_EXPIRATION_HOURS.to_string();
+    let values = [
+        ("name", name),
+        ("link", link.as_str()),
+        ("hours", hours.as_str()),
+    ];
+
+    let content = EmailContent {
+        subject: "Confirm your new e-mail address".to_string(),
+        text: render_template(
+            include_str!("../../templates/emails/change_email.txt"),
+            &values,
+            false,
+        ),
+        html: render_template(
+            include_str!("../../templates/emails/change_email.html"),
+            &values,
+            true,
+        ),
+    };
+
+    send_email(email, content).await
+}
+
+pub async fn send_password_reset_email(
+    name: &str,
+    email: &str,
+    token: &str,
+) -> Result<(), (StatusCode, Json<String>)> {
+    let link = frontend_link("reset-password", token)?;
+    let minutes = PASSWORD_RESET_EXPIRATION_MINUTES.to_string();
+    let values = [
+        ("name", name),
+        ("link", link.as_str()),
+        ("minutes", minutes.as_str()),
+    ];
+
+    let content = EmailContent {
+        subject: "Reset your password".to_string(),
+        text: render_template(
+            include_str!("../../templates/emails/reset_password.txt"),
+            &values,
+            false,
+        ),
+        html: render_template(
+            include_str!("../../templates/emails/reset_password.html"),
+            &values,
+            true,
+        ),
+    };
+
+    send_email(email, content).await
+}
+
+pub async fn send_magic_link_email(
+    name: &str,
+    email: &str,
+    token: &str,
+) -> Result<(), (StatusCode, Json<String>)> {
+    let link = frontend_link("magic-link", token)?;
+    let minutes = MAGIC_LINK_EXPIRATION_MINUTES.to_string();
+    let values = [
+        ("name", name),
+        ("link", link.as_str()),
+        ("minutes", minutes.as_str()),
+    ];
+
+    let content = EmailContent {
+        subject: "Your sign-in link".to_string(),
+        text: render_template(
+            include_str!("../../templates/emails/magic_link.txt"),
+            &values,
+            false,
+        ),
+        html: render_template(
+            include_str!("../../templates/emails/magic_link.html"),
+            &values,
+            true,
+        ),
+    };
+
+    send_email(email, content).await
+}
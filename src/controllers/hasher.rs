@@ -0,0 +1,338 @@
+use std::{env, sync::LazyLock};
+
+use argon2::{
+    Algorithm, Argon2, Params, Version,
+    password_hash::{self, PasswordHasher as _, PasswordVerifier, SaltString, rand_core::OsRng},
+};
+use axum::Json;
+use dotenvy::dotenv;
+use hyper::StatusCode;
+use pwhash::bcrypt::{self, BcryptSetup};
+
+use crate::models::error::ApiError;
+
+/// A password hashing algorithm. Hashes are self-describing (PHC or modular crypt format), so
+/// each scheme can tell whether a stored hash belongs to it.
+pub trait PasswordScheme: Send + Sync {
+    fn hash(&self, password: &str) -> Result<String, String>;
+
+    /// Whether the stored hash was produced by this scheme.
+    fn recognizes(&self, hash: &str) -> bool;
+
+    fn verify(&self, password: &str, hash: &str) -> bool;
+
+    /// Whether a hash from this scheme was made with different parameters than the configured
+    /// ones.
+    fn is_outdated(&self, hash: &str) -> bool;
+}
+
+pub struct Argon2idScheme {
+    params: Params,
+}
+
+impl Argon2idScheme {
+    fn argon2(&self) -> Argon2<'static> {
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, self.params.clone())
+    }
+}
+
+impl PasswordScheme for Argon2idScheme {
+    fn hash(&self, password: &str) -> Result<String, String> {
+        let salt = SaltString::generate(&mut OsRng);
+        self.argon2()
+            .hash_password(password.as_bytes(), &salt)
+            .map(|hash| hash.to_string())
+            .map_err(|e| e.to_string())
+    }
+
+    fn recognizes(&self, hash: &str) -> bool {
+        hash.starts_with("$argon2")
+    }
+
+    fn verify(&self, password: &str, hash: &str) -> bool {
+        // The parameters are read from the hash itself, so hashes made with an older cost still
+        // verify.
+        match password_hash::PasswordHash::new(hash) {
+            Ok(parsed) => self
+                .argon2()
+                .verify_password(password.as_bytes(), &parsed)
+                .is_ok(),
+            Err(_) => false,
+        }
+    }
+
+    fn is_outdated(&self, hash: &str) -> bool {
+        let Ok(parsed) = password_hash::PasswordHash::new(hash) else {
+            return true;
+        };
+        if parsed.algorithm != Algorithm::Argon2id.ident() {
+            return true;
+        }
+
+        match Params::try_from(&parsed) {
+            Ok(params) => {
+                params.m_cost() != self.params.m_cost()
+                    || params.t_cost() != self.params.t_cost()
+                    || params.p_cost() != self.params.p_cost()
+            }
+            Err(_) => true,
+        }
+    }
+}
+
+pub struct BcryptScheme {
+    cost: u32,
+}
+
+impl PasswordScheme for BcryptScheme {
+    fn hash(&self, password: &str) -> Result<String, String> {
+        let setup = BcryptSetup {
+            salt: None,
+            cost: Some(self.cost),
+            variant: None,
+        };
+        bcrypt::hash_with(setup, password).map_err(|e| e.to_string())
+    }
+
+    fn recognizes(&self, hash: &str) -> bool {
+        hash.starts_with("$2")
+    }
+
+    fn verify(&self, password: &str, hash: &str) -> bool {
+        bcrypt::verify(password, hash)
+    }
+
+    fn is_outdated(&self, hash: &str) -> bool {
+        // `$2b$10$...`: the cost is the second field.
+        match hash.split('$').nth(2).map(|cost| cost.parse::<u32>()) {
+            Some(Ok(cost)) => cost != self.cost,
+            _ => true,
+        }
+    }
+}
+
+/// Hashes new passwords with the configured scheme and verifies hashes from any known scheme.
+pub struct PasswordHasher {
+    current: Box<dyn PasswordScheme>,
+    legacy: Vec<Box<dyn PasswordScheme>>,
+}
+
+pub struct PasswordVerification {
+    pub valid: bool,
+    /// The password is correct but its hash should be replaced, e.g. with one from the current scheme.
+    pub needs_rehash: bool,
+}
+
+impl PasswordHasher {
+    pub fn new(current: Box<dyn PasswordScheme>, legacy: Vec<Box<dyn PasswordScheme>>) -> Self {
+        Self { current, legacy }
+    }
+
+    pub fn hash(&self, password: &str) -> Result<String, String> {
+        self.current.hash(password)
+    }
+
+    pub fn verify(&self, password: &str, hash: &str) -> PasswordVerification {
+        if self.current.recognizes(hash) {
+            let valid = self.current.verify(password, hash);
+            return PasswordVerification {
+                valid,
+                needs_rehash: valid && self.current.is_outdated(hash),
+            };
+        }
+
+        let valid = self
+            .legacy
+            .iter()
+            .find(|scheme| scheme.recognizes(hash))
+            .is_some_and(|scheme| scheme.verify(password, hash));
+
+        PasswordVerification {
+            valid,
+            needs_rehash: valid,
+        }
+    }
+
+    /// Whether the hash may predate passwords being stored as typed. Those were all bcrypt hashes
+    /// and any of them is replaced on the next login, so a bcrypt hash the current scheme would
+    /// still produce was set afterwards.
+    pub fn may_hold_trimmed_password(&self, hash: &str) -> bool {
+        hash.starts_with("$2") && (!self.current.recognizes(hash) || self.current.is_outdated(hash))
+    }
+}
+
+static PASSWORD_HASHER: LazyLock<Result<PasswordHasher, String>> = LazyLock::new(load_hasher);
+
+/// Builds the hasher from the environment.
+///
+/// - `PASSWORD_HASH_ALGORITHM`: `argon2id` (default) or `bcrypt`.
+/// - `ARGON2_MEMORY_COST` (KiB), `ARGON2_TIME_COST` and `ARGON2_PARALLELISM`, defaulting to the
+///   OWASP recommendation of 19 MiB, 2 iterations and 1 lane.
+/// - `BCRYPT_COST`: defaults to 12.
+fn load_hasher() -> Result<PasswordHasher, String> {
+    dotenv().ok();
+
+    let number = |var: &str, default: u32| match env::var(var) {
+        Ok(value) => value
+            .trim()
+            .parse::<u32>()
+            .map_err(|e| format!("Invalid {}: {}", var, e)),
+        Err(_) => Ok(default),
+    };
+
+    let params = Params::new(
+        number("ARGON2_MEMORY_COST", Params::DEFAULT_M_COST)?,
+        number("ARGON2_TIME_COST", Params::DEFAULT_T_COST)?,
+        number("ARGON2_PARALLELISM", Params::DEFAULT_P_COST)?,
+        None,
+    )
+    .map_err(|e| format!("Invalid Argon2 parameters: {}", e))?;
+
+    let cost = number("BCRYPT_COST", 12)?;
+    if !(bcrypt::MIN_COST..=bcrypt::MAX_COST).contains(&cost) {
+        return Err(format!("Invalid BCRYPT_COST: {}", cost));
+    }
+
+    let argon2id: Box<dyn PasswordScheme> = Box::new(Argon2idScheme { params });
+    let bcrypt: Box<dyn PasswordScheme> = Box::new(BcryptScheme { cost });
+
+    match env::var("PASSWORD_HASH_ALGORITHM")
+        .unwrap_or_else(|_| "argon2id".to_string())
+        .as_str()
+    {
+        "argon2id" => Ok(PasswordHasher::new(argon2id, vec![bcrypt])),
+        "bcrypt" => Ok(PasswordHasher::new(bcrypt, vec![argon2id])),
+        other => Err(format!("Unknown PASSWORD_HASH_ALGORITHM: {}", other)),
+    }
+}
+
+fn get_hasher() -> Result<&'static PasswordHasher, (StatusCode, Json<String>)> {
+    PASSWORD_HASHER.as_ref().map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiError::PasswordHash(e.to_string()).to_string()),
+        )
+    })
+}
+
+/// Hashes the password on the blocking thread pool, as memory-hard hashing would otherwise stall
+/// the Tokio workers.
+pub async fn hash_password(password: &str) -> Result<String, (StatusCode, Json<String>)> {
+    let hasher = get_hasher()?;
+    let password = password.to_string();
+
+    let hash_error = |e: String| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiError::PasswordHash(e).to_string()),
+        )
+    };
+
+    match tokio::task::spawn_blocking(move || hasher.hash(&password)).await {
+        Ok(result) => result.map_err(hash_error),
+        Err(e) => Err(hash_error(e.to_string())),
+    }
+}
+
+pub async fn verify_password(
+    password: &str,
+    hash: &str,
+) -> Result<PasswordVerification, (StatusCode, Json<String>)> {
+    let hasher = get_hasher()?;
+    let password = password.to_string();
+    let hash = hash.to_string();
+
+    match tokio::task::spawn_blocking(move || hasher.verify(&password, &hash)).await {
+        Ok(verification) => Ok(verification),
+        Err(e) => Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiError::PasswordHash(e.to_string()).to_string()),
+        )),
+    }
+}
+
+/// Verifies a password typed by the user. Accounts registered while passwords were still trimmed
+/// hold the bcrypt hash of the trimmed password, so for those hashes the trimmed password is tried
+/// too when the typed one has surrounding whitespace. Such a match doesn't ask for a rehash, which
+/// would store the padding; signing in without it upgrades the hash as usual.
+pub async fn verify_user_password(
+    password: &str,
+    hash: &str,
+) -> Result<PasswordVerification, (StatusCode, Json<String>)> {
+    let verification = verify_password(password, hash).await?;
+
+    let trimmed = password.trim();
+    if verification.valid || trimmed == password || !get_hasher()?.may_hold_trimmed_password(hash) {
+        return Ok(verification);
+    }
+
+    Ok(PasswordVerification {
+        valid: verify_password(trimmed, hash).await?.valid,
+        needs_rehash: false,
+    })
+}
+
+static DUMMY_HASH: LazyLock<Option<String>> =
+    LazyLock::new(|| PASSWORD_HASHER.as_ref().ok()?.hash("dummy password").ok());
+
+/// Spends the same time as a real verification, so logins for unknown e-mails can't be told
+/// apart from wrong passwords by their response time.
+pub async fn verify_dummy_password(password: &str) {
+    let password = password.to_string();
+
+    let _ = tokio::task::spawn_blocking(move || {
+        if let (Ok(hasher), Some(hash)) = (PASSWORD_HASHER.as_ref(), DUMMY_HASH.as_ref()) {
+            hasher.verify(&password, hash);
+        }
+    })
+    .await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A hash as stored before passwords were kept as typed: bcrypt with the default cost.
+    fn legacy_hash(password: &str) -> String {
+        bcrypt::hash(password.trim()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn accepts_the_untrimmed_password_of_a_legacy_hash_without_rehashing() {
+        let hash = legacy_hash("legacy password");
+
+        let verification = verify_user_password("  legacy password ", &hash)
+            .await
+            .unwrap();
+        assert!(verification.valid);
+        assert!(!verification.needs_rehash);
+
+        let verification = verify_user_password("legacy password", &hash)
+            .await
+            .unwrap();
+        assert!(verification.valid);
+        assert!(verification.needs_rehash);
+    }
+
+    #[tokio::test]
+    async fn only_accepts_the_password_as_typed_for_current_hashes() {
+        let hash = hash_password("current password").await.unwrap();
+
+        let verification = verify_user_password(" current password ", &hash)
+            .await
+            .unwrap();
+        assert!(!verification.valid);
+        assert!(!verification.needs_rehash);
+    }
+
+    #[tokio::test]
+    async fn still_rejects_wrong_passwords() {
+        let hash = legacy_hash("legacy password");
+
+        let verification = verify_user_password(" other password ", &hash)
+            .await
+            .unwrap();
+        assert!(!verification.valid);
+        assert!(!verification.needs_rehash);
+    }
+}
@@ -1,19 +1,85 @@
 use std::env;
 
-use crate::models::{error::ApiError, jwt::Claims, user::UserAuthInfo};
-use axum::{Json, body::Body, extract::Request, middleware::Next, response::Response};
+use crate::{
+    controllers::{
+        api_key::{authenticate_api_key, extract_api_key},
+        jwt_keys::get_jwt_keys,
+        revocation::is_token_revoked,
+    },
+    models::{
+        error::ApiError,
+        jwt::{Actor, Claims},
+        user::UserAuthInfo,
+    },
+};
+use axum::{
+    Json,
+    body::Body,
+    extract::{Request, State},
+    middleware::Next,
+    response::Response,
+};
+use diesel_async::{AsyncPgConnection, pooled_connection::deadpool::Pool};
 use dotenvy::dotenv;
 use hyper::{HeaderMap, StatusCode};
-use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation, decode};
+use jsonwebtoken::{Header, Validation, decode, decode_header, jwk::JwkSet};
+use serde::{Serialize, de::DeserializeOwned};
+use uuid::Uuid;
 
+pub const ACCESS_TOKEN_EXPIRATION_SECONDS: i64 = 60 * 60;
+pub const IMPERSONATION_TOKEN_EXPIRATION_SECONDS: i64 = 15 * 60;
+
+/// Validates the bearer token or API key, if any, once for the whole request and stores its
+/// `Claims` in the request extensions, where the rate limiter, `jwt_auth` and the `AuthUser`
+/// extractor pick them up. Invalid credentials aren't answered here: the routes that need a caller
+/// reject them. Meant to wrap the rate limiter:
+/// `.layer(middleware::from_fn_with_state(pool, identify_caller))`.
+pub async fn identify_caller(
+    State(pool): State<Pool<AsyncPgConnection>>,
+    mut req: Request<Body>,
+    next: Next,
+) -> Response {
+    if let Ok(claims) = authenticate(&pool, req.headers()).await {
+        req.extensions_mut().insert(claims);
+    }
+    next.run(req).await
+}
+
+/// Rejects requests without valid credentials. The `Claims` stored by `identify_caller` are used
+/// when present; otherwise the bearer token or API key is validated here and stored the same way.
 pub async fn jwt_auth(
-    req: Request<Body>,
+    State(pool): State<Pool<AsyncPgConnection>>,
+    mut req: Request<Body>,
     next: Next,
 ) -> Result<Response, (StatusCode, Json<String>)> {
-    let _ = extract_claims_from_header(req.headers()).await?;
+    if req.extensions().get::<Claims>().is_none() {
+        let claims = authenticate(&pool, req.headers()).await?;
+        req.extensions_mut().insert(claims);
+    }
     Ok(next.run(req).await)
 }
 
+/// Accepts a `Bearer` JWT, or an API key sent as `Authorization: ApiKey <key>` or `X-API-Key`.
+pub async fn authenticate(
+    pool: &Pool<AsyncPgConnection>,
+    headers: &HeaderMap,
+) -> Result<Claims, (StatusCode, Json<String>)> {
+    if let Some(key) = extract_api_key(headers) {
+        return authenticate_api_key(pool, key).await;
+    }
+
+    let (_, claims) = extract_claims_from_header(headers).await?;
+
+    if is_token_revoked(pool, &claims).await? {
+        return Err((
+            StatusCode::UNAUTHORIZED,
+            Json(ApiError::RevokedAuthorizationToken.to_string()),
+        ));
+    }
+
+    Ok(claims)
+}
+
 pub async fn extract_claims_from_header(
     headers: &HeaderMap,
 ) -> Result<(String, Claims), (StatusCode, Json<String>)> {
@@ -38,27 +104,55 @@ pub async fn extract_claims_from_header(
         }
     };
 
-    let secret = get_jwt_secret_from_env()?;
+    let claims = decode_token::<Claims>(token)?;
 
-    let decoded = decode::<Claims>(
-        token,
-        &DecodingKey::from_secret(secret.as_bytes()),
-        &Validation::new(Algorithm::HS256),
-    );
+    let _ = validate_claims(&claims).await?;
 
-    let claims = match decoded {
-        Ok(data) => (token.to_string(), data.claims),
-        Err(_) => {
-            return Err((
-                StatusCode::UNAUTHORIZED,
-                Json(ApiError::InvalidAuthorizationToken.to_string()),
-            ));
-        }
+    Ok((token.to_string(), claims))
+}
+
+/// Verifies the token signature with the key referenced by its `kid` header.
+pub fn decode_token<T: DeserializeOwned>(token: &str) -> Result<T, (StatusCode, Json<String>)> {
+    let invalid_token = || {
+        (
+            StatusCode::UNAUTHORIZED,
+            Json(ApiError::InvalidAuthorizationToken.to_string()),
+        )
     };
 
-    let _ = validate_claims(&claims.1).await?;
+    let keys = get_jwt_keys()?;
 
-    Ok(claims)
+    let header = decode_header(token).map_err(|_| invalid_token())?;
+    let key = match keys.find_verification_key(header.kid.as_deref()) {
+        Some(key) if key.algorithm == header.alg => key,
+        _ => return Err(invalid_token()),
+    };
+
+    match decode::<T>(token, &key.key, &Validation::new(key.algorithm)) {
+        Ok(data) => Ok(data.claims),
+        Err(_) => Err(invalid_token()),
+    }
+}
+
+/// Signs the claims with the current signing key, adding its `kid` to the header.
+pub fn encode_token<T: Serialize>(claims: &T) -> Result<String, (StatusCode, Json<String>)> {
+    let keys = get_jwt_keys()?;
+
+    let mut header = Header::new(keys.signing.algorithm);
+    header.kid = keys.signing.kid.clone();
+
+    match jsonwebtoken::encode(&header, claims, &keys.signing.key) {
+        Ok(token) => Ok(token),
+        Err(e) => Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiError::CreateToken(e.to_string()).to_string()),
+        )),
+    }
+}
+
+pub async fn api_jwks() -> Result<(StatusCode, Json<JwkSet>), (StatusCode, Json<String>)> {
+    let keys = get_jwt_keys()?;
+    Ok((StatusCode::OK, Json(keys.jwks())))
 }
 
 pub async fn validate_claims(claims: &Claims) -> Result<StatusCode, (StatusCode, Json<String>)> {
@@ -97,40 +191,72 @@ pub fn get_jwt_secret_from_env() -> Result<String, (StatusCode, Json<String>)> {
 
     match env::var("JWT_SECRET") {
         Ok(secret) => Ok(secret),
-        Err(error) => {
-            return Err((
-                StatusCode::SERVICE_UNAVAILABLE,
-                Json(ApiError::DatabaseConnection(error.to_string()).to_string()),
-            ));
-        }
+        Err(error) => Err((
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ApiError::DatabaseConnection(error.to_string()).to_string()),
+        )),
     }
 }
 
-pub fn generate_jwt(input: UserAuthInfo) -> Result<String, (StatusCode, Json<String>)> {
-    let expiration = chrono::Utc::now()
-        .checked_add_signed(chrono::Duration::hours(1))
+fn build_claims(
+    input: UserAuthInfo,
+    scopes: Vec<String>,
+    expiration_seconds: i64,
+    sid: Option<Uuid>,
+    act: Option<Actor>,
+) -> Claims {
+    let now = chrono::Utc::now();
+    let expiration = now
+        .checked_add_signed(chrono::Duration::seconds(expiration_seconds))
         .expect("Invalid timestamp")
         .timestamp() as usize;
 
-    let claims = Claims {
+    Claims {
         id: input.id,
         email: input.email.to_string(),
         exp: expiration,
+        iat: now.timestamp() as usize,
+        iat_micros: Some(now.timestamp_micros()),
+        jti: Uuid::new_v4(),
+        sid,
         public_id: input.public_id,
         user_type: input.user_type,
-    };
+        api_key_id: None,
+        scopes,
+        impersonated: act.is_some(),
+        act,
+    }
+}
 
-    let secret = get_jwt_secret_from_env()?;
+pub fn generate_jwt(
+    input: UserAuthInfo,
+    scopes: Vec<String>,
+    session_id: Uuid,
+) -> Result<String, (StatusCode, Json<String>)> {
+    let claims = build_claims(
+        input,
+        scopes,
+        ACCESS_TOKEN_EXPIRATION_SECONDS,
+        Some(session_id),
+        None,
+    );
+    encode_token(&claims)
+}
 
-    match jsonwebtoken::encode(
-        &Header::default(),
-        &claims,
-        &EncodingKey::from_secret(secret.as_ref()),
-    ) {
-        Ok(token) => Ok(token),
-        Err(e) => Err((
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ApiError::CreateToken(e.to_string()).to_string()),
-        )),
-    }
+/// Short-lived access token letting `actor` act as the user. Returns the claims too, so the
+/// caller can record the token id.
+pub fn generate_impersonation_jwt(
+    input: UserAuthInfo,
+    scopes: Vec<String>,
+    actor: Actor,
+) -> Result<(String, Claims), (StatusCode, Json<String>)> {
+    let claims = build_claims(
+        input,
+        scopes,
+        IMPERSONATION_TOKEN_EXPIRATION_SECONDS,
+        None,
+        Some(actor),
+    );
+    let token = encode_token(&claims)?;
+    Ok((token, claims))
 }
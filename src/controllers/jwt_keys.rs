@@ -0,0 +1,254 @@
+use std::{env, fs, str::FromStr, sync::LazyLock};
+
+use axum::Json;
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use dotenvy::dotenv;
+use hyper::StatusCode;
+use jsonwebtoken::{
+    Algorithm, DecodingKey, EncodingKey,
+    jwk::{
+        AlgorithmParameters, CommonParameters, EllipticCurve, EllipticCurveKeyParameters,
+        EllipticCurveKeyType, Jwk, JwkSet, KeyAlgorithm, OctetKeyPairParameters, OctetKeyPairType,
+        PublicKeyUse, RSAKeyParameters, RSAKeyType,
+    },
+};
+use simple_asn1::{ASN1Block, oid};
+
+use crate::{controllers::jwt::get_jwt_secret_from_env, models::error::ApiError};
+
+/// Key used to sign the tokens issued by this service.
+pub struct SigningKey {
+    pub kid: Option<String>,
+    pub algorithm: Algorithm,
+    pub key: EncodingKey,
+}
+
+/// Key accepted when verifying a token. Public keys are also published in the JWKS document.
+pub struct VerificationKey {
+    pub kid: Option<String>,
+    pub algorithm: Algorithm,
+    pub key: DecodingKey,
+    pub jwk: Option<Jwk>,
+}
+
+pub struct JwtKeys {
+    pub signing: SigningKey,
+    pub verification: Vec<VerificationKey>,
+}
+
+impl JwtKeys {
+    /// Finds the key a token should be verified with. Tokens without a `kid` are only accepted
+    /// by keys that have no id either, which is how the HS256 shared secret is configured.
+    pub fn find_verification_key(&self, kid: Option<&str>) -> Option<&VerificationKey> {
+        self.verification
+            .iter()
+            .find(|key| key.kid.as_deref() == kid)
+    }
+
+    pub fn jwks(&self) -> JwkSet {
+        JwkSet {
+            keys: self
+                .verification
+                .iter()
+                .filter_map(|key| key.jwk.clone())
+                .collect(),
+        }
+    }
+}
+
+static JWT_KEYS: LazyLock<Result<JwtKeys, String>> = LazyLock::new(load_jwt_keys);
+
+pub fn get_jwt_keys() -> Result<&'static JwtKeys, (StatusCode, Json<String>)> {
+    match JWT_KEYS.as_ref() {
+        Ok(keys) => Ok(keys),
+        Err(e) => Err((
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ApiError::JwtKeys(e.to_string()).to_string()),
+        )),
+    }
+}
+
+/// Loads the signing and verification keys from the environment.
+///
+/// - `JWT_ALGORITHM`: `HS256` (default), `RS256`, `ES256` or `EdDSA`.
+/// - `JWT_SECRET`: shared secret, only used with `HS256`.
+/// - `JWT_KEY_ID`: `kid` written in the header of issued tokens.
+/// - `JWT_PRIVATE_KEY_PATH` / `JWT_PUBLIC_KEY_PATH`: PEM files of the current key pair.
+/// - `JWT_PREVIOUS_PUBLIC_KEYS`: comma separated `kid=path` list of retired public keys that are
+///   still accepted (and published) while the tokens they signed haven't expired.
+fn load_jwt_keys() -> Result<JwtKeys, String> {
+    dotenv().ok();
+
+    let algorithm = match env::var("JWT_ALGORITHM") {
+        Ok(algorithm) => match Algorithm::from_str(algorithm.trim()) {
+            Ok(algorithm) => algorithm,
+            Err(_) => return Err(format!("Unsupported JWT_ALGORITHM: {}", algorithm)),
+        },
+        Err(_) => Algorithm::HS256,
+    };
+
+    if algorithm == Algorithm::HS256 {
+        let secret = get_jwt_secret_from_env().map_err(|e| e.1.0)?;
+        return Ok(JwtKeys {
+            signing: SigningKey {
+                kid: None,
+                algorithm,
+                key: EncodingKey::from_secret(secret.as_bytes()),
+            },
+            verification: vec![VerificationKey {
+                kid: None,
+                algorithm,
+                key: DecodingKey::from_secret(secret.as_bytes()),
+                jwk: None,
+            }],
+        });
+    }
+
+    let kid = match env::var("JWT_KEY_ID") {
+        Ok(kid) if !kid.trim().is_empty() => kid.trim().to_string(),
+        _ => return Err("Missing JWT_KEY_ID".to_string()),
+    };
+    let private_pem = read_env_file("JWT_PRIVATE_KEY_PATH")?;
+    let public_pem = read_env_file("JWT_PUBLIC_KEY_PATH")?;
+
+    let signing_key = match algorithm {
+        Algorithm::RS256 => EncodingKey::from_rsa_pem(&private_pem),
+        Algorithm::ES256 => EncodingKey::from_ec_pem(&private_pem),
+        Algorithm::EdDSA => EncodingKey::from_ed_pem(&private_pem),
+        _ => return Err(format!("Unsupported JWT_ALGORITHM: {:?}", algorithm)),
+    }
+    .map_err(|e| format!("Invalid JWT private key: {}", e))?;
+
+    let current_key = load_public_key(&kid, &public_pem)?;
+    if current_key.algorithm != algorithm {
+        return Err("JWT_PUBLIC_KEY_PATH doesn't match JWT_ALGORITHM".to_string());
+    }
+
+    let mut verification = vec![current_key];
+
+    if let Ok(previous_keys) = env::var("JWT_PREVIOUS_PUBLIC_KEYS") {
+        for entry in previous_keys.split(',').filter(|e| !e.trim().is_empty()) {
+            let (previous_kid, path) = match entry.split_once('=') {
+                Some((previous_kid, path)) => (previous_kid.trim(), path.trim()),
+                None => return Err(format!("Invalid JWT_PREVIOUS_PUBLIC_KEYS entry: {}", entry)),
+            };
+            let pem = fs::read(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+            verification.push(load_public_key(previous_kid, &pem)?);
+        }
+    }
+
+    Ok(JwtKeys {
+        signing: SigningKey {
+            kid: Some(kid),
+            algorithm,
+            key: signing_key,
+        },
+        verification,
+    })
+}
+
+fn read_env_file(var: &str) -> Result<Vec<u8>, String> {
+    let path = env::var(var).map_err(|_| format!("Missing {}", var))?;
+    fs::read(&path).map_err(|e| format!("Failed to read {}: {}", path, e))
+}
+
+/// Parses a PEM encoded `SubjectPublicKeyInfo`, detecting the algorithm from the key type and
+/// building both the decoding key and its JWK representation.
+fn load_public_key(kid: &str, pem: &[u8]) -> Result<VerificationKey, String> {
+    let invalid_key = |reason: &str| format!("Invalid public key '{}': {}", kid, reason);
+
+    let pem = pem::parse(pem).map_err(|e| invalid_key(&e.to_string()))?;
+    let blocks = simple_asn1::from_der(pem.contents()).map_err(|e| invalid_key(&e.to_string()))?;
+
+    let (key_type, public_key) = match blocks.first() {
+        Some(ASN1Block::Sequence(_, spki)) => match (spki.first(), spki.get(1)) {
+            (Some(ASN1Block::Sequence(_, identifier)), Some(ASN1Block::BitString(_, _, bits))) => {
+                match identifier.first() {
+                    Some(ASN1Block::ObjectIdentifier(_, key_type)) => (key_type.clone(), bits),
+                    _ => return Err(invalid_key("missing algorithm identifier")),
+                }
+            }
+            _ => return Err(invalid_key("not a SubjectPublicKeyInfo")),
+        },
+        _ => return Err(invalid_key("not a SubjectPublicKeyInfo")),
+    };
+
+    let common = |algorithm: KeyAlgorithm| CommonParameters {
+        public_key_use: Some(PublicKeyUse::Signature),
+        key_algorithm: Some(algorithm),
+        key_id: Some(kid.to_string()),
+        ..Default::default()
+    };
+
+    if key_type == oid!(1, 2, 840, 113549, 1, 1, 1) {
+        let components =
+            simple_asn1::from_der(public_key).map_err(|e| invalid_key(&e.to_string()))?;
+        let (n, e) = match components.first() {
+            Some(ASN1Block::Sequence(_, values)) => match (values.first(), values.get(1)) {
+                (Some(ASN1Block::Integer(_, n)), Some(ASN1Block::Integer(_, e))) => {
+                    (n.to_bytes_be().1, e.to_bytes_be().1)
+                }
+                _ => return Err(invalid_key("malformed RSA key")),
+            },
+            _ => return Err(invalid_key("malformed RSA key")),
+        };
+
+        return Ok(VerificationKey {
+            kid: Some(kid.to_string()),
+            algorithm: Algorithm::RS256,
+            key: DecodingKey::from_rsa_raw_components(&n, &e),
+            jwk: Some(Jwk {
+                common: common(KeyAlgorithm::RS256),
+                algorithm: AlgorithmParameters::RSA(RSAKeyParameters {
+                    key_type: RSAKeyType::RSA,
+                    n: URL_SAFE_NO_PAD.encode(n),
+                    e: URL_SAFE_NO_PAD.encode(e),
+                }),
+            }),
+        });
+    }
+
+    if key_type == oid!(1, 2, 840, 10045, 2, 1) {
+        if public_key.len() != 65 || public_key[0] != 0x04 {
+            return Err(invalid_key("only uncompressed P-256 keys are supported"));
+        }
+        let x = URL_SAFE_NO_PAD.encode(&public_key[1..33]);
+        let y = URL_SAFE_NO_PAD.encode(&public_key[33..65]);
+
+        return Ok(VerificationKey {
+            kid: Some(kid.to_string()),
+            algorithm: Algorithm::ES256,
+            key: DecodingKey::from_ec_components(&x, &y)
+                .map_err(|e| invalid_key(&e.to_string()))?,
+            jwk: Some(Jwk {
+                common: common(KeyAlgorithm::ES256),
+                algorithm: AlgorithmParameters::EllipticCurve(EllipticCurveKeyParameters {
+                    key_type: EllipticCurveKeyType::EC,
+                    curve: EllipticCurve::P256,
+                    x,
+                    y,
+                }),
+            }),
+        });
+    }
+
+    if key_type == oid!(1, 3, 101, 112) {
+        let x = URL_SAFE_NO_PAD.encode(public_key);
+
+        return Ok(VerificationKey {
+            kid: Some(kid.to_string()),
+            algorithm: Algorithm::EdDSA,
+            key: DecodingKey::from_ed_components(&x).map_err(|e| invalid_key(&e.to_string()))?,
+            jwk: Some(Jwk {
+                common: common(KeyAlgorithm::EdDSA),
+                algorithm: AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                    key_type: OctetKeyPairType::OctetKeyPair,
+                    curve: EllipticCurve::Ed25519,
+                    x,
+                }),
+            }),
+        });
+    }
+
+    Err(invalid_key("unsupported key type"))
+}
@@ -0,0 +1,172 @@
+use std::{env, net::IpAddr, sync::LazyLock};
+
+use axum::Json;
+use diesel_async::AsyncPgConnection;
+use dotenvy::dotenv;
+use hyper::StatusCode;
+
+use crate::models::{self, error::ApiError, login_throttle::ThrottleScope};
+
+pub struct LoginThrottlePolicy {
+    /// Failures allowed per e-mail address before it gets locked.
+    pub max_account_attempts: i32,
+    /// Failures allowed per client IP before it gets locked.
+    pub max_ip_attempts: i32,
+    /// Length of the first lock, doubled for every further failure.
+    pub lockout_seconds: i64,
+    pub max_lockout_seconds: i64,
+    /// Failures older than this are forgotten.
+    pub attempt_window_minutes: i64,
+}
+
+impl LoginThrottlePolicy {
+    /// Reads `LOGIN_MAX_ATTEMPTS_PER_ACCOUNT`, `LOGIN_MAX_ATTEMPTS_PER_IP`,
+    /// `LOGIN_LOCKOUT_SECONDS`, `LOGIN_MAX_LOCKOUT_SECONDS` and `LOGIN_ATTEMPT_WINDOW_MINUTES`.
+    pub fn from_env() -> Self {
+        dotenv().ok();
+
+        fn number<T: std::str::FromStr>(var: &str, default: T) -> T {
+            env::var(var)
+                .ok()
+                .and_then(|value| value.trim().parse::<T>().ok())
+                .unwrap_or(default)
+        }
+
+        Self {
+            max_account_attempts: number("LOGIN_MAX_ATTEMPTS_PER_ACCOUNT", 5),
+            max_ip_attempts: number("LOGIN_MAX_ATTEMPTS_PER_IP", 20),
+            lockout_seconds: number("LOGIN_LOCKOUT_SECONDS", 30),
+            max_lockout_seconds: number("LOGIN_MAX_LOCKOUT_SECONDS", 900),
+            attempt_window_minutes: number("LOGIN_ATTEMPT_WINDOW_MINUTES", 15),
+        }
+    }
+
+    fn max_attempts(&self, scope: ThrottleScope) -> i32 {
+        match scope {
+            ThrottleScope::Account => self.max_account_attempts,
+            ThrottleScope::Ip => self.max_ip_attempts,
+        }
+    }
+
+    /// How long to lock after the given number of failures, if at all.
+    fn lockout_duration(&self, scope: ThrottleScope, failed_attempts: i32) -> Option<i64> {
+        let excess = failed_attempts - self.max_attempts(scope);
+        if excess < 0 {
+            return None;
+        }
+
+        let seconds = 2_i64
+            .checked_pow(excess as u32)
+            .and_then(|factor| factor.checked_mul(self.lockout_seconds))
+            .unwrap_or(i64::MAX);
+        Some(seconds.min(self.max_lockout_seconds))
+    }
+}
+
+static LOGIN_THROTTLE_POLICY: LazyLock<LoginThrottlePolicy> =
+    LazyLock::new(LoginThrottlePolicy::from_env);
+
+/// Throttle subject of an e-mail address, normalized so case variations share a counter.
+fn account_subject(email: &str) -> String {
+    email.trim().to_lowercase().chars().take(255).collect()
+}
+
+fn subjects(email: &str, ip: IpAddr) -> [(ThrottleScope, String); 2] {
+    [
+        (ThrottleScope::Account, account_subject(email)),
+        (ThrottleScope::Ip, ip.to_string()),
+    ]
+}
+
+/// Fails with 429 while either the account or the client IP is locked.
+pub async fn check_login_allowed(
+    conn: &mut AsyncPgConnection,
+    email: &str,
+    ip: IpAddr,
+) -> Result<(), (StatusCode, Json<String>)> {
+    let now = chrono::Utc::now().naive_utc();
+    let mut retry_after = 0;
+
+    for (scope, subject) in subjects(email, ip) {
+        let throttle =
+            match models::login_throttle::find_login_throttle(conn, scope, &subject).await {
+                Ok(throttle) => throttle,
+                Err(e) => return Err((StatusCode::INTERNAL_SERVER_ERROR, Json(e.to_string()))),
+            };
+
+        if let Some(locked_until) = throttle.and_then(|throttle| throttle.locked_until)
+            && locked_until > now
+        {
+            retry_after = retry_after.max((locked_until - now).num_seconds() + 1);
+        }
+    }
+
+    if retry_after > 0 {
+        return Err((
+            StatusCode::TOO_MANY_REQUESTS,
+            Json(ApiError::LoginLocked(retry_after).to_string()),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Counts a failed login against both the account and the client IP, locking them once they go
+/// over their allowance.
+pub async fn register_login_failure(
+    conn: &mut AsyncPgConnection,
+    email: &str,
+    ip: IpAddr,
+) -> Result<(), (StatusCode, Json<String>)> {
+    let policy = &*LOGIN_THROTTLE_POLICY;
+    let now = chrono::Utc::now().naive_utc();
+
+    let window_start = now - chrono::Duration::minutes(policy.attempt_window_minutes);
+    if let Err(e) = models::login_throttle::delete_stale_login_throttles(conn, window_start).await {
+        return Err((StatusCode::INTERNAL_SERVER_ERROR, Json(e.to_string())));
+    }
+
+    for (scope, subject) in subjects(email, ip) {
+        let throttle =
+            match models::login_throttle::record_login_failure(conn, scope, &subject).await {
+                Ok(throttle) => throttle,
+                Err(e) => return Err((StatusCode::INTERNAL_SERVER_ERROR, Json(e.to_string()))),
+            };
+
+        if let Some(seconds) = policy.lockout_duration(scope, throttle.failed_attempts) {
+            let until = now + chrono::Duration::seconds(seconds);
+            if let Err(e) =
+                models::login_throttle::lock_login_throttle(conn, scope, &subject, until).await
+            {
+                return Err((StatusCode::INTERNAL_SERVER_ERROR, Json(e.to_string())));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Resets the account's counter. The IP counter is kept, so logging into one account doesn't
+/// excuse failures against others from the same address.
+pub async fn register_login_success(
+    conn: &mut AsyncPgConnection,
+    email: &str,
+) -> Result<(), (StatusCode, Json<String>)> {
+    unlock_account(conn, email).await
+}
+
+pub async fn unlock_account(
+    conn: &mut AsyncPgConnection,
+    email: &str,
+) -> Result<(), (StatusCode, Json<String>)> {
+    match models::login_throttle::clear_login_throttle(
+        conn,
+        ThrottleScope::Account,
+        &account_subject(email),
+    )
+    .await
+    {
+        Ok(_) => Ok(()),
+        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, Json(e.to_string()))),
+    }
+}
@@ -0,0 +1,456 @@
+use std::{env, net::SocketAddr, sync::LazyLock};
+
+use aes_gcm::{
+    Aes256Gcm, KeyInit, Nonce,
+    aead::{Aead, AeadCore, OsRng, Payload},
+};
+use axum::{
+    Json,
+    extract::{ConnectInfo, State},
+};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use diesel_async::{AsyncPgConnection, pooled_connection::deadpool::Pool};
+use dotenvy::dotenv;
+use hyper::StatusCode;
+use rand::{Rng, seq::SliceRandom};
+use totp_rs::{Algorithm, TOTP};
+use uuid::Uuid;
+
+use crate::{
+    controllers::{
+        auth::CurrentUser,
+        hasher::verify_user_password,
+        jwt::{decode_token, encode_token},
+        login_throttle::{check_login_allowed, register_login_failure, register_login_success},
+        session::{ClientInfo, hash_token, start_session},
+        utils::get_conn,
+    },
+    models::{
+        self,
+        error::ApiError,
+        jwt::MfaPendingClaims,
+        mfa::{
+            ConfirmTotpInput, DisableTotpInput, MfaLoginInput, MfaRecoveryCode, RecoveryCodes,
+            TotpEnrollment, UserMfa,
+        },
+        session::TokenPair,
+        user::User,
+    },
+};
+
+pub const MFA_TOKEN_EXPIRATION_SECONDS: i64 = 300;
+const MFA_TOKEN_USE: &str = "mfa";
+
+const TOTP_DIGITS: usize = 6;
+const TOTP_STEP_SECONDS: u64 = 30;
+/// Codes from one step before or after the current one are still accepted, for clock drift.
+const TOTP_SKEW_STEPS: u64 = 1;
+const TOTP_SECRET_BYTES: usize = 20;
+
+const RECOVERY_CODE_COUNT: usize = 10;
+const RECOVERY_CODE_ALPHABET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+
+static MFA_CIPHER: LazyLock<Result<Aes256Gcm, String>> = LazyLock::new(load_cipher);
+
+/// Reads `MFA_ENCRYPTION_KEY`, a base64 encoded 256-bit key used to encrypt the TOTP secrets.
+fn load_cipher() -> Result<Aes256Gcm, String> {
+    dotenv().ok();
+
+    let key = env::var("MFA_ENCRYPTION_KEY").map_err(|_| "Missing MFA_ENCRYPTION_KEY")?;
+    let key = STANDARD
+        .decode(key.trim())
+        .map_err(|e| format!("Invalid MFA_ENCRYPTION_KEY: {}", e))?;
+
+    Aes256Gcm::new_from_slice(&key)
+        .map_err(|_| "MFA_ENCRYPTION_KEY must be 32 bytes long".to_string())
+}
+
+fn get_cipher() -> Result<&'static Aes256Gcm, (StatusCode, Json<String>)> {
+    MFA_CIPHER.as_ref().map_err(|e| {
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ApiError::MfaConfiguration(e.to_string()).to_string()),
+        )
+    })
+}
+
+fn mfa_error(e: impl ToString) -> (StatusCode, Json<String>) {
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(ApiError::MfaConfiguration(e.to_string()).to_string()),
+    )
+}
+
+/// Builds a pending enrollment with the secret encrypted. The user id is bound to the ciphertext,
+/// so a row copied to another user won't decrypt.
+fn new_enrollment(user_id: &Uuid, secret: &[u8]) -> Result<UserMfa, (StatusCode, Json<String>)> {
+    let cipher = get_cipher()?;
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+
+    let payload = Payload {
+        msg: secret,
+        aad: user_id.as_bytes(),
+    };
+    let secret_ciphertext = cipher.encrypt(&nonce, payload).map_err(mfa_error)?;
+
+    Ok(UserMfa {
+        user_id: *user_id,
+        secret_ciphertext,
+        secret_nonce: nonce.to_vec(),
+        last_used_step: None,
+        create_date: chrono::Utc::now().naive_utc(),
+        enabled_date: None,
+    })
+}
+
+fn decrypt_secret(mfa: &UserMfa) -> Result<Vec<u8>, (StatusCode, Json<String>)> {
+    let cipher = get_cipher()?;
+    if mfa.secret_nonce.len() != 12 {
+        return Err(mfa_error("Invalid stored nonce"));
+    }
+
+    let payload = Payload {
+        msg: &mfa.secret_ciphertext,
+        aad: mfa.user_id.as_bytes(),
+    };
+    cipher
+        .decrypt(Nonce::from_slice(&mfa.secret_nonce), payload)
+        .map_err(mfa_error)
+}
+
+fn build_totp(secret: Vec<u8>, email: &str) -> Result<TOTP, (StatusCode, Json<String>)> {
+    dotenv().ok();
+    let issuer = env::var("MFA_ISSUER").unwrap_or_else(|_| "Rust Backend".to_string());
+
+    // Skew is handled by `find_totp_step`, which needs to know the step that matched.
+    TOTP::new(
+        Algorithm::SHA1,
+        TOTP_DIGITS,
+        0,
+        TOTP_STEP_SECONDS,
+        secret,
+        Some(issuer.replace(':', "")),
+        email.replace(':', ""),
+    )
+    .map_err(mfa_error)
+}
+
+/// Returns the time step the code belongs to, if it matches the current one or a neighbour.
+fn find_totp_step(totp: &TOTP, code: &str) -> Option<u64> {
+    let now = chrono::Utc::now().timestamp() as u64;
+    let current = now / TOTP_STEP_SECONDS;
+
+    (current.saturating_sub(TOTP_SKEW_STEPS)..=current + TOTP_SKEW_STEPS)
+        .find(|step| totp.check(code, step * TOTP_STEP_SECONDS))
+}
+
+fn is_totp_code(code: &str) -> bool {
+    code.len() == TOTP_DIGITS && code.chars().all(|c| c.is_ascii_digit())
+}
+
+/// Accepts a TOTP code at most once, so an intercepted code can't be replayed.
+async fn verify_totp_code(
+    conn: &mut AsyncPgConnection,
+    user: &User,
+    mfa: &UserMfa,
+    code: &str,
+) -> Result<bool, (StatusCode, Json<String>)> {
+    let totp = build_totp(decrypt_secret(mfa)?, &user.email)?;
+
+    let step = match find_totp_step(&totp, code) {
+        Some(step) => step,
+        None => return Ok(false),
+    };
+
+    models::mfa::mark_totp_step_used(conn, &user.id, step as i64)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, Json(e.to_string())))
+}
+
+fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+/// Checks a TOTP code or, failing the format, spends a recovery code.
+async fn verify_second_factor(
+    conn: &mut AsyncPgConnection,
+    user: &User,
+    mfa: &UserMfa,
+    code: &str,
+) -> Result<bool, (StatusCode, Json<String>)> {
+    let code = code.trim();
+    if is_totp_code(code) {
+        return verify_totp_code(conn, user, mfa, code).await;
+    }
+
+    let code_hash = hash_token(&normalize_recovery_code(code));
+    models::mfa::use_recovery_code(conn, &user.id, &code_hash)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, Json(e.to_string())))
+}
+
+/// Generates a new set of recovery codes formatted as `xxxxx-xxxxx`, returning the plain codes
+/// for the user and the hashed rows to store.
+fn generate_recovery_codes(user_id: &Uuid) -> (Vec<String>, Vec<MfaRecoveryCode>) {
+    let mut rng = rand::thread_rng();
+    let now = chrono::Utc::now().naive_utc();
+
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let chars: String = (0..10)
+                .map(|_| *RECOVERY_CODE_ALPHABET.choose(&mut rng).unwrap() as char)
+                .collect();
+            let code = format!("{}-{}", &chars[..5], &chars[5..]);
+
+            let row = MfaRecoveryCode {
+                id: Uuid::new_v4(),
+                user_id: *user_id,
+                code_hash: hash_token(&chars),
+                create_date: now,
+                used_date: None,
+            };
+            (code, row)
+        })
+        .unzip()
+}
+
+/// Issues the token that proves the password step of a login for an account with MFA enabled.
+pub fn issue_mfa_token(
+    user_id: &Uuid,
+    login_type: &str,
+) -> Result<String, (StatusCode, Json<String>)> {
+    let now = chrono::Utc::now();
+    let claims = MfaPendingClaims {
+        sub: *user_id,
+        token_use: MFA_TOKEN_USE.to_string(),
+        login_type: login_type.to_string(),
+        exp: (now + chrono::Duration::seconds(MFA_TOKEN_EXPIRATION_SECONDS)).timestamp() as usize,
+        iat: now.timestamp() as usize,
+        jti: Uuid::new_v4(),
+    };
+
+    encode_token(&claims)
+}
+
+fn decode_mfa_token(token: &str) -> Result<MfaPendingClaims, (StatusCode, Json<String>)> {
+    let claims = decode_token::<MfaPendingClaims>(token)?;
+    if claims.token_use != MFA_TOKEN_USE {
+        return Err((
+            StatusCode::UNAUTHORIZED,
+            Json(ApiError::InvalidAuthorizationToken.to_string()),
+        ));
+    }
+    Ok(claims)
+}
+
+/// Whether the user finished enrolling a second factor.
+pub async fn find_enabled_mfa(
+    conn: &mut AsyncPgConnection,
+    user_id: &Uuid,
+) -> Result<Option<UserMfa>, (StatusCode, Json<String>)> {
+    match models::mfa::find_user_mfa(conn, user_id).await {
+        Ok(mfa) => Ok(mfa.filter(|mfa| mfa.enabled_date.is_some())),
+        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, Json(e.to_string()))),
+    }
+}
+
+/// Starts a TOTP enrollment. It only takes effect once confirmed with a code through
+/// `/user/mfa/totp/confirm`.
+#[utoipa::path(post, path = "/user/mfa/totp/enroll", responses((status = OK, body = TotpEnrollment)), security(("bearer_auth" = ["profile:write"])))]
+pub async fn api_enroll_totp(
+    State(pool): State<Pool<AsyncPgConnection>>,
+    CurrentUser { user, .. }: CurrentUser,
+) -> Result<(StatusCode, Json<TotpEnrollment>), (StatusCode, Json<String>)> {
+    let conn = &mut get_conn(&pool).await?;
+
+    if find_enabled_mfa(conn, &user.id).await?.is_some() {
+        return Err((
+            StatusCode::CONFLICT,
+            Json(ApiError::MfaAlreadyEnabled.to_string()),
+        ));
+    }
+
+    let mut secret = vec![0u8; TOTP_SECRET_BYTES];
+    rand::thread_rng().fill(&mut secret[..]);
+
+    let totp = build_totp(secret.clone(), &user.email)?;
+    let mfa = new_enrollment(&user.id, &secret)?;
+    if let Err(e) = models::mfa::save_user_mfa(conn, &mfa).await {
+        return Err((StatusCode::INTERNAL_SERVER_ERROR, Json(e.to_string())));
+    }
+
+    Ok((
+        StatusCode::OK,
+        Json(TotpEnrollment {
+            secret: totp.get_secret_base32(),
+            otpauth_uri: totp.get_url(),
+        }),
+    ))
+}
+
+/// Enables TOTP after checking a first code, returning the recovery codes. They are only shown
+/// this once.
+#[utoipa::path(post, path = "/user/mfa/totp/confirm", request_body = ConfirmTotpInput, responses((status = OK, body = RecoveryCodes)), security(("bearer_auth" = ["profile:write"])))]
+pub async fn api_confirm_totp(
+    State(pool): State<Pool<AsyncPgConnection>>,
+    CurrentUser { user, .. }: CurrentUser,
+    input: Json<ConfirmTotpInput>,
+) -> Result<(StatusCode, Json<RecoveryCodes>), (StatusCode, Json<String>)> {
+    let code = input.0.code.trim().to_string();
+    if !is_totp_code(&code) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ApiError::InvalidMfaCode.to_string()),
+        ));
+    }
+
+    let conn = &mut get_conn(&pool).await?;
+
+    let mfa = match models::mfa::find_user_mfa(conn, &user.id).await {
+        Ok(Some(mfa)) if mfa.enabled_date.is_none() => mfa,
+        Ok(Some(_)) => {
+            return Err((
+                StatusCode::CONFLICT,
+                Json(ApiError::MfaAlreadyEnabled.to_string()),
+            ));
+        }
+        Ok(None) => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                Json(ApiError::MfaNotEnabled.to_string()),
+            ));
+        }
+        Err(e) => return Err((StatusCode::INTERNAL_SERVER_ERROR, Json(e.to_string()))),
+    };
+
+    if !verify_totp_code(conn, &user, &mfa, &code).await? {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ApiError::InvalidMfaCode.to_string()),
+        ));
+    }
+
+    let (recovery_codes, rows) = generate_recovery_codes(&user.id);
+    if let Err(e) = models::mfa::enable_user_mfa(conn, &user.id, &rows).await {
+        return Err((StatusCode::INTERNAL_SERVER_ERROR, Json(e.to_string())));
+    }
+
+    Ok((StatusCode::OK, Json(RecoveryCodes { recovery_codes })))
+}
+
+#[utoipa::path(post, path = "/user/mfa/totp/disable", request_body = DisableTotpInput, responses((status = OK)), security(("bearer_auth" = ["profile:write"])))]
+pub async fn api_disable_totp(
+    State(pool): State<Pool<AsyncPgConnection>>,
+    CurrentUser { user, .. }: CurrentUser,
+    input: Json<DisableTotpInput>,
+) -> Result<StatusCode, (StatusCode, Json<String>)> {
+    let input = input.0;
+    if input.password.is_empty() || input.code.trim().is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ApiError::InvalidData.to_string()),
+        ));
+    }
+
+    let conn = &mut get_conn(&pool).await?;
+
+    let mfa = match find_enabled_mfa(conn, &user.id).await? {
+        Some(mfa) => mfa,
+        None => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                Json(ApiError::MfaNotEnabled.to_string()),
+            ));
+        }
+    };
+
+    if !verify_user_password(&input.password, &user.password)
+        .await?
+        .valid
+    {
+        return Err((
+            StatusCode::FORBIDDEN,
+            Json(ApiError::InvalidPassword.to_string()),
+        ));
+    }
+
+    if !verify_second_factor(conn, &user, &mfa, &input.code).await? {
+        return Err((
+            StatusCode::FORBIDDEN,
+            Json(ApiError::InvalidMfaCode.to_string()),
+        ));
+    }
+
+    if let Err(e) = models::mfa::delete_user_mfa(conn, &user.id).await {
+        return Err((StatusCode::INTERNAL_SERVER_ERROR, Json(e.to_string())));
+    }
+
+    Ok(StatusCode::OK)
+}
+
+/// Second step of the login for accounts with MFA: exchanges the token returned by `/user/login`
+/// and a TOTP or recovery code for the session tokens. Wrong codes count as failed logins.
+#[utoipa::path(post, path = "/user/login/mfa", request_body = MfaLoginInput, responses((status = OK, body = TokenPair)))]
+pub async fn api_login_mfa(
+    State(pool): State<Pool<AsyncPgConnection>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    client: ClientInfo,
+    input: Json<MfaLoginInput>,
+) -> Result<(StatusCode, Json<TokenPair>), (StatusCode, Json<String>)> {
+    let input = input.0;
+    if input.code.trim().is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ApiError::InvalidData.to_string()),
+        ));
+    }
+
+    let claims = decode_mfa_token(input.mfa_token.trim())?;
+    let ip = addr.ip();
+    let conn = &mut get_conn(&pool).await?;
+
+    let user = match models::user::find_user_by_id(conn, &claims.sub).await {
+        Ok(user) => user,
+        Err(_) => {
+            return Err((
+                StatusCode::UNAUTHORIZED,
+                Json(ApiError::InvalidAuthorizationToken.to_string()),
+            ));
+        }
+    };
+
+    check_login_allowed(conn, &user.email, ip).await?;
+
+    let mfa = match find_enabled_mfa(conn, &user.id).await? {
+        Some(mfa) => mfa,
+        None => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                Json(ApiError::MfaNotEnabled.to_string()),
+            ));
+        }
+    };
+
+    if !verify_second_factor(conn, &user, &mfa, &input.code).await? {
+        register_login_failure(conn, &user.email, ip).await?;
+        return Err((
+            StatusCode::UNAUTHORIZED,
+            Json(ApiError::InvalidMfaCode.to_string()),
+        ));
+    }
+
+    register_login_success(conn, &user.email).await?;
+
+    if !user.is_active || user.deletion_date.is_some() {
+        return Err((
+            StatusCode::FORBIDDEN,
+            Json(ApiError::NotActiveUser.to_string()),
+        ));
+    }
+
+    let tokens = start_session(conn, &user, &claims.login_type, &client).await?;
+    Ok((StatusCode::OK, Json(tokens)))
+}
@@ -1,3 +1,22 @@
+pub mod account;
+pub mod admin;
+pub mod api_key;
+pub mod audit;
+pub mod auth;
+pub mod authorization;
+pub mod email;
+pub mod hasher;
 pub mod jwt;
+pub mod jwt_keys;
+pub mod login_throttle;
+pub mod mfa;
+pub mod oidc;
+pub mod pagination;
+pub mod password;
+pub mod rate_limit;
+pub mod revocation;
+pub mod session;
 pub mod user;
+pub mod user_token;
 pub mod utils;
+pub mod webauthn;
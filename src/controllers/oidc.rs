@@ -0,0 +1,603 @@
+use std::{
+    collections::HashMap,
+    env,
+    sync::{LazyLock, RwLock},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    Json,
+    extract::{Path, State},
+};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use diesel_async::{AsyncPgConnection, pooled_connection::deadpool::Pool};
+use dotenvy::dotenv;
+use hyper::StatusCode;
+use jsonwebtoken::{Algorithm, DecodingKey, Validation, decode, decode_header, jwk::JwkSet};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::{
+    controllers::{
+        hasher::hash_password,
+        session::{ClientInfo, generate_opaque_token, hash_token},
+        user::finish_login,
+        utils::{get_conn, random_public_id},
+    },
+    models::{
+        self,
+        error::ApiError,
+        oauth::{OAuthIdentity, OidcAuthRequest, OidcAuthorization, OidcCallbackInput},
+        role::UserRole,
+        session::LoginResponse,
+        user::User,
+    },
+};
+
+pub const OIDC_AUTH_REQUEST_EXPIRATION_MINUTES: i64 = 10;
+const DISCOVERY_CACHE_TTL: Duration = Duration::from_secs(3600);
+const JWKS_CACHE_TTL: Duration = Duration::from_secs(3600);
+/// Keys are refetched early when a token names one that isn't cached, e.g. after the provider
+/// rotated them, but not more often than this, so forged `kid`s can't make every callback fetch.
+const JWKS_REFETCH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// An identity provider users can sign in with, e.g. Google or a Keycloak realm.
+#[derive(Debug, Clone)]
+pub struct OidcProvider {
+    /// Stored as the user's `login_type`.
+    pub name: String,
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: Option<String>,
+    pub redirect_uri: String,
+    pub scopes: String,
+}
+
+/// The parts of the provider's `/.well-known/openid-configuration` we need.
+#[derive(Deserialize, Debug, Clone)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct IdTokenClaims {
+    sub: String,
+    email: Option<String>,
+    #[serde(default)]
+    email_verified: bool,
+    name: Option<String>,
+    nonce: Option<String>,
+}
+
+static OIDC_PROVIDERS: LazyLock<Result<Vec<OidcProvider>, String>> = LazyLock::new(load_providers);
+
+static HTTP_CLIENT: LazyLock<reqwest::Client> = LazyLock::new(|| {
+    reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()
+        .unwrap_or_default()
+});
+
+static DISCOVERY_CACHE: LazyLock<RwLock<HashMap<String, (ProviderMetadata, Instant)>>> =
+    LazyLock::new(|| RwLock::new(HashMap::new()));
+
+static JWKS_CACHE: LazyLock<RwLock<HashMap<String, (JwkSet, Instant)>>> =
+    LazyLock::new(|| RwLock::new(HashMap::new()));
+
+/// Reads the providers listed in `OIDC_PROVIDERS` (e.g. `google,keycloak`). Each one is
+/// configured through `OIDC_<NAME>_ISSUER`, `OIDC_<NAME>_CLIENT_ID`,
+/// `OIDC_<NAME>_CLIENT_SECRET` (optional for public clients), `OIDC_<NAME>_REDIRECT_URI` and
+/// `OIDC_<NAME>_SCOPES` (default `openid email profile`).
+fn load_providers() -> Result<Vec<OidcProvider>, String> {
+    dotenv().ok();
+
+    let names = env::var("OIDC_PROVIDERS").unwrap_or_default();
+
+    names
+        .split(',')
+        .map(|name| name.trim().to_lowercase())
+        .filter(|name| !name.is_empty())
+        .map(|name| {
+            if name.len() > 16
+                || !name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            {
+                return Err(format!("Invalid OIDC provider name: {}", name));
+            }
+
+            let prefix = format!("OIDC_{}", name.to_uppercase().replace('-', "_"));
+            let required = |suffix: &str| {
+                env::var(format!("{}_{}", prefix, suffix))
+                    .map_err(|_| format!("Missing {}_{}", prefix, suffix))
+            };
+
+            Ok(OidcProvider {
+                issuer: required("ISSUER")?.trim_end_matches('/').to_string(),
+                client_id: required("CLIENT_ID")?,
+                client_secret: env::var(format!("{}_CLIENT_SECRET", prefix)).ok(),
+                redirect_uri: required("REDIRECT_URI")?,
+                scopes: env::var(format!("{}_SCOPES", prefix))
+                    .unwrap_or_else(|_| "openid email profile".to_string()),
+                name,
+            })
+        })
+        .collect()
+}
+
+fn find_provider(name: &str) -> Result<&'static OidcProvider, (StatusCode, Json<String>)> {
+    let providers = OIDC_PROVIDERS.as_ref().map_err(|e| {
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ApiError::OidcConfiguration(e.to_string()).to_string()),
+        )
+    })?;
+
+    providers
+        .iter()
+        .find(|provider| provider.name == name)
+        .ok_or_else(|| {
+            (
+                StatusCode::NOT_FOUND,
+                Json(ApiError::OidcProviderNotFound.to_string()),
+            )
+        })
+}
+
+fn provider_error(e: impl ToString) -> (StatusCode, Json<String>) {
+    (
+        StatusCode::BAD_GATEWAY,
+        Json(ApiError::OidcProvider(e.to_string()).to_string()),
+    )
+}
+
+fn invalid_id_token() -> (StatusCode, Json<String>) {
+    (
+        StatusCode::UNAUTHORIZED,
+        Json(ApiError::InvalidIdToken.to_string()),
+    )
+}
+
+async fn fetch_json<T: for<'de> Deserialize<'de>>(
+    url: &str,
+) -> Result<T, (StatusCode, Json<String>)> {
+    HTTP_CLIENT
+        .get(url)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(provider_error)?
+        .json::<T>()
+        .await
+        .map_err(provider_error)
+}
+
+/// Fetches the provider's discovery document, cached for an hour.
+async fn provider_metadata(
+    provider: &OidcProvider,
+) -> Result<ProviderMetadata, (StatusCode, Json<String>)> {
+    if let Ok(cache) = DISCOVERY_CACHE.read()
+        && let Some((metadata, fetched)) = cache.get(&provider.name)
+        && fetched.elapsed() < DISCOVERY_CACHE_TTL
+    {
+        return Ok(metadata.clone());
+    }
+
+    let url = format!("{}/.well-known/openid-configuration", provider.issuer);
+    let metadata = fetch_json::<ProviderMetadata>(&url).await?;
+
+    if metadata.issuer.trim_end_matches('/') != provider.issuer {
+        return Err(provider_error(format!(
+            "Issuer mismatch in discovery document: {}",
+            metadata.issuer
+        )));
+    }
+
+    if let Ok(mut cache) = DISCOVERY_CACHE.write() {
+        cache.insert(provider.name.clone(), (metadata.clone(), Instant::now()));
+    }
+
+    Ok(metadata)
+}
+
+/// The cached keys, unless they expired or lack `kid` and are old enough to be refetched.
+fn cached_keys(cached: Option<&(JwkSet, Instant)>, kid: Option<&str>) -> Option<JwkSet> {
+    let (jwks, fetched) = cached?;
+    if fetched.elapsed() >= JWKS_CACHE_TTL {
+        return None;
+    }
+    if let Some(kid) = kid
+        && jwks.find(kid).is_none()
+        && fetched.elapsed() >= JWKS_REFETCH_INTERVAL
+    {
+        return None;
+    }
+    Some(jwks.clone())
+}
+
+/// Fetches the provider's signing keys, cached for an hour or until a token names a new one.
+async fn provider_keys(
+    provider: &OidcProvider,
+    metadata: &ProviderMetadata,
+    kid: Option<&str>,
+) -> Result<JwkSet, (StatusCode, Json<String>)> {
+    if let Ok(cache) = JWKS_CACHE.read()
+        && let Some(jwks) = cached_keys(cache.get(&provider.name), kid)
+    {
+        return Ok(jwks);
+    }
+
+    let jwks = fetch_json::<JwkSet>(&metadata.jwks_uri).await?;
+
+    if let Ok(mut cache) = JWKS_CACHE.write() {
+        cache.insert(provider.name.clone(), (jwks.clone(), Instant::now()));
+    }
+
+    Ok(jwks)
+}
+
+fn pkce_challenge(code_verifier: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()))
+}
+
+/// Checks the ID token signature against the provider's published keys, along with its issuer,
+/// audience, expiration and nonce.
+async fn verify_id_token(
+    provider: &OidcProvider,
+    metadata: &ProviderMetadata,
+    id_token: &str,
+    nonce: &str,
+) -> Result<IdTokenClaims, (StatusCode, Json<String>)> {
+    let header = decode_header(id_token).map_err(|_| invalid_id_token())?;
+    if !matches!(
+        header.alg,
+        Algorithm::RS256
+            | Algorithm::RS384
+            | Algorithm::RS512
+            | Algorithm::PS256
+            | Algorithm::PS384
+            | Algorithm::PS512
+            | Algorithm::ES256
+            | Algorithm::ES384
+            | Algorithm::EdDSA
+    ) {
+        return Err(invalid_id_token());
+    }
+
+    let jwks = provider_keys(provider, metadata, header.kid.as_deref()).await?;
+    let jwk = match header.kid.as_deref() {
+        Some(kid) => jwks.find(kid),
+        None if jwks.keys.len() == 1 => jwks.keys.first(),
+        None => None,
+    }
+    .ok_or_else(invalid_id_token)?;
+    let key = DecodingKey::from_jwk(jwk).map_err(|_| invalid_id_token())?;
+
+    let mut validation = Validation::new(header.alg);
+    validation.set_audience(&[&provider.client_id]);
+    validation.set_issuer(&[&provider.issuer, &metadata.issuer]);
+
+    let claims = decode::<IdTokenClaims>(id_token, &key, &validation)
+        .map_err(|_| invalid_id_token())?
+        .claims;
+
+    if claims.nonce.as_deref() != Some(nonce) {
+        return Err(invalid_id_token());
+    }
+
+    Ok(claims)
+}
+
+/// Exchanges the authorization code, proving possession of the PKCE verifier.
+async fn exchange_code(
+    provider: &OidcProvider,
+    metadata: &ProviderMetadata,
+    code: &str,
+    code_verifier: &str,
+) -> Result<String, (StatusCode, Json<String>)> {
+    let mut form = vec![
+        ("grant_type", "authorization_code"),
+        ("code", code),
+        ("redirect_uri", provider.redirect_uri.as_str()),
+        ("client_id", provider.client_id.as_str()),
+        ("code_verifier", code_verifier),
+    ];
+    if let Some(secret) = &provider.client_secret {
+        form.push(("client_secret", secret.as_str()));
+    }
+
+    let response = HTTP_CLIENT
+        .post(&metadata.token_endpoint)
+        .form(&form)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(provider_error)?
+        .json::<TokenResponse>()
+        .await
+        .map_err(provider_error)?;
+
+    response
+        .id_token
+        .ok_or_else(|| provider_error("The token response has no id_token"))
+}
+
+/// Maps the provider identity to a user: an already linked account, an existing account with the
+/// same verified e-mail (which gets linked), or a new account.
+async fn find_or_create_user(
+    conn: &mut AsyncPgConnection,
+    provider: &OidcProvider,
+    claims: &IdTokenClaims,
+) -> Result<User, (StatusCode, Json<String>)> {
+    let identity = match models::oauth::find_oauth_identity(conn, &provider.name, &claims.sub).await
+    {
+        Ok(identity) => identity,
+        Err(e) => return Err((StatusCode::INTERNAL_SERVER_ERROR, Json(e.to_string()))),
+    };
+
+    if let Some(identity) = identity {
+        return match models::user::find_user_by_id(conn, &identity.user_id).await {
+            Ok(user) => Ok(user),
+            Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, Json(e))),
+        };
+    }
+
+    let email = match &claims.email {
+        Some(email) if claims.email_verified => email.trim().to_lowercase(),
+        _ => {
+            return Err((
+                StatusCode::FORBIDDEN,
+                Json(ApiError::EmailNotVerified.to_string()),
+            ));
+        }
+    };
+
+    let user = match models::user::find_user_by_email(conn, &email).await {
+        // Only link accounts whose owner proved the address, otherwise whoever registered it
+        // first with a password would share the account with the provider identity.
+        Ok(user) if user.email_verified => user,
+        Ok(_) => {
+            return Err((
+                StatusCode::CONFLICT,
+                Json(ApiError::OAuthAccountConflict.to_string()),
+            ));
+        }
+        Err(_) => create_oidc_user(conn, provider, claims, &email).await?,
+    };
+
+    let identity = OAuthIdentity {
+        id: Uuid::new_v4(),
+        user_id: user.id,
+        provider: provider.name.clone(),
+        subject: claims.sub.clone(),
+        create_date: chrono::Utc::now().naive_utc(),
+    };
+    if let Err(e) = models::oauth::create_oauth_identity(conn, &identity).await {
+        return Err((StatusCode::INTERNAL_SERVER_ERROR, Json(e.to_string())));
+    }
+
+    Ok(user)
+}
+
+async fn create_oidc_user(
+    conn: &mut AsyncPgConnection,
+    provider: &OidcProvider,
+    claims: &IdTokenClaims,
+    email: &str,
+) -> Result<User, (StatusCode, Json<String>)> {
+    let name = claims
+        .name
+        .as_deref()
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| email.split('@').next().unwrap_or_default());
+
+    let now = chrono::Utc::now().naive_utc();
+    let user = User {
+        id: Uuid::new_v4(),
+        public_id: random_public_id(),
+        name: name.chars().take(128).collect(),
+        email: email.to_string(),
+        document: None,
+        // Nobody knows this password; the user can set one through the password reset flow.
+        password: hash_password(&generate_opaque_token()).await?,
+        birthdate: None,
+        login_type: provider.name.clone(),
+        user_type: UserRole::Customer,
+        is_active: true,
+        create_date: now,
+        update_date: now,
+        deletion_date: None,
+        email_verified: true,
+        pending_email: None,
+    };
+
+    if let Err(e) = models::user::register_user(conn, &user).await {
+        return Err((StatusCode::INTERNAL_SERVER_ERROR, Json(e)));
+    }
+
+    Ok(user)
+}
+
+/// Starts a sign in with the provider. The client sends the user to `authorization_url`; the
+/// provider then redirects to the configured redirect URI with `code` and `state`, which the
+/// client posts to `/auth/oidc/{provider}/callback`.
+#[utoipa::path(
+    get,
+    path = "/auth/oidc/{provider}/authorize",
+    params(("provider" = String, Path, description = "Provider name, e.g. google")),
+    responses((status = OK, body = OidcAuthorization))
+)]
+pub async fn api_oidc_authorize(
+    State(pool): State<Pool<AsyncPgConnection>>,
+    Path(provider): Path<String>,
+) -> Result<(StatusCode, Json<OidcAuthorization>), (StatusCode, Json<String>)> {
+    let provider = find_provider(&provider)?;
+    let metadata = provider_metadata(provider).await?;
+
+    let state = generate_opaque_token();
+    let nonce = generate_opaque_token();
+    let code_verifier = generate_opaque_token();
+
+    let authorization_url = reqwest::Url::parse_with_params(
+        &metadata.authorization_endpoint,
+        &[
+            ("response_type", "code"),
+            ("client_id", provider.client_id.as_str()),
+            ("redirect_uri", provider.redirect_uri.as_str()),
+            ("scope", provider.scopes.as_str()),
+            ("state", state.as_str()),
+            ("nonce", nonce.as_str()),
+            ("code_challenge", pkce_challenge(&code_verifier).as_str()),
+            ("code_challenge_method", "S256"),
+        ],
+    )
+    .map_err(provider_error)?;
+
+    let conn = &mut get_conn(&pool).await?;
+
+    if let Err(e) = models::oauth::delete_expired_oidc_auth_requests(conn).await {
+        return Err((StatusCode::INTERNAL_SERVER_ERROR, Json(e.to_string())));
+    }
+
+    let now = chrono::Utc::now().naive_utc();
+    let request = OidcAuthRequest {
+        state_hash: hash_token(&state),
+        provider: provider.name.clone(),
+        code_verifier,
+        nonce,
+        create_date: now,
+        expiration_date: now + chrono::Duration::minutes(OIDC_AUTH_REQUEST_EXPIRATION_MINUTES),
+    };
+    if let Err(e) = models::oauth::create_oidc_auth_request(conn, &request).await {
+        return Err((StatusCode::INTERNAL_SERVER_ERROR, Json(e.to_string())));
+    }
+
+    Ok((
+        StatusCode::OK,
+        Json(OidcAuthorization {
+            authorization_url: authorization_url.to_string(),
+        }),
+    ))
+}
+
+/// Completes the sign in, answering like `/user/login`.
+#[utoipa::path(
+    post,
+    path = "/auth/oidc/{provider}/callback",
+    params(("provider" = String, Path, description = "Provider name, e.g. google")),
+    request_body = OidcCallbackInput,
+    responses((status = OK, body = LoginResponse))
+)]
+pub async fn api_oidc_callback(
+    State(pool): State<Pool<AsyncPgConnection>>,
+    Path(provider): Path<String>,
+    client: ClientInfo,
+    input: Json<OidcCallbackInput>,
+) -> Result<(StatusCode, Json<LoginResponse>), (StatusCode, Json<String>)> {
+    let input = input.0;
+    if input.code.trim().is_empty() || input.state.trim().is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ApiError::InvalidData.to_string()),
+        ));
+    }
+
+    let provider = find_provider(&provider)?;
+    let conn = &mut get_conn(&pool).await?;
+
+    let invalid_state = || {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(ApiError::InvalidOidcState.to_string()),
+        )
+    };
+
+    let request =
+        match models::oauth::take_oidc_auth_request(conn, &hash_token(input.state.trim())).await {
+            Ok(Some(request)) => request,
+            Ok(None) => return Err(invalid_state()),
+            Err(e) => return Err((StatusCode::INTERNAL_SERVER_ERROR, Json(e.to_string()))),
+        };
+
+    if request.provider != provider.name || request.expiration_date < chrono::Utc::now().naive_utc()
+    {
+        return Err(invalid_state());
+    }
+
+    let metadata = provider_metadata(provider).await?;
+    let id_token = exchange_code(
+        provider,
+        &metadata,
+        input.code.trim(),
+        &request.code_verifier,
+    )
+    .await?;
+    let claims = verify_id_token(provider, &metadata, &id_token, &request.nonce).await?;
+
+    let user = find_or_create_user(conn, provider, &claims).await?;
+
+    if !user.is_active || user.deletion_date.is_some() {
+        return Err((
+            StatusCode::FORBIDDEN,
+            Json(ApiError::NotActiveUser.to_string()),
+        ));
+    }
+
+    let response = finish_login(conn, &user, &provider.name, &client).await?;
+    Ok((StatusCode::OK, Json(response)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn jwks() -> JwkSet {
+        serde_json::from_value(serde_json::json!({
+            "keys": [{
+                "kty": "EC",
+                "crv": "P-256",
+                "kid": "current",
+                "x": "MKBCTNIcKUSDii11ySs3526iDZ8AiTo7Tu6KPAqv7D4",
+                "y": "4Etl6SRW2YiLUrN5vfvVHuhp7x8PxltmWWlbbM4IFyM",
+            }]
+        }))
+        .unwrap()
+    }
+
+    fn fetched_ago(seconds: u64) -> Instant {
+        Instant::now()
+            .checked_sub(Duration::from_secs(seconds))
+            .unwrap()
+    }
+
+    #[test]
+    fn cached_keys_are_used_until_they_expire() {
+        let cached = (jwks(), fetched_ago(0));
+        assert!(cached_keys(Some(&cached), Some("current")).is_some());
+        assert!(cached_keys(Some(&cached), None).is_some());
+
+        let expired = (jwks(), fetched_ago(JWKS_CACHE_TTL.as_secs()));
+        assert!(cached_keys(Some(&expired), Some("current")).is_none());
+        assert!(cached_keys(None, Some("current")).is_none());
+    }
+
+    #[test]
+    fn unknown_kid_refetches_at_most_once_per_interval() {
+        let recent = (jwks(), fetched_ago(0));
+        assert!(cached_keys(Some(&recent), Some("rotated")).is_some());
+
+        let older = (jwks(), fetched_ago(JWKS_REFETCH_INTERVAL.as_secs()));
+        assert!(cached_keys(Some(&older), Some("rotated")).is_none());
+        assert!(cached_keys(Some(&older), Some("current")).is_some());
+    }
+}
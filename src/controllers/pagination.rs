@@ -0,0 +1,234 @@
+use axum::{
+    Json,
+    extract::{FromRequestParts, Query},
+    http::request::Parts,
+};
+use chrono::{NaiveDate, NaiveDateTime};
+use hyper::StatusCode;
+use serde::de::DeserializeOwned;
+
+use crate::models::{
+    error::ApiError,
+    pagination::{CursorParams, OffsetParams, Sort, SortField, SortParams, decode_cursor},
+};
+
+pub const DEFAULT_PAGE_SIZE: i64 = 20;
+pub const MAX_PAGE_SIZE: i64 = 100;
+
+fn invalid_query(message: impl Into<String>) -> (StatusCode, Json<String>) {
+    (
+        StatusCode::BAD_REQUEST,
+        Json(ApiError::InvalidQuery(message.into()).to_string()),
+    )
+}
+
+/// Parses the query string into `T`, answering errors in the same format as the rest of the API.
+fn parse_query<T: DeserializeOwned>(parts: &Parts) -> Result<T, (StatusCode, Json<String>)> {
+    match Query::<T>::try_from_uri(&parts.uri) {
+        Ok(Query(value)) => Ok(value),
+        Err(e) => Err(invalid_query(e.body_text())),
+    }
+}
+
+fn page_size(per_page: Option<i64>) -> Result<i64, (StatusCode, Json<String>)> {
+    let per_page = per_page.unwrap_or(DEFAULT_PAGE_SIZE);
+    if !(1..=MAX_PAGE_SIZE).contains(&per_page) {
+        return Err(invalid_query(format!(
+            "per_page must be between 1 and {}",
+            MAX_PAGE_SIZE
+        )));
+    }
+    Ok(per_page)
+}
+
+/// `?page=&per_page=`, for lists that show page numbers and a total.
+pub struct OffsetPagination {
+    pub page: i64,
+    pub per_page: i64,
+}
+
+impl OffsetPagination {
+    pub fn limit(&self) -> i64 {
+        self.per_page
+    }
+
+    pub fn offset(&self) -> i64 {
+        (self.page - 1) * self.per_page
+    }
+}
+
+impl<S> FromRequestParts<S> for OffsetPagination
+where
+    S: Send + Sync,
+{
+    type Rejection = (StatusCode, Json<String>);
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let params: OffsetParams = parse_query(parts)?;
+
+        let page = params.page.unwrap_or(1);
+        if page < 1 {
+            return Err(invalid_query("page must be 1 or more"));
+        }
+
+        let per_page = page_size(params.per_page)?;
+        if (page - 1).checked_mul(per_page).is_none() {
+            return Err(invalid_query("page is too large"));
+        }
+
+        Ok(Self { page, per_page })
+    }
+}
+
+/// `?cursor=&per_page=`, for lists that are walked through with `next_cursor`. `K` is the sort
+/// key of the model, and `after` the key of the last row of the previous page.
+pub struct CursorPagination<K> {
+    pub after: Option<K>,
+    pub per_page: i64,
+}
+
+impl<K> CursorPagination<K> {
+    /// Rows to load; the extra one tells [`Page::cursor`](crate::models::pagination::Page::cursor)
+    /// whether there is a next page.
+    pub fn limit(&self) -> i64 {
+        self.per_page + 1
+    }
+}
+
+impl<S, K> FromRequestParts<S> for CursorPagination<K>
+where
+    S: Send + Sync,
+    K: DeserializeOwned,
+{
+    type Rejection = (StatusCode, Json<String>);
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let params: CursorParams = parse_query(parts)?;
+
+        let after = match params.cursor {
+            Some(cursor) => match decode_cursor(&cursor) {
+                Some(key) => Some(key),
+                None => {
+                    return Err((
+                        StatusCode::BAD_REQUEST,
+                        Json(ApiError::InvalidCursor.to_string()),
+                    ));
+                }
+            },
+            None => None,
+        };
+
+        Ok(Self {
+            after,
+            per_page: page_size(params.per_page)?,
+        })
+    }
+}
+
+/// `?sort=field` or `?sort=-field`, limited to the fields of `F`.
+impl<S, F> FromRequestParts<S> for Sort<F>
+where
+    S: Send + Sync,
+    F: SortField,
+{
+    type Rejection = (StatusCode, Json<String>);
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let params: SortParams = parse_query(parts)?;
+
+        let Some(value) = params.sort else {
+            return Ok(F::DEFAULT);
+        };
+
+        match Sort::parse(&value) {
+            Some(sort) => Ok(sort),
+            None => Err((
+                StatusCode::BAD_REQUEST,
+                Json(ApiError::InvalidSortField(value, Sort::<F>::field_names()).to_string()),
+            )),
+        }
+    }
+}
+
+/// Typed filters read from the query string. Unlike [`Query`], values that don't parse are
+/// answered with a 400 in the API's error format.
+pub struct Filters<T>(pub T);
+
+impl<S, T> FromRequestParts<S> for Filters<T>
+where
+    S: Send + Sync,
+    T: DeserializeOwned,
+{
+    type Rejection = (StatusCode, Json<String>);
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(Self(parse_query(parts)?))
+    }
+}
+
+/// `[start, end)` bounds for a timestamp column; either side can be open.
+pub struct DateRange {
+    pub start: Option<NaiveDateTime>,
+    pub end: Option<NaiveDateTime>,
+}
+
+/// Turns an inclusive range of days into a [`DateRange`].
+pub fn date_range(
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+) -> Result<DateRange, (StatusCode, Json<String>)> {
+    if let (Some(from), Some(to)) = (from, to)
+        && from > to
+    {
+        return Err(invalid_query("the start date is after the end date"));
+    }
+
+    let start = from.and_then(|date| date.and_hms_opt(0, 0, 0));
+    let end = to
+        .and_then(|date| date.succ_opt())
+        .and_then(|date| date.and_hms_opt(0, 0, 0));
+    Ok(DateRange { start, end })
+}
+
+/// Escapes the `ILIKE` wildcards in a search term and matches it anywhere. Blank terms give `None`.
+pub fn search_pattern(term: Option<String>) -> Option<String> {
+    let term = term?;
+    let term = term.trim();
+    if term.is_empty() {
+        return None;
+    }
+
+    let escaped = term
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    Some(format!("%{}%", escaped))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::Request;
+
+    use super::*;
+
+    async fn offset_pagination(uri: &str) -> Result<OffsetPagination, (StatusCode, Json<String>)> {
+        let (mut parts, _) = Request::builder().uri(uri).body(()).unwrap().into_parts();
+        OffsetPagination::from_request_parts(&mut parts, &()).await
+    }
+
+    #[tokio::test]
+    async fn offset_starts_at_the_first_row_of_the_page() {
+        let pagination = offset_pagination("/users?page=3&per_page=10")
+            .await
+            .unwrap();
+        assert_eq!(pagination.limit(), 10);
+        assert_eq!(pagination.offset(), 20);
+    }
+
+    #[tokio::test]
+    async fn rejects_a_page_whose_offset_overflows() {
+        let uri = format!("/users?page={}&per_page={}", i64::MAX, MAX_PAGE_SIZE);
+        let (status, _) = offset_pagination(&uri).await.err().unwrap();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+}
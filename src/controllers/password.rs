@@ -0,0 +1,154 @@
+use std::{collections::HashSet, env, sync::LazyLock};
+
+use axum::Json;
+use dotenvy::dotenv;
+use hyper::StatusCode;
+
+use crate::models::error::ApiError;
+
+static COMMON_PASSWORDS: LazyLock<HashSet<&'static str>> = LazyLock::new(|| {
+    include_str!("../../data/common_passwords.txt")
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .collect()
+});
+
+static PASSWORD_POLICY: LazyLock<PasswordPolicy> = LazyLock::new(PasswordPolicy::from_env);
+
+/// Personal data a password must not contain.
+pub struct PasswordContext<'a> {
+    pub email: &'a str,
+    pub name: &'a str,
+    pub document: &'a str,
+}
+
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    pub max_length: usize,
+    pub require_lowercase: bool,
+    pub require_uppercase: bool,
+    pub require_digit: bool,
+    pub require_symbol: bool,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self {
+            min_length: 8,
+            max_length: 128,
+            require_lowercase: true,
+            require_uppercase: true,
+            require_digit: true,
+            require_symbol: false,
+        }
+    }
+}
+
+impl PasswordPolicy {
+    /// Reads `PASSWORD_MIN_LENGTH`, `PASSWORD_MAX_LENGTH` and `PASSWORD_REQUIRE_LOWERCASE`,
+    /// `_UPPERCASE`, `_DIGIT` and `_SYMBOL`, falling back to the defaults for unset values.
+    pub fn from_env() -> Self {
+        dotenv().ok();
+
+        let default = Self::default();
+        let number = |var: &str, default: usize| {
+            env::var(var)
+                .ok()
+                .and_then(|value| value.trim().parse::<usize>().ok())
+                .unwrap_or(default)
+        };
+        let flag = |var: &str, default: bool| {
+            env::var(var)
+                .ok()
+                .and_then(|value| value.trim().parse::<bool>().ok())
+                .unwrap_or(default)
+        };
+
+        Self {
+            min_length: number("PASSWORD_MIN_LENGTH", default.min_length),
+            max_length: number("PASSWORD_MAX_LENGTH", default.max_length),
+            require_lowercase: flag("PASSWORD_REQUIRE_LOWERCASE", default.require_lowercase),
+            require_uppercase: flag("PASSWORD_REQUIRE_UPPERCASE", default.require_uppercase),
+            require_digit: flag("PASSWORD_REQUIRE_DIGIT", default.require_digit),
+            require_symbol: flag("PASSWORD_REQUIRE_SYMBOL", default.require_symbol),
+        }
+    }
+
+    /// Returns the identifiers of every rule the password breaks.
+    pub fn failed_rules(&self, password: &str, context: &PasswordContext) -> Vec<String> {
+        let mut errors = vec![];
+        let length = password.chars().count();
+
+        if length < self.min_length {
+            errors.push("min_length".to_string());
+        }
+        if length > self.max_length {
+            errors.push("max_length".to_string());
+        }
+        if self.require_lowercase && !password.chars().any(|c| c.is_lowercase()) {
+            errors.push("lowercase".to_string());
+        }
+        if self.require_uppercase && !password.chars().any(|c| c.is_uppercase()) {
+            errors.push("uppercase".to_string());
+        }
+        if self.require_digit && !password.chars().any(|c| c.is_ascii_digit()) {
+            errors.push("digit".to_string());
+        }
+        if self.require_symbol && password.chars().all(|c| c.is_alphanumeric()) {
+            errors.push("symbol".to_string());
+        }
+        if contains_personal_data(password, context) {
+            errors.push("personal_data".to_string());
+        }
+        if COMMON_PASSWORDS.contains(password.to_lowercase().as_str()) {
+            errors.push("common_password".to_string());
+        }
+
+        errors
+    }
+}
+
+fn contains_personal_data(password: &str, context: &PasswordContext) -> bool {
+    let password = password.to_lowercase();
+
+    let email = context.email.trim().to_lowercase();
+    let local_part = email.split('@').next().unwrap_or_default();
+
+    let mut fragments: Vec<String> = context
+        .name
+        .split_whitespace()
+        .map(|part| part.to_lowercase())
+        .collect();
+    fragments.push(local_part.to_string());
+
+    let document: String = context
+        .document
+        .chars()
+        .filter(|c| c.is_ascii_digit())
+        .collect();
+    if !document.is_empty() && password.contains(&document) {
+        return true;
+    }
+
+    fragments
+        .iter()
+        .filter(|fragment| fragment.chars().count() >= 3)
+        .any(|fragment| password.contains(fragment.as_str()))
+}
+
+pub fn validate_password(
+    password: &str,
+    context: &PasswordContext,
+) -> Result<(), (StatusCode, Json<String>)> {
+    let errors = PASSWORD_POLICY.failed_rules(password, context);
+
+    if errors.is_empty() {
+        return Ok(());
+    }
+
+    Err((
+        StatusCode::BAD_REQUEST,
+        Json(ApiError::PasswordPolicy(errors).to_string()),
+    ))
+}
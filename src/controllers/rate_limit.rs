@@ -0,0 +1,419 @@
+use std::{
+    collections::HashMap,
+    env,
+    net::SocketAddr,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use axum::{
+    Json,
+    body::Body,
+    extract::{ConnectInfo, Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chrono::NaiveDateTime;
+use diesel_async::{AsyncPgConnection, pooled_connection::deadpool::Pool};
+use dotenvy::dotenv;
+use hyper::{HeaderMap, StatusCode, header::RETRY_AFTER};
+
+use crate::models::{self, error::ApiError, jwt::Claims, rate_limit::RateLimitBucket};
+
+/// Memory buckets are swept once the map grows past this size.
+const MEMORY_SWEEP_THRESHOLD: usize = 10_000;
+/// Minimum time between two memory sweeps, so a flood of new keys can't make every request scan
+/// the whole map.
+const MEMORY_SWEEP_INTERVAL_SECONDS: i64 = 60;
+/// Idle Postgres buckets are deleted once every this many requests.
+const POSTGRES_SWEEP_INTERVAL: u64 = 1_000;
+
+/// Allows bursts of `capacity` requests, refilled at `capacity` per `period_seconds`.
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimitPolicy {
+    pub capacity: u32,
+    pub period_seconds: u32,
+}
+
+impl RateLimitPolicy {
+    /// Parses `<requests>/<seconds>`, e.g. `5/60`.
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let invalid = || format!("Invalid rate limit policy: {}", value);
+
+        let (capacity, period) = value.trim().split_once('/').ok_or_else(invalid)?;
+        let capacity = capacity.trim().parse::<u32>().map_err(|_| invalid())?;
+        let period_seconds = period.trim().parse::<u32>().map_err(|_| invalid())?;
+        if capacity == 0 || period_seconds == 0 {
+            return Err(invalid());
+        }
+
+        Ok(Self {
+            capacity,
+            period_seconds,
+        })
+    }
+
+    fn refill_per_second(&self) -> f64 {
+        self.capacity as f64 / self.period_seconds as f64
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct TokenBucket {
+    tokens: f64,
+    update_date: NaiveDateTime,
+}
+
+pub struct RateLimitDecision {
+    pub allowed: bool,
+    pub limit: u32,
+    pub remaining: u32,
+    /// Seconds until the bucket is full again.
+    pub reset: u64,
+    /// Seconds until the next request would be allowed.
+    pub retry_after: u64,
+}
+
+impl TokenBucket {
+    fn full(policy: &RateLimitPolicy, now: NaiveDateTime) -> Self {
+        Self {
+            tokens: policy.capacity as f64,
+            update_date: now,
+        }
+    }
+
+    /// Refills the bucket for the time elapsed since its last update and spends a token if one
+    /// is available.
+    fn take(&mut self, policy: &RateLimitPolicy, now: NaiveDateTime) -> RateLimitDecision {
+        let elapsed = (now - self.update_date).num_milliseconds().max(0) as f64 / 1000.0;
+        let rate = policy.refill_per_second();
+
+        self.tokens = (self.tokens + elapsed * rate).min(policy.capacity as f64);
+        self.update_date = now;
+
+        let allowed = self.tokens >= 1.0;
+        if allowed {
+            self.tokens -= 1.0;
+        }
+
+        RateLimitDecision {
+            allowed,
+            limit: policy.capacity,
+            remaining: self.tokens.floor() as u32,
+            reset: ((policy.capacity as f64 - self.tokens) / rate).ceil() as u64,
+            retry_after: if allowed {
+                0
+            } else {
+                ((1.0 - self.tokens) / rate).ceil() as u64
+            },
+        }
+    }
+}
+
+#[derive(Default)]
+struct MemoryBuckets {
+    buckets: HashMap<String, TokenBucket>,
+    last_sweep: Option<NaiveDateTime>,
+}
+
+impl MemoryBuckets {
+    /// Drops the buckets idle since `idle_before`, which are full again, when the map is past
+    /// [`MEMORY_SWEEP_THRESHOLD`] and the last sweep is older than the interval.
+    fn sweep(&mut self, now: NaiveDateTime, idle_before: NaiveDateTime) {
+        if self.buckets.len() <= MEMORY_SWEEP_THRESHOLD
+            || self.last_sweep.is_some_and(|last_sweep| {
+                now - last_sweep < chrono::Duration::seconds(MEMORY_SWEEP_INTERVAL_SECONDS)
+            })
+        {
+            return;
+        }
+
+        self.buckets
+            .retain(|_, bucket| bucket.update_date > idle_before);
+        self.last_sweep = Some(now);
+    }
+}
+
+enum RateLimitBackend {
+    Memory(Mutex<MemoryBuckets>),
+    /// Shares the buckets between instances through the `rate_limit_buckets` table.
+    Postgres(Pool<AsyncPgConnection>),
+}
+
+/// Endpoints that check a password, a second factor or a single-use token.
+pub const CREDENTIAL_PATHS: &[&str] = &[
+    "/api/user/login",
+    "/api/user/login/mfa",
+    "/api/user/webauthn/login",
+    "/api/user/restore",
+    "/api/user/password",
+    "/api/user/mfa/totp/disable",
+    "/api/user/reset-password",
+    "/api/user/magic-link/consume",
+    "/api/user/verify-email",
+    "/api/user/confirm-email",
+];
+
+/// Endpoints that send an e-mail, limited so they can't be used to flood an inbox.
+pub const EMAIL_PATHS: &[&str] = &[
+    "/api/user/magic-link",
+    "/api/user/forgot-password",
+    "/api/user/resend-verification",
+];
+
+/// Profile updates that change the e-mail, which send a confirmation to the new address. Other
+/// profile edits only fall under the default policy, so the handler checks this one itself.
+pub const EMAIL_CHANGE_ROUTE: &str = "/api/user/update#email";
+
+pub struct RateLimiter {
+    backend: RateLimitBackend,
+    /// Exact request paths with their own policy, e.g. the login endpoint.
+    routes: Vec<(String, RateLimitPolicy)>,
+    default: RateLimitPolicy,
+    requests: AtomicU64,
+}
+
+impl RateLimiter {
+    /// Builds the limiter from the environment.
+    ///
+    /// - `RATE_LIMIT_BACKEND`: `memory` (default) or `postgres` for deployments with several
+    ///   instances.
+    /// - `RATE_LIMIT_DEFAULT`, `RATE_LIMIT_LOGIN`, `RATE_LIMIT_REGISTER` and `RATE_LIMIT_EMAIL`:
+    ///   policies as `<requests>/<seconds>`, defaulting to `120/60`, `5/60`, `3/600` and `3/600`.
+    ///   The login policy covers [`CREDENTIAL_PATHS`] and the e-mail one [`EMAIL_PATHS`] and
+    ///   [`EMAIL_CHANGE_ROUTE`], each with its own buckets. `RATE_LIMIT_MAGIC_LINK`, its former
+    ///   name, is still read.
+    pub fn from_env(pool: Pool<AsyncPgConnection>) -> Result<Self, String> {
+        dotenv().ok();
+
+        let policy = |var: &str, default: &str| {
+            RateLimitPolicy::parse(&env::var(var).unwrap_or_else(|_| default.to_string()))
+        };
+
+        let backend = match env::var("RATE_LIMIT_BACKEND")
+            .unwrap_or_else(|_| "memory".to_string())
+            .as_str()
+        {
+            "memory" => RateLimitBackend::Memory(Mutex::new(MemoryBuckets::default())),
+            "postgres" => RateLimitBackend::Postgres(pool),
+            other => return Err(format!("Unknown RATE_LIMIT_BACKEND: {}", other)),
+        };
+
+        let login = policy("RATE_LIMIT_LOGIN", "5/60")?;
+        let email = match env::var("RATE_LIMIT_EMAIL") {
+            Ok(value) => RateLimitPolicy::parse(&value)?,
+            Err(_) => policy("RATE_LIMIT_MAGIC_LINK", "3/600")?,
+        };
+
+        let mut routes = vec![(
+            "/api/user/register".to_string(),
+            policy("RATE_LIMIT_REGISTER", "3/600")?,
+        )];
+        routes.extend(
+            CREDENTIAL_PATHS
+                .iter()
+                .map(|path| (path.to_string(), login)),
+        );
+        routes.extend(EMAIL_PATHS.iter().map(|path| (path.to_string(), email)));
+        routes.push((EMAIL_CHANGE_ROUTE.to_string(), email));
+
+        Ok(Self {
+            backend,
+            routes,
+            default: policy("RATE_LIMIT_DEFAULT", "120/60")?,
+            requests: AtomicU64::new(0),
+        })
+    }
+
+    /// Returns the bucket name prefix and the policy that apply to the path.
+    fn policy_for(&self, path: &str) -> (&str, RateLimitPolicy) {
+        match self.routes.iter().find(|(route, _)| route == path) {
+            Some((route, policy)) => (route.as_str(), *policy),
+            None => ("default", self.default),
+        }
+    }
+
+    /// Longest refill period among the policies; buckets idle for longer are full again.
+    fn max_period_seconds(&self) -> i64 {
+        self.routes
+            .iter()
+            .map(|(_, policy)| policy.period_seconds)
+            .chain([self.default.period_seconds])
+            .max()
+            .unwrap_or_default() as i64
+    }
+
+    pub async fn check(&self, path: &str, subject: &str) -> RateLimitDecision {
+        let (name, policy) = self.policy_for(path);
+        let key = format!("{}:{}", name, subject);
+        let now = chrono::Utc::now().naive_utc();
+
+        match &self.backend {
+            RateLimitBackend::Memory(buckets) => {
+                let mut buckets = buckets.lock().unwrap_or_else(|e| e.into_inner());
+
+                let idle_before = now - chrono::Duration::seconds(self.max_period_seconds());
+                buckets.sweep(now, idle_before);
+
+                buckets
+                    .buckets
+                    .entry(key)
+                    .or_insert_with(|| TokenBucket::full(&policy, now))
+                    .take(&policy, now)
+            }
+            RateLimitBackend::Postgres(pool) => {
+                match self.check_postgres(pool, &key, &policy, now).await {
+                    Ok(decision) => decision,
+                    Err(e) => {
+                        // Fail open: an unreachable database shouldn't take the whole API down.
+                        tracing::error!("Rate limit check failed: {}", e);
+                        TokenBucket::full(&policy, now).take(&policy, now)
+                    }
+                }
+            }
+        }
+    }
+
+    async fn check_postgres(
+        &self,
+        pool: &Pool<AsyncPgConnection>,
+        key: &str,
+        policy: &RateLimitPolicy,
+        now: NaiveDateTime,
+    ) -> Result<RateLimitDecision, String> {
+        let conn = &mut pool.get().await.map_err(|e| e.to_string())?;
+
+        if self
+            .requests
+            .fetch_add(1, Ordering::Relaxed)
+            .is_multiple_of(POSTGRES_SWEEP_INTERVAL)
+        {
+            let idle_before = now - chrono::Duration::seconds(self.max_period_seconds());
+            if let Err(e) =
+                models::rate_limit::delete_idle_rate_limit_buckets(conn, idle_before).await
+            {
+                tracing::error!("Failed to delete idle rate limit buckets: {}", e);
+            }
+        }
+
+        let policy = *policy;
+        models::rate_limit::update_rate_limit_bucket(conn, key, move |current| {
+            let mut bucket = match current {
+                Some(current) => TokenBucket {
+                    tokens: current.tokens,
+                    update_date: current.update_date,
+                },
+                None => TokenBucket::full(&policy, now),
+            };
+            let decision = bucket.take(&policy, now);
+
+            let row = RateLimitBucket {
+                key: key.to_string(),
+                tokens: bucket.tokens,
+                update_date: bucket.update_date,
+            };
+            (row, decision)
+        })
+        .await
+        .map_err(|e| e.to_string())
+    }
+}
+
+fn rate_limit_headers(headers: &mut HeaderMap, decision: &RateLimitDecision) {
+    headers.insert("x-ratelimit-limit", decision.limit.into());
+    headers.insert("x-ratelimit-remaining", decision.remaining.into());
+    headers.insert("x-ratelimit-reset", decision.reset.into());
+}
+
+/// The limiter and the caller's bucket subject, left in the request extensions by [`rate_limit`]
+/// for handlers whose limit depends on the request body.
+#[derive(Clone)]
+pub struct CallerRateLimit {
+    limiter: Arc<RateLimiter>,
+    subject: String,
+}
+
+impl CallerRateLimit {
+    /// Spends a request from the caller's bucket for `route`, answering `429` once it's empty.
+    pub async fn check(&self, route: &str) -> Result<(), (StatusCode, Json<String>)> {
+        if self.limiter.check(route, &self.subject).await.allowed {
+            return Ok(());
+        }
+
+        Err((
+            StatusCode::TOO_MANY_REQUESTS,
+            Json(ApiError::RateLimited.to_string()),
+        ))
+    }
+}
+
+/// Token-bucket rate limiting, keyed by user id for authenticated requests and by client IP
+/// otherwise. The caller is read from the `Claims` that `identify_caller` stored, so it must be
+/// layered inside it: `.layer(middleware::from_fn_with_state(limiter, rate_limit))`.
+pub async fn rate_limit(
+    State(limiter): State<Arc<RateLimiter>>,
+    mut req: Request<Body>,
+    next: Next,
+) -> Response {
+    let subject = match req.extensions().get::<Claims>() {
+        Some(claims) => format!("user:{}", claims.id),
+        None => match req.extensions().get::<ConnectInfo<SocketAddr>>() {
+            Some(ConnectInfo(addr)) => format!("ip:{}", addr.ip()),
+            None => "ip:unknown".to_string(),
+        },
+    };
+
+    let decision = limiter.check(req.uri().path(), &subject).await;
+
+    if !decision.allowed {
+        let mut response = (
+            StatusCode::TOO_MANY_REQUESTS,
+            Json(ApiError::RateLimited.to_string()),
+        )
+            .into_response();
+        rate_limit_headers(response.headers_mut(), &decision);
+        response
+            .headers_mut()
+            .insert(RETRY_AFTER, decision.retry_after.into());
+        return response;
+    }
+
+    req.extensions_mut()
+        .insert(CallerRateLimit { limiter, subject });
+
+    let mut response = next.run(req).await;
+    rate_limit_headers(response.headers_mut(), &decision);
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn memory_buckets_are_swept_at_most_once_per_interval() {
+        let now = chrono::Utc::now().naive_utc();
+        let idle_before = now - chrono::Duration::seconds(60);
+        let policy = RateLimitPolicy::parse("5/60").unwrap();
+        let idle = TokenBucket::full(&policy, idle_before - chrono::Duration::seconds(1));
+
+        let mut memory = MemoryBuckets::default();
+        for i in 0..=MEMORY_SWEEP_THRESHOLD {
+            memory.buckets.insert(format!("default:ip:{}", i), idle);
+        }
+        memory.sweep(now, idle_before);
+        assert!(memory.buckets.is_empty());
+
+        for i in 0..=MEMORY_SWEEP_THRESHOLD {
+            memory.buckets.insert(format!("default:ip:{}", i), idle);
+        }
+        memory.sweep(now + chrono::Duration::seconds(1), idle_before);
+        assert_eq!(memory.buckets.len(), MEMORY_SWEEP_THRESHOLD + 1);
+
+        let later = now + chrono::Duration::seconds(MEMORY_SWEEP_INTERVAL_SECONDS);
+        memory.sweep(later, idle_before);
+        assert!(memory.buckets.is_empty());
+    }
+}
@@ -0,0 +1,281 @@
+use std::{
+    collections::HashMap,
+    sync::{LazyLock, RwLock},
+    time::{Duration, Instant},
+};
+
+use axum::Json;
+use chrono::{DateTime, NaiveDateTime};
+use diesel_async::{AsyncPgConnection, pooled_connection::deadpool::Pool};
+use hyper::StatusCode;
+use uuid::Uuid;
+
+use crate::{
+    controllers::{jwt::ACCESS_TOKEN_EXPIRATION_SECONDS, utils::get_conn},
+    models::{
+        self,
+        jwt::Claims,
+        revocation::{RevokedToken, UserTokenRevocation},
+    },
+};
+
+/// How long the in-memory copy of the revocation list is trusted before it's reloaded from the
+/// database, so revocations made by other instances are picked up.
+const REVOCATION_CACHE_TTL: Duration = Duration::from_secs(30);
+
+#[derive(Default)]
+struct RevocationCache {
+    tokens: HashMap<Uuid, NaiveDateTime>,
+    users: HashMap<Uuid, NaiveDateTime>,
+    sessions: HashMap<Uuid, NaiveDateTime>,
+    last_sync: Option<Instant>,
+}
+
+impl RevocationCache {
+    fn is_stale(&self) -> bool {
+        match self.last_sync {
+            Some(last_sync) => last_sync.elapsed() > REVOCATION_CACHE_TTL,
+            None => true,
+        }
+    }
+
+    fn is_revoked(&self, claims: &Claims) -> bool {
+        if self.tokens.contains_key(&claims.jti) {
+            return true;
+        }
+        if let Some(sid) = claims.sid
+            && self.sessions.contains_key(&sid)
+        {
+            return true;
+        }
+        let Some(revoked_before) = self.users.get(&claims.id) else {
+            return false;
+        };
+        match claims.iat_micros {
+            Some(iat_micros) => iat_micros < revoked_before.and_utc().timestamp_micros(),
+            // Only tokens issued before `iat_micros` existed lack it, so all of them predate a
+            // revocation made in the same second.
+            None => (claims.iat as i64) <= revoked_before.and_utc().timestamp(),
+        }
+    }
+}
+
+static REVOCATION_CACHE: LazyLock<RwLock<RevocationCache>> =
+    LazyLock::new(|| RwLock::new(RevocationCache::default()));
+
+async fn sync_revocation_cache(
+    conn: &mut AsyncPgConnection,
+) -> Result<(), (StatusCode, Json<String>)> {
+    let _ = models::revocation::delete_expired_revoked_tokens(conn).await;
+
+    let tokens = match models::revocation::list_active_revoked_tokens(conn).await {
+        Ok(tokens) => tokens,
+        Err(e) => return Err((StatusCode::INTERNAL_SERVER_ERROR, Json(e.to_string()))),
+    };
+    let users = match models::revocation::list_user_token_revocations(conn).await {
+        Ok(users) => users,
+        Err(e) => return Err((StatusCode::INTERNAL_SERVER_ERROR, Json(e.to_string()))),
+    };
+    // Older revocations don't matter, every access token of those sessions has expired by now.
+    let since =
+        chrono::Utc::now().naive_utc() - chrono::Duration::seconds(ACCESS_TOKEN_EXPIRATION_SECONDS);
+    let sessions = match models::session::list_revoked_session_ids(conn, since).await {
+        Ok(sessions) => sessions,
+        Err(e) => return Err((StatusCode::INTERNAL_SERVER_ERROR, Json(e.to_string()))),
+    };
+
+    let mut cache = REVOCATION_CACHE.write().unwrap();
+    cache.tokens = tokens
+        .into_iter()
+        .map(|token| (token.jti, token.expiration_date))
+        .collect();
+    cache.users = users
+        .into_iter()
+        .map(|user| (user.user_id, user.revoked_before))
+        .collect();
+    cache.sessions = sessions.into_iter().collect();
+    cache.last_sync = Some(Instant::now());
+
+    Ok(())
+}
+
+pub async fn is_token_revoked(
+    pool: &Pool<AsyncPgConnection>,
+    claims: &Claims,
+) -> Result<bool, (StatusCode, Json<String>)> {
+    let is_stale = REVOCATION_CACHE.read().unwrap().is_stale();
+    if is_stale {
+        let conn = &mut get_conn(pool).await?;
+        sync_revocation_cache(conn).await?;
+    }
+
+    Ok(REVOCATION_CACHE.read().unwrap().is_revoked(claims))
+}
+
+pub async fn revoke_token(
+    conn: &mut AsyncPgConnection,
+    claims: &Claims,
+) -> Result<(), (StatusCode, Json<String>)> {
+    let expiration_date = DateTime::from_timestamp(claims.exp as i64, 0)
+        .map(|date| date.naive_utc())
+        .unwrap_or_else(|| chrono::Utc::now().naive_utc());
+
+    let revoked_token = RevokedToken {
+        jti: claims.jti,
+        user_id: claims.id,
+        expiration_date,
+        revocation_date: chrono::Utc::now().naive_utc(),
+    };
+
+    if let Err(e) = models::revocation::revoke_token(conn, &revoked_token).await {
+        return Err((StatusCode::INTERNAL_SERVER_ERROR, Json(e.to_string())));
+    }
+
+    REVOCATION_CACHE
+        .write()
+        .unwrap()
+        .tokens
+        .insert(revoked_token.jti, revoked_token.expiration_date);
+
+    Ok(())
+}
+
+/// Revokes every access token issued to the user up to this moment.
+pub async fn revoke_all_user_tokens(
+    conn: &mut AsyncPgConnection,
+    user_id: &Uuid,
+) -> Result<(), (StatusCode, Json<String>)> {
+    let revocation = UserTokenRevocation {
+        user_id: *user_id,
+        revoked_before: chrono::Utc::now().naive_utc(),
+    };
+
+    if let Err(e) = models::revocation::revoke_user_tokens(conn, &revocation).await {
+        return Err((StatusCode::INTERNAL_SERVER_ERROR, Json(e.to_string())));
+    }
+
+    REVOCATION_CACHE
+        .write()
+        .unwrap()
+        .users
+        .insert(revocation.user_id, revocation.revoked_before);
+
+    Ok(())
+}
+
+/// Revokes the session, so it can't be refreshed and its access tokens stop being accepted.
+/// Returns `false` when it was already revoked.
+pub async fn revoke_session(
+    conn: &mut AsyncPgConnection,
+    session_id: &Uuid,
+) -> Result<bool, (StatusCode, Json<String>)> {
+    let revoked = match models::session::revoke_session(conn, session_id).await {
+        Ok(revoked) => revoked,
+        Err(e) => return Err((StatusCode::INTERNAL_SERVER_ERROR, Json(e.to_string()))),
+    };
+
+    REVOCATION_CACHE
+        .write()
+        .unwrap()
+        .sessions
+        .insert(*session_id, chrono::Utc::now().naive_utc());
+
+    Ok(revoked)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+
+    use super::*;
+    use crate::models::role::UserRole;
+
+    fn claims_issued_at(user_id: Uuid, issued_at: DateTime<Utc>) -> Claims {
+        let iat = issued_at.timestamp();
+        Claims {
+            id: user_id,
+            public_id: 1000000,
+            user_type: UserRole::Customer,
+            email: "user@example.com".to_string(),
+            exp: (iat + ACCESS_TOKEN_EXPIRATION_SECONDS) as usize,
+            iat: iat as usize,
+            iat_micros: Some(issued_at.timestamp_micros()),
+            jti: Uuid::new_v4(),
+            sid: None,
+            api_key_id: None,
+            scopes: Vec::new(),
+            act: None,
+            impersonated: false,
+        }
+    }
+
+    #[test]
+    fn token_issued_right_after_a_user_revocation_is_accepted() {
+        let user_id = Uuid::new_v4();
+        let mut cache = RevocationCache::default();
+
+        let revoked_before = Utc::now();
+        cache.users.insert(user_id, revoked_before.naive_utc());
+        let claims = claims_issued_at(user_id, Utc::now());
+
+        assert!(!cache.is_revoked(&claims));
+    }
+
+    #[test]
+    fn token_issued_before_a_user_revocation_is_revoked() {
+        let user_id = Uuid::new_v4();
+        let mut cache = RevocationCache::default();
+
+        let revoked_before = Utc::now();
+        cache.users.insert(user_id, revoked_before.naive_utc());
+        let claims = claims_issued_at(user_id, revoked_before - chrono::Duration::seconds(1));
+
+        assert!(cache.is_revoked(&claims));
+    }
+
+    #[test]
+    fn token_issued_earlier_in_the_same_second_as_a_user_revocation_is_revoked() {
+        let user_id = Uuid::new_v4();
+        let mut cache = RevocationCache::default();
+
+        let second = Utc.with_ymd_and_hms(2026, 1, 1, 12, 0, 0).unwrap();
+        let revoked_before = second + chrono::Duration::milliseconds(500);
+        cache.users.insert(user_id, revoked_before.naive_utc());
+
+        let mut claims = claims_issued_at(user_id, second + chrono::Duration::milliseconds(200));
+        assert!(cache.is_revoked(&claims));
+
+        claims.iat_micros = None;
+        assert!(cache.is_revoked(&claims));
+
+        let claims = claims_issued_at(user_id, revoked_before + chrono::Duration::microseconds(1));
+        assert!(!cache.is_revoked(&claims));
+    }
+
+    #[test]
+    fn user_revocation_only_applies_to_that_user() {
+        let mut cache = RevocationCache::default();
+        cache.users.insert(Uuid::new_v4(), Utc::now().naive_utc());
+
+        let claims = claims_issued_at(Uuid::new_v4(), Utc::now() - chrono::Duration::seconds(60));
+
+        assert!(!cache.is_revoked(&claims));
+    }
+
+    #[test]
+    fn revoked_jti_and_session_are_revoked() {
+        let user_id = Uuid::new_v4();
+        let mut cache = RevocationCache::default();
+        let now = Utc::now();
+
+        let claims = claims_issued_at(user_id, now);
+        cache.tokens.insert(claims.jti, now.naive_utc());
+        assert!(cache.is_revoked(&claims));
+
+        let session_id = Uuid::new_v4();
+        let mut claims = claims_issued_at(user_id, now);
+        claims.sid = Some(session_id);
+        cache.sessions.insert(session_id, now.naive_utc());
+        assert!(cache.is_revoked(&claims));
+    }
+}
@@ -0,0 +1,299 @@
+use std::{convert::Infallible, net::SocketAddr};
+
+use axum::{
+    Json,
+    extract::{ConnectInfo, FromRequestParts, Path, State},
+    http::request::Parts,
+};
+use diesel_async::{AsyncPgConnection, pooled_connection::deadpool::Pool};
+use hyper::{StatusCode, header::USER_AGENT};
+use rand::Rng;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::{
+    controllers::{
+        auth::AuthUser,
+        authorization::role_scopes,
+        jwt::{ACCESS_TOKEN_EXPIRATION_SECONDS, generate_jwt},
+        revocation::{revoke_all_user_tokens, revoke_session},
+        utils::get_conn,
+    },
+    models::{
+        self,
+        error::ApiError,
+        session::{RefreshToken, Session, SessionInfo, TokenPair},
+        user::{User, UserAuthInfo},
+    },
+};
+
+pub const REFRESH_TOKEN_EXPIRATION_DAYS: i64 = 30;
+const MAX_USER_AGENT_LENGTH: usize = 512;
+
+/// The device a request comes from, recorded on the sessions it opens or refreshes.
+pub struct ClientInfo {
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+impl<S> FromRequestParts<S> for ClientInfo
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let ip_address = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip().to_string());
+
+        let user_agent = parts
+            .headers
+            .get(USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.trim().chars().take(MAX_USER_AGENT_LENGTH).collect())
+            .filter(|value: &String| !value.is_empty());
+
+        Ok(ClientInfo {
+            ip_address,
+            user_agent,
+        })
+    }
+}
+
+pub fn generate_opaque_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill(&mut bytes);
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub fn hash_token(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Opens a new session (refresh token family) for the user and returns its first token pair.
+pub async fn start_session(
+    conn: &mut AsyncPgConnection,
+    user: &User,
+    login_type: &str,
+    client: &ClientInfo,
+) -> Result<TokenPair, (StatusCode, Json<String>)> {
+    let now = chrono::Utc::now().naive_utc();
+    let session = Session {
+        id: Uuid::new_v4(),
+        user_id: user.id,
+        create_date: now,
+        expiration_date: now + chrono::Duration::days(REFRESH_TOKEN_EXPIRATION_DAYS),
+        revocation_date: None,
+        login_type: login_type.to_string(),
+        ip_address: client.ip_address.clone(),
+        user_agent: client.user_agent.clone(),
+        last_seen_date: now,
+    };
+
+    if let Err(e) = models::session::create_session(conn, &session).await {
+        return Err((StatusCode::INTERNAL_SERVER_ERROR, Json(e)));
+    }
+
+    issue_token_pair(conn, &session, user).await
+}
+
+/// Exchanges a refresh token for a new token pair. Every refresh token can be used once; when an
+/// already used token is presented again the whole session is revoked, since either the client or
+/// an attacker is holding a stolen copy.
+pub async fn rotate_refresh_token(
+    conn: &mut AsyncPgConnection,
+    token: &str,
+    client: &ClientInfo,
+) -> Result<TokenPair, (StatusCode, Json<String>)> {
+    let invalid_token = || {
+        (
+            StatusCode::UNAUTHORIZED,
+            Json(ApiError::InvalidRefreshToken.to_string()),
+        )
+    };
+
+    let refresh_token =
+        match models::session::find_refresh_token_by_hash(conn, &hash_token(token)).await {
+            Ok(refresh_token) => refresh_token,
+            Err(_) => return Err(invalid_token()),
+        };
+
+    let session = match models::session::find_session_by_id(conn, &refresh_token.session_id).await {
+        Ok(session) => session,
+        Err(_) => return Err(invalid_token()),
+    };
+
+    let now = chrono::Utc::now().naive_utc();
+    if session.revocation_date.is_some() || session.expiration_date < now {
+        return Err(invalid_token());
+    }
+
+    let first_use = match models::session::mark_refresh_token_used(conn, &refresh_token.id).await {
+        Ok(first_use) => first_use,
+        Err(e) => return Err((StatusCode::INTERNAL_SERVER_ERROR, Json(e.to_string()))),
+    };
+
+    if !first_use {
+        tracing::warn!(
+            "Refresh token reuse detected, revoking session {}",
+            session.id
+        );
+        revoke_session(conn, &session.id).await?;
+        return Err((
+            StatusCode::UNAUTHORIZED,
+            Json(ApiError::RefreshTokenReuse.to_string()),
+        ));
+    }
+
+    if refresh_token.expiration_date < now {
+        return Err(invalid_token());
+    }
+
+    let user = match models::user::find_user_by_id(conn, &session.user_id).await {
+        Ok(user) => user,
+        Err(_) => return Err(invalid_token()),
+    };
+
+    if !user.is_active || user.deletion_date.is_some() {
+        return Err((
+            StatusCode::UNAUTHORIZED,
+            Json(ApiError::NotActiveUser.to_string()),
+        ));
+    }
+
+    if let Err(e) = models::session::touch_session(
+        conn,
+        &session.id,
+        client.ip_address.as_deref(),
+        client.user_agent.as_deref(),
+    )
+    .await
+    {
+        return Err((StatusCode::INTERNAL_SERVER_ERROR, Json(e.to_string())));
+    }
+
+    issue_token_pair(conn, &session, &user).await
+}
+
+/// Revokes the session the refresh token belongs to, as long as it is owned by the given user.
+pub async fn end_session(
+    conn: &mut AsyncPgConnection,
+    user_id: &Uuid,
+    token: &str,
+) -> Result<(), (StatusCode, Json<String>)> {
+    let refresh_token =
+        match models::session::find_refresh_token_by_hash(conn, &hash_token(token)).await {
+            Ok(refresh_token) => refresh_token,
+            Err(_) => return Ok(()),
+        };
+
+    let session = match models::session::find_session_by_id(conn, &refresh_token.session_id).await {
+        Ok(session) => session,
+        Err(_) => return Ok(()),
+    };
+
+    if session.user_id != *user_id {
+        return Ok(());
+    }
+
+    revoke_session(conn, &session.id).await?;
+    Ok(())
+}
+
+/// Signs the user out everywhere: every refresh token family is revoked and every access token
+/// issued so far stops being accepted.
+pub async fn end_all_sessions(
+    conn: &mut AsyncPgConnection,
+    user_id: &Uuid,
+) -> Result<(), (StatusCode, Json<String>)> {
+    revoke_all_user_tokens(conn, user_id).await?;
+
+    match models::session::revoke_user_sessions(conn, user_id).await {
+        Ok(_) => Ok(()),
+        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, Json(e.to_string()))),
+    }
+}
+
+async fn issue_token_pair(
+    conn: &mut AsyncPgConnection,
+    session: &Session,
+    user: &User,
+) -> Result<TokenPair, (StatusCode, Json<String>)> {
+    let scopes = role_scopes(conn, user.user_type).await?;
+    let access_token = generate_jwt(UserAuthInfo::from(user.clone()), scopes, session.id)?;
+
+    let token = generate_opaque_token();
+    let refresh_token = RefreshToken {
+        id: Uuid::new_v4(),
+        session_id: session.id,
+        token_hash: hash_token(&token),
+        create_date: chrono::Utc::now().naive_utc(),
+        expiration_date: session.expiration_date,
+        used_date: None,
+    };
+
+    if let Err(e) = models::session::create_refresh_token(conn, &refresh_token).await {
+        return Err((StatusCode::INTERNAL_SERVER_ERROR, Json(e)));
+    }
+
+    Ok(TokenPair {
+        access_token,
+        refresh_token: token,
+        token_type: "Bearer".to_string(),
+        expires_in: ACCESS_TOKEN_EXPIRATION_SECONDS,
+    })
+}
+
+/// Devices the user is signed in on.
+#[utoipa::path(get, path = "/user/sessions", responses((status = OK, body = Vec<SessionInfo>)), security(("bearer_auth" = ["profile:read"]), ("api_key" = ["profile:read"])))]
+pub async fn api_list_sessions(
+    State(pool): State<Pool<AsyncPgConnection>>,
+    AuthUser(claims): AuthUser,
+) -> Result<(StatusCode, Json<Vec<SessionInfo>>), (StatusCode, Json<String>)> {
+    let conn = &mut get_conn(&pool).await?;
+
+    match models::session::list_active_sessions(conn, &claims.id).await {
+        Ok(sessions) => Ok((
+            StatusCode::OK,
+            Json(
+                sessions
+                    .into_iter()
+                    .map(|session| SessionInfo::new(session, claims.sid))
+                    .collect(),
+            ),
+        )),
+        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, Json(e.to_string()))),
+    }
+}
+
+/// Signs a device out: its refresh token stops working and so do its access tokens.
+#[utoipa::path(delete, path = "/user/sessions/{id}", params(("id" = Uuid, Path)), responses((status = NO_CONTENT), (status = NOT_FOUND)), security(("bearer_auth" = ["profile:write"])))]
+pub async fn api_revoke_session(
+    State(pool): State<Pool<AsyncPgConnection>>,
+    AuthUser(claims): AuthUser,
+    Path(id): Path<Uuid>,
+) -> Result<StatusCode, (StatusCode, Json<String>)> {
+    let not_found = || {
+        (
+            StatusCode::NOT_FOUND,
+            Json(ApiError::SessionNotFound.to_string()),
+        )
+    };
+
+    let conn = &mut get_conn(&pool).await?;
+
+    let session = match models::session::find_session_by_id(conn, &id).await {
+        Ok(session) if session.user_id == claims.id => session,
+        _ => return Err(not_found()),
+    };
+
+    match revoke_session(conn, &session.id).await? {
+        true => Ok(StatusCode::NO_CONTENT),
+        false => Err(not_found()),
+    }
+}
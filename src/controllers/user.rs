@@ -1,17 +1,47 @@
-use axum::{Json, extract::State};
+use std::net::SocketAddr;
+
+use axum::{
+    Extension, Json,
+    extract::{ConnectInfo, State},
+};
 use diesel_async::{AsyncPgConnection, pooled_connection::deadpool::Pool};
-use hyper::{HeaderMap, StatusCode};
-use pwhash::bcrypt::verify;
+use hyper::StatusCode;
 
 use crate::{
     controllers::{
-        jwt::{extract_claims_from_header, generate_jwt},
+        audit::record_audit_event,
+        auth::{AuthUser, CurrentUser},
+        email::{
+            EMAIL_VERIFICATION_EXPIRATION_HOURS, MAGIC_LINK_EXPIRATION_MINUTES,
+            PASSWORD_RESET_EXPIRATION_MINUTES, send_email_change_email, send_magic_link_email,
+            send_password_reset_email, send_verification_email,
+        },
+        hasher::{hash_password, verify_dummy_password, verify_user_password},
+        login_throttle::{check_login_allowed, register_login_failure, register_login_success},
+        mfa::{MFA_TOKEN_EXPIRATION_SECONDS, find_enabled_mfa, issue_mfa_token},
+        password::{PasswordContext, validate_password},
+        rate_limit::{CallerRateLimit, EMAIL_CHANGE_ROUTE},
+        revocation::revoke_token,
+        session::{ClientInfo, end_all_sessions, end_session, rotate_refresh_token, start_session},
+        user_token::{
+            consume_user_token, find_valid_user_token, issue_user_token, redeem_user_token,
+        },
         utils::get_conn,
     },
     models::{
         self,
+        audit::{EMAIL_CHANGED, IMPERSONATION_ENDED},
         error::ApiError,
-        user::{LoginUser, RegisterUser, UpdateUser, User, UserAuthInfo},
+        mfa::MfaChallenge,
+        session::{LoginResponse, LogoutInput, RefreshTokenInput, TokenPair},
+        user::{
+            ChangePasswordInput, LoginUser, MAGIC_LINK_LOGIN_TYPE, PASSWORD_LOGIN_TYPE,
+            RegisterUser, UpdateUser, User, UserChanges, UserResponse,
+        },
+        user_token::{
+            ConsumeMagicLinkInput, ForgotPasswordInput, MagicLinkInput, ResendVerificationInput,
+            ResetPasswordInput, TokenPurpose, VerifyEmailInput,
+        },
     },
 };
 #[utoipa::path(post, path = "/user/register", responses((status = CREATED, body = RegisterUser)))]
@@ -27,26 +57,293 @@ pub async fn api_register_user(
         ));
     }
 
+    validate_password(
+        &user_input.password,
+        &PasswordContext {
+            email: &user_input.email,
+            name: &user_input.name,
+            document: &user_input.document,
+        },
+    )?;
+
     match user_input.parse_fields() {
         Ok(_) => {}
         Err(e) => return Err((StatusCode::BAD_REQUEST, Json(e))),
     }
+    user_input.password = hash_password(&user_input.password).await?;
 
     let user = User::from(user_input);
 
     let conn = &mut get_conn(&pool).await?;
 
-    match models::user::register_user(conn, &user).await {
-        Ok(_) => Ok(StatusCode::CREATED),
-        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, Json(e))),
+    if let Err(e) = models::user::register_user(conn, &user).await {
+        return Err((StatusCode::INTERNAL_SERVER_ERROR, Json(e)));
+    }
+
+    // The account is already created at this point; if the e-mail can't be sent the user can
+    // still ask for a new one through `/user/resend-verification`.
+    if let Err(e) = request_email_verification(conn, &user).await {
+        tracing::error!("Failed to send the verification e-mail: {}", e.1.0);
+    }
+
+    Ok(StatusCode::CREATED)
+}
+
+async fn request_email_verification(
+    conn: &mut AsyncPgConnection,
+    user: &User,
+) -> Result<(), (StatusCode, Json<String>)> {
+    let token = issue_user_token(
+        conn,
+        &user.id,
+        TokenPurpose::EmailVerification,
+        chrono::Duration::hours(EMAIL_VERIFICATION_EXPIRATION_HOURS),
+    )
+    .await?;
+
+    send_verification_email(&user.name, &user.email, &token).await
+}
+
+#[utoipa::path(post, path = "/user/verify-email", request_body = VerifyEmailInput, responses((status = OK)))]
+pub async fn api_verify_email(
+    State(pool): State<Pool<AsyncPgConnection>>,
+    input: Json<VerifyEmailInput>,
+) -> Result<StatusCode, (StatusCode, Json<String>)> {
+    let token = input.0.token.trim().to_string();
+    if token.is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ApiError::InvalidData.to_string()),
+        ));
+    }
+
+    let conn = &mut get_conn(&pool).await?;
+
+    let user_token = consume_user_token(conn, TokenPurpose::EmailVerification, &token).await?;
+
+    match models::user::set_email_verified(conn, &user_token.user_id).await {
+        Ok(_) => Ok(StatusCode::OK),
+        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, Json(e.to_string()))),
+    }
+}
+
+/// Always answers 200 so the endpoint can't be used to find out which e-mails are registered.
+#[utoipa::path(post, path = "/user/resend-verification", request_body = ResendVerificationInput, responses((status = OK)))]
+pub async fn api_resend_verification(
+    State(pool): State<Pool<AsyncPgConnection>>,
+    input: Json<ResendVerificationInput>,
+) -> Result<StatusCode, (StatusCode, Json<String>)> {
+    let email = input.0.email.trim().to_string();
+    if email.is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ApiError::InvalidData.to_string()),
+        ));
+    }
+
+    let conn = &mut get_conn(&pool).await?;
+
+    if let Ok(user) = models::user::find_user_by_email(conn, &email).await
+        && !user.email_verified
+        && user.deletion_date.is_none()
+        && let Err(e) = request_email_verification(conn, &user).await
+    {
+        tracing::error!("Failed to send the verification e-mail: {}", e.1.0);
+    }
+
+    Ok(StatusCode::OK)
+}
+
+/// Always answers 200 so the endpoint can't be used to find out which e-mails are registered.
+#[utoipa::path(post, path = "/user/forgot-password", request_body = ForgotPasswordInput, responses((status = OK)))]
+pub async fn api_forgot_password(
+    State(pool): State<Pool<AsyncPgConnection>>,
+    input: Json<ForgotPasswordInput>,
+) -> Result<StatusCode, (StatusCode, Json<String>)> {
+    let email = input.0.email.trim().to_string();
+    if email.is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ApiError::InvalidData.to_string()),
+        ));
+    }
+
+    let conn = &mut get_conn(&pool).await?;
+
+    if let Ok(user) = models::user::find_user_by_email(conn, &email).await
+        && user.is_active
+        && user.deletion_date.is_none()
+        && let Err(e) = request_password_reset(conn, &user).await
+    {
+        tracing::error!("Failed to send the password reset e-mail: {}", e.1.0);
     }
+
+    Ok(StatusCode::OK)
+}
+
+pub async fn request_password_reset(
+    conn: &mut AsyncPgConnection,
+    user: &User,
+) -> Result<(), (StatusCode, Json<String>)> {
+    let token = issue_user_token(
+        conn,
+        &user.id,
+        TokenPurpose::PasswordReset,
+        chrono::Duration::minutes(PASSWORD_RESET_EXPIRATION_MINUTES),
+    )
+    .await?;
+
+    send_password_reset_email(&user.name, &user.email, &token).await
 }
 
-#[utoipa::path(post, path = "/user/login", responses((status = OK, body = LoginUser)))]
+#[utoipa::path(post, path = "/user/reset-password", request_body = ResetPasswordInput, responses((status = OK)))]
+pub async fn api_reset_password(
+    State(pool): State<Pool<AsyncPgConnection>>,
+    input: Json<ResetPasswordInput>,
+) -> Result<StatusCode, (StatusCode, Json<String>)> {
+    let input = input.0;
+    if input.token.trim().is_empty() || input.password.is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ApiError::InvalidData.to_string()),
+        ));
+    }
+
+    let conn = &mut get_conn(&pool).await?;
+
+    let user_token =
+        find_valid_user_token(conn, TokenPurpose::PasswordReset, input.token.trim()).await?;
+
+    let user = match models::user::find_user_by_id(conn, &user_token.user_id).await {
+        Ok(user) => user,
+        Err(_) => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                Json(ApiError::InvalidUserToken.to_string()),
+            ));
+        }
+    };
+
+    // Checked before the token is redeemed so a rejected password doesn't burn the link.
+    validate_password(
+        &input.password,
+        &PasswordContext {
+            email: &user.email,
+            name: &user.name,
+            document: user.document.as_deref().unwrap_or_default(),
+        },
+    )?;
+
+    redeem_user_token(conn, &user_token).await?;
+
+    let password = hash_password(&input.password).await?;
+    if let Err(e) = models::user::update_password(conn, &user_token.user_id, &password).await {
+        return Err((StatusCode::INTERNAL_SERVER_ERROR, Json(e.to_string())));
+    }
+
+    end_all_sessions(conn, &user_token.user_id).await?;
+
+    Ok(StatusCode::OK)
+}
+
+/// Always answers 200 so the endpoint can't be used to find out which e-mails are registered.
+#[utoipa::path(post, path = "/user/magic-link", request_body = MagicLinkInput, responses((status = OK)))]
+pub async fn api_request_magic_link(
+    State(pool): State<Pool<AsyncPgConnection>>,
+    input: Json<MagicLinkInput>,
+) -> Result<StatusCode, (StatusCode, Json<String>)> {
+    let email = input.0.email.trim().to_string();
+    if email.is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ApiError::InvalidData.to_string()),
+        ));
+    }
+
+    let conn = &mut get_conn(&pool).await?;
+
+    if let Ok(user) = models::user::find_user_by_email(conn, &email).await
+        && user.is_active
+        && user.deletion_date.is_none()
+        && let Err(e) = request_magic_link(conn, &user).await
+    {
+        tracing::error!("Failed to send the magic link e-mail: {}", e.1.0);
+    }
+
+    Ok(StatusCode::OK)
+}
+
+async fn request_magic_link(
+    conn: &mut AsyncPgConnection,
+    user: &User,
+) -> Result<(), (StatusCode, Json<String>)> {
+    let token = issue_user_token(
+        conn,
+        &user.id,
+        TokenPurpose::MagicLink,
+        chrono::Duration::minutes(MAGIC_LINK_EXPIRATION_MINUTES),
+    )
+    .await?;
+
+    send_magic_link_email(&user.name, &user.email, &token).await
+}
+
+/// Exchanges a link sent by `/user/magic-link` for the session tokens. Since the link could only
+/// be opened from the user's inbox, it also confirms the e-mail address.
+#[utoipa::path(post, path = "/user/magic-link/consume", request_body = ConsumeMagicLinkInput, responses((status = OK, body = LoginResponse)))]
+pub async fn api_consume_magic_link(
+    State(pool): State<Pool<AsyncPgConnection>>,
+    client: ClientInfo,
+    input: Json<ConsumeMagicLinkInput>,
+) -> Result<(StatusCode, Json<LoginResponse>), (StatusCode, Json<String>)> {
+    let token = input.0.token.trim().to_string();
+    if token.is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ApiError::InvalidData.to_string()),
+        ));
+    }
+
+    let conn = &mut get_conn(&pool).await?;
+
+    let user_token = consume_user_token(conn, TokenPurpose::MagicLink, &token).await?;
+
+    let user = match models::user::find_user_by_id(conn, &user_token.user_id).await {
+        Ok(user) => user,
+        Err(_) => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                Json(ApiError::InvalidUserToken.to_string()),
+            ));
+        }
+    };
+
+    if !user.is_active || user.deletion_date.is_some() {
+        return Err((
+            StatusCode::FORBIDDEN,
+            Json(ApiError::NotActiveUser.to_string()),
+        ));
+    }
+
+    if !user.email_verified
+        && let Err(e) = models::user::set_email_verified(conn, &user.id).await
+    {
+        return Err((StatusCode::INTERNAL_SERVER_ERROR, Json(e.to_string())));
+    }
+
+    let response = finish_login(conn, &user, MAGIC_LINK_LOGIN_TYPE, &client).await?;
+    Ok((StatusCode::OK, Json(response)))
+}
+
+/// Wrong passwords and unknown e-mails get the same 401 answer, and repeated failures lock the
+/// account and the client IP for an increasing amount of time.
+#[utoipa::path(post, path = "/user/login", request_body = LoginUser, responses((status = OK, body = LoginResponse)))]
 pub async fn api_login_user(
     State(pool): State<Pool<AsyncPgConnection>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    client: ClientInfo,
     input: Json<LoginUser>,
-) -> Result<(StatusCode, Json<String>), (StatusCode, Json<String>)> {
+) -> Result<(StatusCode, Json<LoginResponse>), (StatusCode, Json<String>)> {
     let mut user_input = input.0;
 
     match user_input.validate_fields() {
@@ -55,60 +352,383 @@ pub async fn api_login_user(
     }
     user_input.parse_fields();
 
+    let ip = addr.ip();
     let conn = &mut get_conn(&pool).await?;
 
+    check_login_allowed(conn, &user_input.email, ip).await?;
+
+    let invalid_credentials = || {
+        (
+            StatusCode::UNAUTHORIZED,
+            Json(ApiError::InvalidCredentials.to_string()),
+        )
+    };
+
     let user = match models::user::find_user_by_email(conn, &user_input.email).await {
         Ok(user) => user,
         Err(_) => {
-            return Err((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiError::EmailNotFound.to_string()),
-            ));
+            verify_dummy_password(&user_input.password).await;
+            register_login_failure(conn, &user_input.email, ip).await?;
+            return Err(invalid_credentials());
         }
     };
 
+    let verification = verify_user_password(&user_input.password, &user.password).await?;
+    if !verification.valid {
+        register_login_failure(conn, &user_input.email, ip).await?;
+        return Err(invalid_credentials());
+    }
+
+    register_login_success(conn, &user_input.email).await?;
+
     if !user.is_active || user.deletion_date.is_some() {
         return Err((
-            StatusCode::INTERNAL_SERVER_ERROR,
+            StatusCode::FORBIDDEN,
             Json(ApiError::NotActiveUser.to_string()),
         ));
     }
 
-    let token = generate_jwt(UserAuthInfo::from(user.clone()))?;
+    if !user.email_verified {
+        return Err((
+            StatusCode::FORBIDDEN,
+            Json(ApiError::EmailNotVerified.to_string()),
+        ));
+    }
+
+    if verification.needs_rehash {
+        upgrade_password_hash(conn, &user, &user_input.password).await;
+    }
+
+    let response = finish_login(conn, &user, PASSWORD_LOGIN_TYPE, &client).await?;
+    Ok((StatusCode::OK, Json(response)))
+}
+
+/// Last step of every login method once the user is identified: starts the session, or asks for
+/// the second factor when the account has MFA enabled.
+pub async fn finish_login(
+    conn: &mut AsyncPgConnection,
+    user: &User,
+    login_type: &str,
+    client: &ClientInfo,
+) -> Result<LoginResponse, (StatusCode, Json<String>)> {
+    if find_enabled_mfa(conn, &user.id).await?.is_some() {
+        let challenge = MfaChallenge {
+            mfa_required: true,
+            mfa_token: issue_mfa_token(&user.id, login_type)?,
+            expires_in: MFA_TOKEN_EXPIRATION_SECONDS,
+        };
+        return Ok(LoginResponse::MfaRequired(challenge));
+    }
+
+    let tokens = start_session(conn, user, login_type, client).await?;
+    Ok(LoginResponse::Tokens(tokens))
+}
+
+/// Replaces a hash made by a legacy scheme or with outdated parameters. Failures are only logged,
+/// the login itself already succeeded.
+async fn upgrade_password_hash(conn: &mut AsyncPgConnection, user: &User, password: &str) {
+    let hash = match hash_password(password).await {
+        Ok(hash) => hash,
+        Err(e) => {
+            tracing::error!("Failed to rehash the password: {}", e.1.0);
+            return;
+        }
+    };
+
+    if let Err(e) = models::user::update_password(conn, &user.id, &hash).await {
+        tracing::error!("Failed to store the rehashed password: {}", e);
+    }
+}
+
+#[utoipa::path(post, path = "/user/refresh", request_body = RefreshTokenInput, responses((status = OK, body = TokenPair)))]
+pub async fn api_refresh_token(
+    State(pool): State<Pool<AsyncPgConnection>>,
+    client: ClientInfo,
+    input: Json<RefreshTokenInput>,
+) -> Result<(StatusCode, Json<TokenPair>), (StatusCode, Json<String>)> {
+    let refresh_token = input.0.refresh_token.trim().to_string();
+    if refresh_token.is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ApiError::InvalidData.to_string()),
+        ));
+    }
+
+    let conn = &mut get_conn(&pool).await?;
+
+    let tokens = rotate_refresh_token(conn, &refresh_token, &client).await?;
+    Ok((StatusCode::OK, Json(tokens)))
+}
+
+#[utoipa::path(post, path = "/user/logout", request_body = Option<LogoutInput>, responses((status = OK)), security(("bearer_auth" = []), ("api_key" = [])))]
+pub async fn api_logout_user(
+    State(pool): State<Pool<AsyncPgConnection>>,
+    AuthUser(claims): AuthUser,
+    input: Option<Json<LogoutInput>>,
+) -> Result<StatusCode, (StatusCode, Json<String>)> {
+    let conn = &mut get_conn(&pool).await?;
+
+    revoke_token(conn, &claims).await?;
+
+    // Signing out of an impersonation token ends the impersonation.
+    if let Some(actor) = &claims.act {
+        record_audit_event(
+            conn,
+            actor.sub,
+            IMPERSONATION_ENDED,
+            Some(claims.id),
+            Some(serde_json::json!({ "token_id": claims.jti })),
+        )
+        .await?;
+        return Ok(StatusCode::OK);
+    }
 
-    if verify(user_input.password, &user.password) {
-        return Ok((StatusCode::OK, Json(token)));
+    if let Some(refresh_token) = input.and_then(|input| input.0.refresh_token) {
+        end_session(conn, &claims.id, refresh_token.trim()).await?;
     }
 
-    Err((
-        StatusCode::INTERNAL_SERVER_ERROR,
-        Json(ApiError::InvalidPassword.to_string()),
-    ))
+    Ok(StatusCode::OK)
+}
+
+#[utoipa::path(post, path = "/user/logout-all", responses((status = OK)), security(("bearer_auth" = []), ("api_key" = [])))]
+pub async fn api_logout_all(
+    State(pool): State<Pool<AsyncPgConnection>>,
+    AuthUser(claims): AuthUser,
+) -> Result<StatusCode, (StatusCode, Json<String>)> {
+    let conn = &mut get_conn(&pool).await?;
+
+    end_all_sessions(conn, &claims.id).await?;
+
+    Ok(StatusCode::OK)
 }
 
+#[utoipa::path(patch, path = "/user/password", request_body = ChangePasswordInput, responses((status = OK, body = Option<TokenPair>)), security(("bearer_auth" = ["profile:write"])))]
+pub async fn api_change_password(
+    State(pool): State<Pool<AsyncPgConnection>>,
+    CurrentUser { user, .. }: CurrentUser,
+    client: ClientInfo,
+    input: Json<ChangePasswordInput>,
+) -> Result<(StatusCode, Json<Option<TokenPair>>), (StatusCode, Json<String>)> {
+    let input = input.0;
+    if input.current_password.is_empty() || input.new_password.is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ApiError::InvalidData.to_string()),
+        ));
+    }
+
+    if !verify_user_password(&input.current_password, &user.password)
+        .await?
+        .valid
+    {
+        return Err((
+            StatusCode::FORBIDDEN,
+            Json(ApiError::InvalidPassword.to_string()),
+        ));
+    }
+
+    if input.current_password == input.new_password {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ApiError::SamePassword.to_string()),
+        ));
+    }
+
+    validate_password(
+        &input.new_password,
+        &PasswordContext {
+            email: &user.email,
+            name: &user.name,
+            document: user.document.as_deref().unwrap_or_default(),
+        },
+    )?;
+
+    let conn = &mut get_conn(&pool).await?;
+
+    let password = hash_password(&input.new_password).await?;
+    if let Err(e) = models::user::update_password(conn, &user.id, &password).await {
+        return Err((StatusCode::INTERNAL_SERVER_ERROR, Json(e.to_string())));
+    }
+
+    if !input.logout_other_sessions {
+        return Ok((StatusCode::OK, Json(None)));
+    }
+
+    end_all_sessions(conn, &user.id).await?;
+    let tokens = start_session(conn, &user, PASSWORD_LOGIN_TYPE, &client).await?;
+
+    Ok((StatusCode::OK, Json(Some(tokens))))
+}
+
+/// Account of the signed-in user.
+#[utoipa::path(get, path = "/user/me", responses((status = OK, body = UserResponse)), security(("bearer_auth" = ["profile:read"]), ("api_key" = ["profile:read"])))]
+pub async fn api_get_current_user(
+    CurrentUser { user, .. }: CurrentUser,
+) -> Result<(StatusCode, Json<UserResponse>), (StatusCode, Json<String>)> {
+    Ok((StatusCode::OK, Json(UserResponse::from(user))))
+}
+
+/// Partially updates the signed-in user's account. A new e-mail is kept as `pending_email` and a
+/// link is sent to it; it replaces the current one once confirmed at `/user/confirm-email`. Sending
+/// the current e-mail again cancels a pending change.
+#[utoipa::path(
+    patch,
+    path = "/user/update",
+    request_body(content = UpdateUser, content_type = "application/merge-patch+json"),
+    responses((status = OK, body = UserResponse), (status = BAD_REQUEST), (status = CONFLICT)),
+    security(("bearer_auth" = ["profile:write"]), ("api_key" = ["profile:write"]))
+)]
 pub async fn api_update_user_data(
     State(pool): State<Pool<AsyncPgConnection>>,
-    headers: HeaderMap,
+    CurrentUser { claims, user }: CurrentUser,
+    Extension(rate_limit): Extension<CallerRateLimit>,
     input: Json<UpdateUser>,
+) -> Result<(StatusCode, Json<UserResponse>), (StatusCode, Json<String>)> {
+    let mut input = input.0;
+    if let Err(e) = input.parse_fields() {
+        return Err((StatusCode::BAD_REQUEST, Json(e)));
+    }
+
+    let requested_email = input.email.flatten();
+    let new_email = requested_email.clone().filter(|email| *email != user.email);
+
+    let conn = &mut get_conn(&pool).await?;
+
+    if let Some(email) = &new_email {
+        // The e-mail is how the account is recovered, so only its owner may change it.
+        if claims.impersonated {
+            return Err((
+                StatusCode::FORBIDDEN,
+                Json(ApiError::ImpersonationForbidden.to_string()),
+            ));
+        }
+        if claims.api_key_id.is_some() {
+            return Err((
+                StatusCode::FORBIDDEN,
+                Json(ApiError::ApiKeyForbidden.to_string()),
+            ));
+        }
+        let password = input.current_password.as_deref().unwrap_or_default();
+        if password.is_empty() {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                Json(ApiError::InvalidData.to_string()),
+            ));
+        }
+        rate_limit.check(EMAIL_CHANGE_ROUTE).await?;
+        if !verify_user_password(password, &user.password).await?.valid {
+            return Err((
+                StatusCode::FORBIDDEN,
+                Json(ApiError::InvalidPassword.to_string()),
+            ));
+        }
+        // The lookup ignores case, so a change of case only finds the account itself.
+        if let Ok(existing) = models::user::find_user_by_email(conn, email).await
+            && existing.id != user.id
+        {
+            return Err((StatusCode::CONFLICT, Json(ApiError::EmailInUse.to_string())));
+        }
+    }
+
+    let changes = UserChanges {
+        name: input.name.flatten(),
+        document: input.document,
+        birthdate: input.birthdate,
+        pending_email: requested_email.map(|_| new_email.clone()),
+        update_date: chrono::Utc::now().naive_utc(),
+    };
+
+    let updated = match models::user::update_user_data(conn, &user.id, &changes).await {
+        Ok(updated) => updated,
+        Err(ApiError::DocumentInUse) => {
+            return Err((
+                StatusCode::CONFLICT,
+                Json(ApiError::DocumentInUse.to_string()),
+            ));
+        }
+        Err(e) => return Err((StatusCode::INTERNAL_SERVER_ERROR, Json(e.to_string()))),
+    };
+
+    // The change is saved at this point; if the e-mail can't be sent the user can ask for the
+    // same address again.
+    if let Some(email) = &new_email
+        && let Err(e) = request_email_change(conn, &updated, email).await
+    {
+        tracing::error!("Failed to send the e-mail change confirmation: {}", e.1.0);
+    }
+
+    Ok((StatusCode::OK, Json(UserResponse::from(updated))))
+}
+
+async fn request_email_change(
+    conn: &mut AsyncPgConnection,
+    user: &User,
+    email: &str,
+) -> Result<(), (StatusCode, Json<String>)> {
+    let token = issue_user_token(
+        conn,
+        &user.id,
+        TokenPurpose::EmailChange,
+        chrono::Duration::hours(EMAIL_VERIFICATION_EXPIRATION_HOURS),
+    )
+    .await?;
+
+    send_email_change_email(&user.name, email, &token).await
+}
+
+/// Replaces the account's e-mail with the pending one, using the token sent to the new address.
+#[utoipa::path(post, path = "/user/confirm-email", request_body = VerifyEmailInput, responses((status = OK), (status = CONFLICT)))]
+pub async fn api_confirm_email_change(
+    State(pool): State<Pool<AsyncPgConnection>>,
+    input: Json<VerifyEmailInput>,
 ) -> Result<StatusCode, (StatusCode, Json<String>)> {
-    let id = extract_claims_from_header(&headers).await?.1.id;
+    let token = input.0.token.trim().to_string();
+    if token.is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ApiError::InvalidData.to_string()),
+        ));
+    }
 
     let conn = &mut get_conn(&pool).await?;
 
-    match models::user::find_user_by_id(conn, &id).await {
+    let user_token = consume_user_token(conn, TokenPurpose::EmailChange, &token).await?;
+
+    let user = match models::user::find_user_by_id(conn, &user_token.user_id).await {
+        Ok(user) => user,
         Err(_) => {
             return Err((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiError::UserNotFound.to_string()),
+                StatusCode::BAD_REQUEST,
+                Json(ApiError::InvalidUserToken.to_string()),
             ));
         }
-        _ => {}
     };
 
-    let update_data = input.0;
+    let updated = match models::user::confirm_pending_email(conn, &user.id).await {
+        Ok(Some(updated)) => updated,
+        Ok(None) => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                Json(ApiError::NoPendingEmail.to_string()),
+            ));
+        }
+        Err(ApiError::EmailInUse) => {
+            return Err((StatusCode::CONFLICT, Json(ApiError::EmailInUse.to_string())));
+        }
+        Err(e) => return Err((StatusCode::INTERNAL_SERVER_ERROR, Json(e.to_string()))),
+    };
 
-    match models::user::update_user_data(conn, &id, &update_data).await {
-        Ok(_) => Ok(StatusCode::OK),
-        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, Json(e.to_string()))),
-    }
+    record_audit_event(
+        conn,
+        user.id,
+        EMAIL_CHANGED,
+        Some(user.id),
+        Some(serde_json::json!({
+            "from": user.email,
+            "to": updated.email,
+        })),
+    )
+    .await?;
+
+    Ok(StatusCode::OK)
 }
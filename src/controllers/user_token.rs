@@ -0,0 +1,101 @@
+use axum::Json;
+use diesel_async::AsyncPgConnection;
+use hyper::StatusCode;
+use uuid::Uuid;
+
+use crate::{
+    controllers::session::{generate_opaque_token, hash_token},
+    models::{
+        self,
+        error::ApiError,
+        user_token::{TokenPurpose, UserToken},
+    },
+};
+
+/// Creates a single-use token for the user, replacing any pending one with the same purpose.
+/// Only its hash is stored; the returned plain token is meant to be sent to the user.
+pub async fn issue_user_token(
+    conn: &mut AsyncPgConnection,
+    user_id: &Uuid,
+    purpose: TokenPurpose,
+    expires_in: chrono::Duration,
+) -> Result<String, (StatusCode, Json<String>)> {
+    if let Err(e) = models::user_token::invalidate_user_tokens(conn, user_id, purpose).await {
+        return Err((StatusCode::INTERNAL_SERVER_ERROR, Json(e.to_string())));
+    }
+
+    let token = generate_opaque_token();
+    let now = chrono::Utc::now().naive_utc();
+    let user_token = UserToken {
+        id: Uuid::new_v4(),
+        user_id: *user_id,
+        purpose,
+        token_hash: hash_token(&token),
+        create_date: now,
+        expiration_date: now + expires_in,
+        used_date: None,
+    };
+
+    match models::user_token::create_user_token(conn, &user_token).await {
+        Ok(_) => Ok(token),
+        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, Json(e.to_string()))),
+    }
+}
+
+/// Looks up a token without using it, failing when it doesn't exist, has expired or was already
+/// used.
+pub async fn find_valid_user_token(
+    conn: &mut AsyncPgConnection,
+    purpose: TokenPurpose,
+    token: &str,
+) -> Result<UserToken, (StatusCode, Json<String>)> {
+    let user_token = match models::user_token::find_user_token_by_hash(
+        conn,
+        purpose,
+        &hash_token(token),
+    )
+    .await
+    {
+        Ok(user_token) => user_token,
+        Err(_) => return Err(invalid_user_token()),
+    };
+
+    if user_token.used_date.is_some() || user_token.expiration_date < chrono::Utc::now().naive_utc()
+    {
+        return Err(invalid_user_token());
+    }
+
+    Ok(user_token)
+}
+
+/// Marks a token returned by [`find_valid_user_token`] as used, failing if a concurrent request
+/// redeemed it first.
+pub async fn redeem_user_token(
+    conn: &mut AsyncPgConnection,
+    user_token: &UserToken,
+) -> Result<(), (StatusCode, Json<String>)> {
+    match models::user_token::mark_user_token_used(conn, &user_token.id).await {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(invalid_user_token()),
+        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, Json(e.to_string()))),
+    }
+}
+
+/// Redeems a token, failing when it doesn't exist, has expired or was already used.
+pub async fn consume_user_token(
+    conn: &mut AsyncPgConnection,
+    purpose: TokenPurpose,
+    token: &str,
+) -> Result<UserToken, (StatusCode, Json<String>)> {
+    let user_token = find_valid_user_token(conn, purpose, token).await?;
+    redeem_user_token(conn, &user_token).await?;
+
+    Ok(user_token)
+}
+
+fn invalid_user_token() -> (StatusCode, Json<String>) {
+    (
+        StatusCode::BAD_REQUEST,
+        Json(ApiError::InvalidUserToken.to_string()),
+    )
+}
@@ -11,7 +11,7 @@ use crate::models::error::ApiError;
 pub fn validate_cpf(cpf: &str) -> bool {
     let cpf: Vec<u8> = cpf
         .chars()
-        .filter(|c| c.is_digit(10))
+        .filter(|c| c.is_ascii_digit())
         .map(|c| c.to_digit(10).unwrap() as u8)
         .collect();
 
@@ -43,7 +43,7 @@ pub fn validate_cpf(cpf: &str) -> bool {
 pub fn validate_cnpj(cnpj: &str) -> bool {
     let cnpj: Vec<u8> = cnpj
         .chars()
-        .filter(|c| c.is_digit(10))
+        .filter(|c| c.is_ascii_digit())
         .map(|c| c.to_digit(10).unwrap() as u8)
         .collect();
 
@@ -88,7 +88,7 @@ pub fn format_cnpj(cnpj: &str) -> Result<String, String> {
 }
 
 pub fn format_cpf(cpf: &str) -> Result<String, String> {
-    let cpf: Vec<char> = cpf.chars().filter(|c: &char| c.is_digit(10)).collect();
+    let cpf: Vec<char> = cpf.chars().filter(|c: &char| c.is_ascii_digit()).collect();
     if cpf.len() != 11 {
         return Err("Invalid CPF length".to_string());
     }
@@ -103,29 +103,55 @@ pub fn format_cpf(cpf: &str) -> Result<String, String> {
     Ok(cpffinal)
 }
 
+/// Formats a CPF or CNPJ whose check digits are valid.
 pub fn format_document(documento_: &str) -> Result<String, String> {
-    if let Ok(cpf) = format_cpf(documento_) {
+    if validate_cpf(documento_)
+        && let Ok(cpf) = format_cpf(documento_)
+    {
         return Ok(cpf);
     }
-    if let Ok(cnpj) = format_cnpj(documento_) {
+    if validate_cnpj(documento_)
+        && let Ok(cnpj) = format_cnpj(documento_)
+    {
         return Ok(cnpj);
     }
     Err("Invalid document".to_string())
 }
 
+/// Hides the leading digits and the check digits of a CPF or CNPJ, keeping the formatting, e.g.
+/// `***.456.789-**`. Anything else is masked entirely.
+pub fn mask_document(document: &str) -> String {
+    let digits = document.chars().filter(|c| c.is_ascii_digit()).count();
+    let hidden_prefix = match digits {
+        11 => 3,
+        14 => 2,
+        _ => return "*".repeat(document.chars().count()),
+    };
+
+    let mut position = 0;
+    document
+        .chars()
+        .map(|c| {
+            if !c.is_ascii_digit() {
+                return c;
+            }
+            position += 1;
+            if position <= hidden_prefix || position > digits - 2 {
+                '*'
+            } else {
+                c
+            }
+        })
+        .collect()
+}
+
 pub fn random_hash() -> String {
     let now = chrono::Utc::now().to_string();
     bcrypt::hash(now).unwrap()
 }
 
-pub fn password_hash(input: &str) -> String {
-    let output = bcrypt::hash(input).unwrap();
-    output
-}
-
 pub fn random_public_id() -> i32 {
-    let output = rand::thread_rng().gen_range(1000000..9999999);
-    output
+    rand::thread_rng().gen_range(1000000..9999999)
 }
 
 pub fn get_database_url_from_env() -> Result<String, (StatusCode, Json<String>)> {
@@ -133,12 +159,10 @@ pub fn get_database_url_from_env() -> Result<String, (StatusCode, Json<String>)>
 
     match env::var("DATABASE_URL") {
         Ok(secret) => Ok(secret),
-        Err(error) => {
-            return Err((
-                StatusCode::SERVICE_UNAVAILABLE,
-                Json(ApiError::DatabaseConnection(error.to_string()).to_string()),
-            ));
-        }
+        Err(error) => Err((
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ApiError::DatabaseConnection(error.to_string()).to_string()),
+        )),
     }
 }
 
@@ -147,12 +171,10 @@ pub fn get_frontend_url_from_env() -> Result<String, (StatusCode, Json<String>)>
 
     match env::var("FRONTEND_URL") {
         Ok(secret) => Ok(secret),
-        Err(_) => {
-            return Err((
-                StatusCode::SERVICE_UNAVAILABLE,
-                Json(ApiError::FrontendUrl.to_string()),
-            ));
-        }
+        Err(_) => Err((
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ApiError::FrontendUrl.to_string()),
+        )),
     }
 }
 
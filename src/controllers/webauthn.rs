@@ -0,0 +1,674 @@
+use std::{env, sync::LazyLock};
+
+use axum::{
+    Json,
+    extract::{Path, State},
+};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use ciborium::Value;
+use diesel_async::{AsyncPgConnection, pooled_connection::deadpool::Pool};
+use dotenvy::dotenv;
+use hyper::StatusCode;
+use jsonwebtoken::{Algorithm, DecodingKey};
+use p256::ecdsa::{Signature, VerifyingKey, signature::Verifier};
+use rand::Rng;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::{
+    controllers::{
+        auth::CurrentUser,
+        session::{ClientInfo, hash_token, start_session},
+        utils::get_conn,
+    },
+    models::{
+        self,
+        error::ApiError,
+        session::TokenPair,
+        user::PASSKEY_LOGIN_TYPE,
+        webauthn::{
+            AuthenticationCredential, AuthenticatorSelection, CredentialDescriptor,
+            CredentialParameters, PasskeyInfo, PublicKeyCredentialCreationOptions,
+            PublicKeyCredentialRequestOptions, RegisterPasskeyInput, RelyingParty,
+            WebauthnChallenge, WebauthnCredential, WebauthnUser,
+        },
+    },
+};
+
+const CHALLENGE_EXPIRATION_SECONDS: i64 = 300;
+const CHALLENGE_BYTES: usize = 32;
+
+const REGISTRATION_CEREMONY: &str = "registration";
+const AUTHENTICATION_CEREMONY: &str = "authentication";
+
+const DEFAULT_PASSKEY_NAME: &str = "Passkey";
+const MAX_PASSKEY_NAME_LENGTH: usize = 64;
+
+const FLAG_USER_PRESENT: u8 = 0x01;
+const FLAG_USER_VERIFIED: u8 = 0x04;
+const FLAG_ATTESTED_CREDENTIAL_DATA: u8 = 0x40;
+
+// COSE key labels and values (RFC 9053).
+const COSE_KEY_TYPE: i64 = 1;
+const COSE_ALGORITHM: i64 = 3;
+const COSE_CURVE: i64 = -1;
+const COSE_X: i64 = -2;
+const COSE_Y: i64 = -3;
+const COSE_RSA_N: i64 = -1;
+const COSE_RSA_E: i64 = -2;
+
+const COSE_KTY_OKP: i64 = 1;
+const COSE_KTY_EC2: i64 = 2;
+const COSE_KTY_RSA: i64 = 3;
+const COSE_CRV_P256: i64 = 1;
+const COSE_CRV_ED25519: i64 = 6;
+
+const COSE_ALG_ES256: i64 = -7;
+const COSE_ALG_EDDSA: i64 = -8;
+const COSE_ALG_RS256: i64 = -257;
+
+struct RelyingPartyConfig {
+    id: String,
+    name: String,
+    origins: Vec<String>,
+}
+
+static RELYING_PARTY: LazyLock<Result<RelyingPartyConfig, String>> =
+    LazyLock::new(load_relying_party);
+
+/// Reads the relying party from the environment.
+///
+/// - `WEBAUTHN_ORIGINS`: comma separated origins the ceremonies may run on, defaulting to
+///   `FRONTEND_URL`.
+/// - `WEBAUTHN_RP_ID`: domain the passkeys are bound to, defaulting to the host of the first
+///   origin.
+/// - `WEBAUTHN_RP_NAME`: name shown by the authenticator, defaulting to `Rust Backend`.
+fn load_relying_party() -> Result<RelyingPartyConfig, String> {
+    dotenv().ok();
+
+    let origins: Vec<String> = env::var("WEBAUTHN_ORIGINS")
+        .or_else(|_| env::var("FRONTEND_URL"))
+        .map_err(|_| "Missing WEBAUTHN_ORIGINS".to_string())?
+        .split(',')
+        .map(|origin| origin.trim().trim_end_matches('/').to_string())
+        .filter(|origin| !origin.is_empty())
+        .collect();
+
+    let first = origins.first().ok_or("Missing WEBAUTHN_ORIGINS")?;
+
+    let id = match env::var("WEBAUTHN_RP_ID") {
+        Ok(id) => id.trim().to_string(),
+        Err(_) => reqwest::Url::parse(first)
+            .map_err(|e| format!("Invalid WEBAUTHN_ORIGINS: {}", e))?
+            .host_str()
+            .ok_or("WEBAUTHN_ORIGINS must include a host")?
+            .to_string(),
+    };
+
+    let name = env::var("WEBAUTHN_RP_NAME").unwrap_or_else(|_| "Rust Backend".to_string());
+
+    Ok(RelyingPartyConfig { id, name, origins })
+}
+
+fn get_relying_party() -> Result<&'static RelyingPartyConfig, (StatusCode, Json<String>)> {
+    RELYING_PARTY.as_ref().map_err(|e| {
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ApiError::WebauthnConfiguration(e.to_string()).to_string()),
+        )
+    })
+}
+
+/// A credential public key decoded from its COSE form.
+enum PasskeyKey {
+    Es256(VerifyingKey),
+    EdDsa(DecodingKey),
+    Rs256(DecodingKey),
+}
+
+impl PasskeyKey {
+    fn verify(&self, message: &[u8], signature: &[u8]) -> bool {
+        match self {
+            // WebAuthn ECDSA signatures are DER encoded.
+            PasskeyKey::Es256(key) => match Signature::from_der(signature) {
+                Ok(signature) => key.verify(message, &signature).is_ok(),
+                Err(_) => false,
+            },
+            PasskeyKey::EdDsa(key) => verify_raw(key, Algorithm::EdDSA, message, signature),
+            PasskeyKey::Rs256(key) => verify_raw(key, Algorithm::RS256, message, signature),
+        }
+    }
+}
+
+fn verify_raw(key: &DecodingKey, algorithm: Algorithm, message: &[u8], signature: &[u8]) -> bool {
+    jsonwebtoken::crypto::verify(&URL_SAFE_NO_PAD.encode(signature), message, key, algorithm)
+        .unwrap_or(false)
+}
+
+fn cose_value(map: &[(Value, Value)], label: i64) -> Option<&Value> {
+    map.iter()
+        .find(|(key, _)| {
+            key.as_integer()
+                .is_some_and(|key| i128::from(key) == label as i128)
+        })
+        .map(|(_, value)| value)
+}
+
+fn cose_integer(map: &[(Value, Value)], label: i64) -> Option<i64> {
+    cose_value(map, label)?
+        .as_integer()
+        .and_then(|value| i64::try_from(value).ok())
+}
+
+fn cose_bytes(map: &[(Value, Value)], label: i64) -> Option<&[u8]> {
+    cose_value(map, label)?.as_bytes().map(Vec::as_slice)
+}
+
+/// Decodes an ES256, EdDSA or RS256 COSE key; other algorithms aren't offered at registration.
+fn parse_cose_key(bytes: &[u8]) -> Option<PasskeyKey> {
+    let value: Value = ciborium::from_reader(bytes).ok()?;
+    let map = value.as_map()?;
+
+    match (
+        cose_integer(map, COSE_KEY_TYPE)?,
+        cose_integer(map, COSE_ALGORITHM)?,
+    ) {
+        (COSE_KTY_EC2, COSE_ALG_ES256) if cose_integer(map, COSE_CURVE)? == COSE_CRV_P256 => {
+            let mut point = vec![0x04];
+            point.extend_from_slice(cose_bytes(map, COSE_X)?);
+            point.extend_from_slice(cose_bytes(map, COSE_Y)?);
+            VerifyingKey::from_sec1_bytes(&point)
+                .ok()
+                .map(PasskeyKey::Es256)
+        }
+        (COSE_KTY_OKP, COSE_ALG_EDDSA) if cose_integer(map, COSE_CURVE)? == COSE_CRV_ED25519 => {
+            let x = cose_bytes(map, COSE_X)?;
+            if x.len() != 32 {
+                return None;
+            }
+            DecodingKey::from_ed_components(&URL_SAFE_NO_PAD.encode(x))
+                .ok()
+                .map(PasskeyKey::EdDsa)
+        }
+        (COSE_KTY_RSA, COSE_ALG_RS256) => {
+            Some(PasskeyKey::Rs256(DecodingKey::from_rsa_raw_components(
+                cose_bytes(map, COSE_RSA_N)?,
+                cose_bytes(map, COSE_RSA_E)?,
+            )))
+        }
+        _ => None,
+    }
+}
+
+struct AuthenticatorData {
+    rp_id_hash: Vec<u8>,
+    flags: u8,
+    sign_count: u32,
+    /// Only present on registration.
+    credential: Option<AttestedCredential>,
+}
+
+struct AttestedCredential {
+    credential_id: Vec<u8>,
+    /// The COSE key, re-encoded on its own without any extensions that followed it.
+    public_key: Vec<u8>,
+}
+
+/// Parses the binary authenticator data: the RP ID hash, flags, signature counter and, when the
+/// `AT` flag is set, the AAGUID followed by the length-prefixed credential id and its COSE key.
+fn parse_authenticator_data(data: &[u8]) -> Option<AuthenticatorData> {
+    let rp_id_hash = data.get(..32)?.to_vec();
+    let flags = *data.get(32)?;
+    let sign_count = u32::from_be_bytes(data.get(33..37)?.try_into().ok()?);
+
+    let credential = if flags & FLAG_ATTESTED_CREDENTIAL_DATA != 0 {
+        let attested = data.get(37..)?;
+        let length = u16::from_be_bytes(attested.get(16..18)?.try_into().ok()?) as usize;
+        let credential_id = attested.get(18..18 + length)?.to_vec();
+
+        let mut key_bytes = attested.get(18 + length..)?;
+        let key: Value = ciborium::from_reader(&mut key_bytes).ok()?;
+        let mut public_key = Vec::new();
+        ciborium::into_writer(&key, &mut public_key).ok()?;
+
+        Some(AttestedCredential {
+            credential_id,
+            public_key,
+        })
+    } else {
+        None
+    };
+
+    Some(AuthenticatorData {
+        rp_id_hash,
+        flags,
+        sign_count,
+        credential,
+    })
+}
+
+/// Returns the authenticator data of an attestation object. Attestation statements aren't
+/// checked: registration asks for `none`, as passkeys are trusted like any other authenticator.
+fn parse_attestation_object(bytes: &[u8]) -> Option<Vec<u8>> {
+    let value: Value = ciborium::from_reader(bytes).ok()?;
+
+    value
+        .as_map()?
+        .iter()
+        .find(|(key, _)| key.as_text() == Some("authData"))
+        .and_then(|(_, value)| value.as_bytes())
+        .cloned()
+}
+
+/// Whether the data was produced for this relying party, with the user present and verified.
+fn is_valid_assertion_data(rp: &RelyingPartyConfig, data: &AuthenticatorData) -> bool {
+    data.rp_id_hash == Sha256::digest(rp.id.as_bytes()).as_slice()
+        && data.flags & FLAG_USER_PRESENT != 0
+        && data.flags & FLAG_USER_VERIFIED != 0
+}
+
+#[derive(Deserialize)]
+struct ClientData {
+    #[serde(rename = "type")]
+    ceremony_type: String,
+    challenge: String,
+    origin: String,
+    #[serde(rename = "crossOrigin", default)]
+    cross_origin: bool,
+}
+
+/// Checks the client data of a ceremony answer and redeems the challenge it signed.
+async fn verify_client_data(
+    conn: &mut AsyncPgConnection,
+    rp: &RelyingPartyConfig,
+    client_data_json: &[u8],
+    ceremony_type: &str,
+    ceremony: &str,
+) -> Result<WebauthnChallenge, (StatusCode, Json<String>)> {
+    let invalid_challenge = || {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(ApiError::InvalidWebauthnChallenge.to_string()),
+        )
+    };
+
+    let client_data: ClientData =
+        serde_json::from_slice(client_data_json).map_err(|_| invalid_challenge())?;
+
+    if client_data.ceremony_type != ceremony_type
+        || client_data.cross_origin
+        || !rp.origins.contains(&client_data.origin)
+    {
+        return Err(invalid_challenge());
+    }
+
+    let challenge =
+        match models::webauthn::take_webauthn_challenge(conn, &hash_token(&client_data.challenge))
+            .await
+        {
+            Ok(Some(challenge)) => challenge,
+            Ok(None) => return Err(invalid_challenge()),
+            Err(e) => return Err((StatusCode::INTERNAL_SERVER_ERROR, Json(e.to_string()))),
+        };
+
+    if challenge.ceremony != ceremony || challenge.expiration_date < chrono::Utc::now().naive_utc()
+    {
+        return Err(invalid_challenge());
+    }
+
+    Ok(challenge)
+}
+
+/// Stores a new challenge for the ceremony and returns it base64url encoded.
+async fn create_challenge(
+    conn: &mut AsyncPgConnection,
+    ceremony: &str,
+    user_id: Option<Uuid>,
+) -> Result<String, (StatusCode, Json<String>)> {
+    if let Err(e) = models::webauthn::delete_expired_webauthn_challenges(conn).await {
+        return Err((StatusCode::INTERNAL_SERVER_ERROR, Json(e.to_string())));
+    }
+
+    let mut bytes = [0u8; CHALLENGE_BYTES];
+    rand::thread_rng().fill(&mut bytes);
+    let challenge = URL_SAFE_NO_PAD.encode(bytes);
+
+    let now = chrono::Utc::now().naive_utc();
+    let row = WebauthnChallenge {
+        challenge_hash: hash_token(&challenge),
+        ceremony: ceremony.to_string(),
+        user_id,
+        create_date: now,
+        expiration_date: now + chrono::Duration::seconds(CHALLENGE_EXPIRATION_SECONDS),
+    };
+
+    match models::webauthn::create_webauthn_challenge(conn, &row).await {
+        Ok(_) => Ok(challenge),
+        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, Json(e.to_string()))),
+    }
+}
+
+fn public_key_descriptor(credential_id: &[u8]) -> CredentialDescriptor {
+    CredentialDescriptor {
+        credential_type: "public-key".to_string(),
+        id: URL_SAFE_NO_PAD.encode(credential_id),
+    }
+}
+
+/// Starts a passkey registration for the signed-in user. The options are meant for
+/// `navigator.credentials.create()`; its answer goes to `/user/webauthn/register`.
+#[utoipa::path(post, path = "/user/webauthn/register/options", responses((status = OK, body = PublicKeyCredentialCreationOptions)), security(("bearer_auth" = ["profile:write"])))]
+pub async fn api_webauthn_register_options(
+    State(pool): State<Pool<AsyncPgConnection>>,
+    CurrentUser { user, .. }: CurrentUser,
+) -> Result<(StatusCode, Json<PublicKeyCredentialCreationOptions>), (StatusCode, Json<String>)> {
+    let rp = get_relying_party()?;
+    let conn = &mut get_conn(&pool).await?;
+
+    let credentials = match models::webauthn::list_webauthn_credentials(conn, &user.id).await {
+        Ok(credentials) => credentials,
+        Err(e) => return Err((StatusCode::INTERNAL_SERVER_ERROR, Json(e.to_string()))),
+    };
+
+    let challenge = create_challenge(conn, REGISTRATION_CEREMONY, Some(user.id)).await?;
+
+    let pub_key_cred_params = [COSE_ALG_ES256, COSE_ALG_EDDSA, COSE_ALG_RS256]
+        .into_iter()
+        .map(|alg| CredentialParameters {
+            credential_type: "public-key".to_string(),
+            alg,
+        })
+        .collect();
+
+    Ok((
+        StatusCode::OK,
+        Json(PublicKeyCredentialCreationOptions {
+            rp: RelyingParty {
+                id: rp.id.clone(),
+                name: rp.name.clone(),
+            },
+            user: WebauthnUser {
+                id: URL_SAFE_NO_PAD.encode(user.id.as_bytes()),
+                name: user.email.clone(),
+                display_name: user.name.clone(),
+            },
+            challenge,
+            pub_key_cred_params,
+            timeout: CHALLENGE_EXPIRATION_SECONDS * 1000,
+            exclude_credentials: credentials
+                .iter()
+                .map(|credential| public_key_descriptor(&credential.credential_id))
+                .collect(),
+            authenticator_selection: AuthenticatorSelection {
+                resident_key: "required".to_string(),
+                require_resident_key: true,
+                user_verification: "required".to_string(),
+            },
+            attestation: "none".to_string(),
+        }),
+    ))
+}
+
+/// Verifies the answer of `navigator.credentials.create()` and stores the new passkey.
+#[utoipa::path(post, path = "/user/webauthn/register", request_body = RegisterPasskeyInput, responses((status = CREATED, body = PasskeyInfo)), security(("bearer_auth" = ["profile:write"])))]
+pub async fn api_webauthn_register(
+    State(pool): State<Pool<AsyncPgConnection>>,
+    CurrentUser { user, .. }: CurrentUser,
+    input: Json<RegisterPasskeyInput>,
+) -> Result<(StatusCode, Json<PasskeyInfo>), (StatusCode, Json<String>)> {
+    let input = input.0;
+    let rp = get_relying_party()?;
+
+    let invalid_passkey = || {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(ApiError::InvalidPasskey.to_string()),
+        )
+    };
+
+    let response = &input.credential.response;
+    let client_data_json = URL_SAFE_NO_PAD
+        .decode(&response.client_data_json)
+        .map_err(|_| invalid_passkey())?;
+    let attestation_object = URL_SAFE_NO_PAD
+        .decode(&response.attestation_object)
+        .map_err(|_| invalid_passkey())?;
+    let raw_id = URL_SAFE_NO_PAD
+        .decode(&input.credential.raw_id)
+        .map_err(|_| invalid_passkey())?;
+
+    let conn = &mut get_conn(&pool).await?;
+
+    let challenge = verify_client_data(
+        conn,
+        rp,
+        &client_data_json,
+        "webauthn.create",
+        REGISTRATION_CEREMONY,
+    )
+    .await?;
+    if challenge.user_id != Some(user.id) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ApiError::InvalidWebauthnChallenge.to_string()),
+        ));
+    }
+
+    let data = parse_attestation_object(&attestation_object)
+        .and_then(|auth_data| parse_authenticator_data(&auth_data))
+        .ok_or_else(invalid_passkey)?;
+    if !is_valid_assertion_data(rp, &data) {
+        return Err(invalid_passkey());
+    }
+
+    let attested = data.credential.ok_or_else(invalid_passkey)?;
+    if attested.credential_id != raw_id || parse_cose_key(&attested.public_key).is_none() {
+        return Err(invalid_passkey());
+    }
+
+    match models::webauthn::find_webauthn_credential(conn, &attested.credential_id).await {
+        Ok(None) => {}
+        Ok(Some(_)) => {
+            return Err((
+                StatusCode::CONFLICT,
+                Json(ApiError::PasskeyAlreadyRegistered.to_string()),
+            ));
+        }
+        Err(e) => return Err((StatusCode::INTERNAL_SERVER_ERROR, Json(e.to_string()))),
+    }
+
+    let name: String = input
+        .name
+        .as_deref()
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .unwrap_or(DEFAULT_PASSKEY_NAME)
+        .chars()
+        .take(MAX_PASSKEY_NAME_LENGTH)
+        .collect();
+
+    let credential = WebauthnCredential {
+        id: Uuid::new_v4(),
+        user_id: user.id,
+        credential_id: attested.credential_id,
+        public_key: attested.public_key,
+        sign_count: data.sign_count as i64,
+        name,
+        create_date: chrono::Utc::now().naive_utc(),
+        last_used_date: None,
+    };
+
+    if let Err(e) = models::webauthn::create_webauthn_credential(conn, &credential).await {
+        return Err((StatusCode::INTERNAL_SERVER_ERROR, Json(e.to_string())));
+    }
+
+    Ok((StatusCode::CREATED, Json(PasskeyInfo::from(credential))))
+}
+
+#[utoipa::path(get, path = "/user/webauthn/credentials", responses((status = OK, body = Vec<PasskeyInfo>)), security(("bearer_auth" = ["profile:read"]), ("api_key" = ["profile:read"])))]
+pub async fn api_list_passkeys(
+    State(pool): State<Pool<AsyncPgConnection>>,
+    CurrentUser { user, .. }: CurrentUser,
+) -> Result<(StatusCode, Json<Vec<PasskeyInfo>>), (StatusCode, Json<String>)> {
+    let conn = &mut get_conn(&pool).await?;
+
+    match models::webauthn::list_webauthn_credentials(conn, &user.id).await {
+        Ok(credentials) => Ok((
+            StatusCode::OK,
+            Json(credentials.into_iter().map(PasskeyInfo::from).collect()),
+        )),
+        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, Json(e.to_string()))),
+    }
+}
+
+#[utoipa::path(delete, path = "/user/webauthn/credentials/{id}", params(("id" = Uuid, Path)), responses((status = NO_CONTENT)), security(("bearer_auth" = ["profile:write"])))]
+pub async fn api_delete_passkey(
+    State(pool): State<Pool<AsyncPgConnection>>,
+    CurrentUser { user, .. }: CurrentUser,
+    Path(id): Path<Uuid>,
+) -> Result<StatusCode, (StatusCode, Json<String>)> {
+    let conn = &mut get_conn(&pool).await?;
+
+    match models::webauthn::delete_webauthn_credential(conn, &user.id, &id).await {
+        Ok(true) => Ok(StatusCode::NO_CONTENT),
+        Ok(false) => Err((
+            StatusCode::NOT_FOUND,
+            Json(ApiError::PasskeyNotFound.to_string()),
+        )),
+        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, Json(e.to_string()))),
+    }
+}
+
+/// Starts a passkey login. No account is named up front: the browser offers the passkeys it has
+/// for this site and the chosen credential identifies the user.
+#[utoipa::path(post, path = "/user/webauthn/login/options", responses((status = OK, body = PublicKeyCredentialRequestOptions)))]
+pub async fn api_webauthn_login_options(
+    State(pool): State<Pool<AsyncPgConnection>>,
+) -> Result<(StatusCode, Json<PublicKeyCredentialRequestOptions>), (StatusCode, Json<String>)> {
+    let rp = get_relying_party()?;
+    let conn = &mut get_conn(&pool).await?;
+
+    let challenge = create_challenge(conn, AUTHENTICATION_CEREMONY, None).await?;
+
+    Ok((
+        StatusCode::OK,
+        Json(PublicKeyCredentialRequestOptions {
+            challenge,
+            timeout: CHALLENGE_EXPIRATION_SECONDS * 1000,
+            rp_id: rp.id.clone(),
+            allow_credentials: Vec::new(),
+            user_verification: "required".to_string(),
+        }),
+    ))
+}
+
+/// Verifies the answer of `navigator.credentials.get()` and starts a session. A passkey with user
+/// verification already combines possession and a PIN or biometric, so TOTP isn't asked for.
+#[utoipa::path(post, path = "/user/webauthn/login", request_body = AuthenticationCredential, responses((status = OK, body = TokenPair)))]
+pub async fn api_webauthn_login(
+    State(pool): State<Pool<AsyncPgConnection>>,
+    client: ClientInfo,
+    input: Json<AuthenticationCredential>,
+) -> Result<(StatusCode, Json<TokenPair>), (StatusCode, Json<String>)> {
+    let input = input.0;
+    let rp = get_relying_party()?;
+
+    let invalid_passkey = || {
+        (
+            StatusCode::UNAUTHORIZED,
+            Json(ApiError::InvalidPasskey.to_string()),
+        )
+    };
+
+    let response = &input.response;
+    let client_data_json = URL_SAFE_NO_PAD
+        .decode(&response.client_data_json)
+        .map_err(|_| invalid_passkey())?;
+    let authenticator_data = URL_SAFE_NO_PAD
+        .decode(&response.authenticator_data)
+        .map_err(|_| invalid_passkey())?;
+    let signature = URL_SAFE_NO_PAD
+        .decode(&response.signature)
+        .map_err(|_| invalid_passkey())?;
+    let raw_id = URL_SAFE_NO_PAD
+        .decode(&input.raw_id)
+        .map_err(|_| invalid_passkey())?;
+
+    let conn = &mut get_conn(&pool).await?;
+
+    verify_client_data(
+        conn,
+        rp,
+        &client_data_json,
+        "webauthn.get",
+        AUTHENTICATION_CEREMONY,
+    )
+    .await?;
+
+    let credential = match models::webauthn::find_webauthn_credential(conn, &raw_id).await {
+        Ok(Some(credential)) => credential,
+        Ok(None) => return Err(invalid_passkey()),
+        Err(e) => return Err((StatusCode::INTERNAL_SERVER_ERROR, Json(e.to_string()))),
+    };
+
+    if let Some(user_handle) = &response.user_handle {
+        let user_handle = URL_SAFE_NO_PAD
+            .decode(user_handle)
+            .map_err(|_| invalid_passkey())?;
+        if user_handle != credential.user_id.as_bytes() {
+            return Err(invalid_passkey());
+        }
+    }
+
+    let data = parse_authenticator_data(&authenticator_data).ok_or_else(invalid_passkey)?;
+    if !is_valid_assertion_data(rp, &data) {
+        return Err(invalid_passkey());
+    }
+
+    let key = parse_cose_key(&credential.public_key).ok_or_else(invalid_passkey)?;
+    let mut signed = authenticator_data.clone();
+    signed.extend_from_slice(&Sha256::digest(&client_data_json));
+    if !key.verify(&signed, &signature) {
+        return Err(invalid_passkey());
+    }
+
+    // Authenticators that keep a counter must increase it on every use; one going backwards
+    // means the credential was cloned. Synced passkeys always report zero.
+    let sign_count = data.sign_count as i64;
+    if (sign_count != 0 || credential.sign_count != 0) && sign_count <= credential.sign_count {
+        tracing::warn!(
+            "Passkey {} signature counter went backwards, rejecting the login",
+            credential.id
+        );
+        return Err(invalid_passkey());
+    }
+
+    match models::webauthn::update_webauthn_sign_count(
+        conn,
+        &credential.id,
+        credential.sign_count,
+        sign_count,
+    )
+    .await
+    {
+        Ok(true) => {}
+        Ok(false) => return Err(invalid_passkey()),
+        Err(e) => return Err((StatusCode::INTERNAL_SERVER_ERROR, Json(e.to_string()))),
+    }
+
+    let user = match models::user::find_user_by_id(conn, &credential.user_id).await {
+        Ok(user) => user,
+        Err(_) => return Err(invalid_passkey()),
+    };
+
+    if !user.is_active || user.deletion_date.is_some() {
+        return Err((
+            StatusCode::FORBIDDEN,
+            Json(ApiError::NotActiveUser.to_string()),
+        ));
+    }
+
+    let tokens = start_session(conn, &user, PASSKEY_LOGIN_TYPE, &client).await?;
+    Ok((StatusCode::OK, Json(tokens)))
+}
@@ -3,6 +3,9 @@ pub mod models;
 pub mod routes;
 pub mod schema;
 
+#[cfg(test)]
+mod tests;
+
 #[tokio::main(flavor = "multi_thread", worker_threads = 4)]
 async fn main() {
     tracing_subscriber::fmt::init();
@@ -0,0 +1,171 @@
+use crate::{models::error::ApiError, schema::api_keys};
+use chrono::NaiveDateTime;
+use diesel::{
+    BoolExpressionMethods, ExpressionMethods, OptionalExtension, QueryDsl,
+    prelude::{Insertable, Queryable},
+};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+/// A long-lived credential owned by a user, meant for scripts and batch jobs. Only the hash of
+/// the key is stored; `prefix` is the public part used to find it.
+#[derive(Queryable, Insertable, Debug, Clone)]
+#[diesel(table_name = api_keys)]
+pub struct ApiKey {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub name: String,
+    pub prefix: String,
+    pub key_hash: String,
+    /// Space separated scopes the key is limited to; `None` when it isn't restricted.
+    pub scopes: Option<String>,
+    pub create_date: NaiveDateTime,
+    pub expiration_date: Option<NaiveDateTime>,
+    pub last_used_date: Option<NaiveDateTime>,
+    pub revocation_date: Option<NaiveDateTime>,
+}
+
+impl ApiKey {
+    pub fn scope_list(&self) -> Option<Vec<String>> {
+        self.scopes
+            .as_ref()
+            .map(|scopes| scopes.split_whitespace().map(str::to_string).collect())
+    }
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct CreateApiKeyInput {
+    pub name: String,
+    /// Limits the key to these scopes. Leave it out for a key that can do anything you can.
+    pub scopes: Option<Vec<String>>,
+    /// Days until the key stops working, from 1 to 3650. Leave it out for a key that doesn't
+    /// expire.
+    pub expires_in_days: Option<i64>,
+}
+
+/// Answer of the key creation, the only time the full `key` is shown.
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct CreatedApiKey {
+    pub id: Uuid,
+    pub name: String,
+    pub key: String,
+    pub scopes: Option<Vec<String>>,
+    pub expiration_date: Option<NaiveDateTime>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct ApiKeyInfo {
+    pub id: Uuid,
+    pub name: String,
+    pub prefix: String,
+    pub scopes: Option<Vec<String>>,
+    pub create_date: NaiveDateTime,
+    pub expiration_date: Option<NaiveDateTime>,
+    pub last_used_date: Option<NaiveDateTime>,
+    pub revocation_date: Option<NaiveDateTime>,
+}
+
+impl From<ApiKey> for ApiKeyInfo {
+    fn from(input: ApiKey) -> Self {
+        Self {
+            scopes: input.scope_list(),
+            id: input.id,
+            name: input.name,
+            prefix: input.prefix,
+            create_date: input.create_date,
+            expiration_date: input.expiration_date,
+            last_used_date: input.last_used_date,
+            revocation_date: input.revocation_date,
+        }
+    }
+}
+
+pub async fn create_api_key(conn: &mut AsyncPgConnection, key: &ApiKey) -> Result<(), ApiError> {
+    use crate::schema::api_keys::dsl::*;
+
+    match diesel::insert_into(api_keys)
+        .values(key)
+        .execute(conn)
+        .await
+    {
+        Ok(_) => Ok(()),
+        Err(e) => Err(ApiError::Database(e.to_string())),
+    }
+}
+
+pub async fn find_api_key_by_prefix(
+    conn: &mut AsyncPgConnection,
+    param: &str,
+) -> Result<Option<ApiKey>, ApiError> {
+    use crate::schema::api_keys::dsl::*;
+
+    match api_keys
+        .filter(prefix.eq(param))
+        .get_result(conn)
+        .await
+        .optional()
+    {
+        Ok(key) => Ok(key),
+        Err(e) => Err(ApiError::Database(e.to_string())),
+    }
+}
+
+pub async fn list_api_keys(
+    conn: &mut AsyncPgConnection,
+    user_id_param: &Uuid,
+) -> Result<Vec<ApiKey>, ApiError> {
+    use crate::schema::api_keys::dsl::*;
+
+    match api_keys
+        .filter(user_id.eq(user_id_param))
+        .order(create_date.desc())
+        .load(conn)
+        .await
+    {
+        Ok(keys) => Ok(keys),
+        Err(e) => Err(ApiError::Database(e.to_string())),
+    }
+}
+
+/// Revokes one of the user's keys, returning `false` when it doesn't exist or was already revoked.
+pub async fn revoke_api_key(
+    conn: &mut AsyncPgConnection,
+    user_id_param: &Uuid,
+    id_param: &Uuid,
+) -> Result<bool, ApiError> {
+    use crate::schema::api_keys::dsl::*;
+
+    match diesel::update(api_keys)
+        .filter(id.eq(id_param))
+        .filter(user_id.eq(user_id_param))
+        .filter(revocation_date.is_null())
+        .set(revocation_date.eq(chrono::Utc::now().naive_utc()))
+        .execute(conn)
+        .await
+    {
+        Ok(rows) => Ok(rows == 1),
+        Err(e) => Err(ApiError::Database(e.to_string())),
+    }
+}
+
+/// Records the key as used, skipping the write when it was already recorded after `since`.
+pub async fn touch_api_key(
+    conn: &mut AsyncPgConnection,
+    id_param: &Uuid,
+    since: NaiveDateTime,
+) -> Result<(), ApiError> {
+    use crate::schema::api_keys::dsl::*;
+
+    match diesel::update(api_keys)
+        .filter(id.eq(id_param))
+        .filter(last_used_date.is_null().or(last_used_date.lt(since)))
+        .set(last_used_date.eq(chrono::Utc::now().naive_utc()))
+        .execute(conn)
+        .await
+    {
+        Ok(_) => Ok(()),
+        Err(e) => Err(ApiError::Database(e.to_string())),
+    }
+}
@@ -0,0 +1,139 @@
+use crate::{models::error::ApiError, schema::audit_events};
+use chrono::NaiveDateTime;
+use diesel::{
+    BoolExpressionMethods, ExpressionMethods, QueryDsl,
+    prelude::{Insertable, Queryable},
+};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+
+pub const IMPERSONATION_STARTED: &str = "impersonation.started";
+pub const IMPERSONATION_ENDED: &str = "impersonation.ended";
+pub const ACCOUNT_DELETED: &str = "account.deleted";
+pub const ACCOUNT_RESTORED: &str = "account.restored";
+pub const EMAIL_CHANGED: &str = "account.email_changed";
+pub const USER_ACTIVATED: &str = "user.activated";
+pub const USER_DEACTIVATED: &str = "user.deactivated";
+pub const USER_ROLE_CHANGED: &str = "user.role_changed";
+pub const USER_PASSWORD_RESET_FORCED: &str = "user.password_reset_forced";
+pub const USER_RESTORED: &str = "user.restored";
+pub const USER_UNLOCKED: &str = "user.unlocked";
+
+/// Something a user did that has to be traceable later, usually to another user's account.
+#[derive(Queryable, Insertable, Debug, Clone)]
+#[diesel(table_name = audit_events)]
+pub struct AuditEvent {
+    pub id: Uuid,
+    pub actor_id: Option<Uuid>,
+    pub target_user_id: Option<Uuid>,
+    pub action: String,
+    pub details: Option<serde_json::Value>,
+    pub create_date: NaiveDateTime,
+}
+
+pub async fn create_audit_event(
+    conn: &mut AsyncPgConnection,
+    event: &AuditEvent,
+) -> Result<(), ApiError> {
+    use crate::schema::audit_events::dsl::*;
+
+    match diesel::insert_into(audit_events)
+        .values(event)
+        .execute(conn)
+        .await
+    {
+        Ok(_) => Ok(()),
+        Err(e) => Err(ApiError::Database(e.to_string())),
+    }
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct AuditEventInfo {
+    pub id: Uuid,
+    pub actor_id: Option<Uuid>,
+    pub target_user_id: Option<Uuid>,
+    pub action: String,
+    #[schema(value_type = Option<Object>)]
+    pub details: Option<serde_json::Value>,
+    pub create_date: NaiveDateTime,
+}
+
+impl From<AuditEvent> for AuditEventInfo {
+    fn from(input: AuditEvent) -> Self {
+        Self {
+            id: input.id,
+            actor_id: input.actor_id,
+            target_user_id: input.target_user_id,
+            action: input.action,
+            details: input.details,
+            create_date: input.create_date,
+        }
+    }
+}
+
+/// Filters of `GET /admin/audit-events`. Every filter is optional and they all have to match.
+#[derive(Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AuditEventFilter {
+    pub actor_id: Option<Uuid>,
+    pub target_user_id: Option<Uuid>,
+    /// E.g. `user.role_changed`.
+    pub action: Option<String>,
+}
+
+/// Position of an event in [`list_audit_events`], used as its page cursor.
+#[derive(Serialize, Deserialize)]
+pub struct AuditEventKey {
+    pub create_date: NaiveDateTime,
+    pub id: Uuid,
+}
+
+impl From<&AuditEvent> for AuditEventKey {
+    fn from(event: &AuditEvent) -> Self {
+        Self {
+            create_date: event.create_date,
+            id: event.id,
+        }
+    }
+}
+
+/// Events matching the filter, newest first, starting after `after`.
+pub async fn list_audit_events(
+    conn: &mut AsyncPgConnection,
+    filter: &AuditEventFilter,
+    after: Option<&AuditEventKey>,
+    limit: i64,
+) -> Result<Vec<AuditEvent>, ApiError> {
+    use crate::schema::audit_events::dsl::*;
+
+    let mut query = audit_events.into_boxed();
+
+    if let Some(actor) = filter.actor_id {
+        query = query.filter(actor_id.eq(actor));
+    }
+    if let Some(target) = filter.target_user_id {
+        query = query.filter(target_user_id.eq(target));
+    }
+    if let Some(action_param) = &filter.action {
+        query = query.filter(action.eq(action_param));
+    }
+    if let Some(key) = after {
+        query = query.filter(
+            create_date
+                .lt(key.create_date)
+                .or(create_date.eq(key.create_date).and(id.lt(key.id))),
+        );
+    }
+
+    match query
+        .order((create_date.desc(), id.desc()))
+        .limit(limit)
+        .load(conn)
+        .await
+    {
+        Ok(events) => Ok(events),
+        Err(e) => Err(ApiError::Database(e.to_string())),
+    }
+}
@@ -12,6 +12,30 @@ pub enum ApiError {
     #[error("Invalid authorization token")]
     InvalidAuthorizationToken,
 
+    #[error("Authorization token has been revoked")]
+    RevokedAuthorizationToken,
+
+    #[error("You don't have permission to access this resource")]
+    Forbidden,
+
+    #[error("Missing the required scope: {0}")]
+    MissingScope(String),
+
+    #[error("These scopes can't be granted: {0:?}")]
+    InvalidScopes(Vec<String>),
+
+    #[error("Not allowed while impersonating a user")]
+    ImpersonationForbidden,
+
+    #[error("Not allowed with an API key")]
+    ApiKeyForbidden,
+
+    #[error("This user can't be impersonated")]
+    CannotImpersonate,
+
+    #[error("Admins can't do this to their own account")]
+    CannotModifySelf,
+
     #[error("Multiple errors while validating the authorization token: {0:?}")]
     MultipleAuthorizationErrors(Vec<String>),
 
@@ -21,12 +45,33 @@ pub enum ApiError {
     #[error("Failed to create token: {0}")]
     CreateToken(String),
 
+    #[error("Failed to load the JWT keys: {0}")]
+    JwtKeys(String),
+
     #[error("Missing fields in the request")]
     InvalidData,
 
+    #[error("Invalid query parameters: {0}")]
+    InvalidQuery(String),
+
+    #[error("Can't sort by {0}, use one of {1:?}")]
+    InvalidSortField(String, Vec<String>),
+
+    #[error("Invalid page cursor")]
+    InvalidCursor,
+
     #[error("Invalid email provided")]
     InvalidEmail,
 
+    #[error("This e-mail is already in use")]
+    EmailInUse,
+
+    #[error("This document is already in use")]
+    DocumentInUse,
+
+    #[error("No e-mail change is pending")]
+    NoPendingEmail,
+
     #[error("User not found by email")]
     EmailNotFound,
 
@@ -36,9 +81,105 @@ pub enum ApiError {
     #[error("Invalid password")]
     InvalidPassword,
 
+    #[error("Invalid email or password")]
+    InvalidCredentials,
+
+    #[error("Too many requests, slow down")]
+    RateLimited,
+
+    #[error("Too many failed login attempts, try again in {0} seconds")]
+    LoginLocked(i64),
+
+    #[error("Failed to hash the password: {0}")]
+    PasswordHash(String),
+
+    #[error("Password does not meet the policy: {0:?}")]
+    PasswordPolicy(Vec<String>),
+
+    #[error("The new password must be different from the current one")]
+    SamePassword,
+
+    #[error("Failed to configure MFA: {0}")]
+    MfaConfiguration(String),
+
+    #[error("Two-factor authentication is already enabled")]
+    MfaAlreadyEnabled,
+
+    #[error("Two-factor authentication is not enabled")]
+    MfaNotEnabled,
+
+    #[error("Invalid two-factor authentication code")]
+    InvalidMfaCode,
+
+    #[error("Failed to configure the OIDC providers: {0}")]
+    OidcConfiguration(String),
+
+    #[error("Unknown login provider")]
+    OidcProviderNotFound,
+
+    #[error("The login provider returned an error: {0}")]
+    OidcProvider(String),
+
+    #[error("Invalid or expired login state")]
+    InvalidOidcState,
+
+    #[error("Invalid ID token")]
+    InvalidIdToken,
+
+    #[error("An account with this e-mail already exists; sign in with your password to link it")]
+    OAuthAccountConflict,
+
+    #[error("Failed to configure WebAuthn: {0}")]
+    WebauthnConfiguration(String),
+
+    #[error("Invalid or expired passkey challenge")]
+    InvalidWebauthnChallenge,
+
+    #[error("Invalid passkey response")]
+    InvalidPasskey,
+
+    #[error("This passkey is already registered")]
+    PasskeyAlreadyRegistered,
+
+    #[error("Passkey not found")]
+    PasskeyNotFound,
+
     #[error("Missing frontend URL")]
     FrontendUrl,
 
+    #[error("E-mail address has not been verified")]
+    EmailNotVerified,
+
+    #[error("Invalid or expired token")]
+    InvalidUserToken,
+
+    #[error("Failed to configure the mailer: {0}")]
+    Mailer(String),
+
+    #[error("Failed to send e-mail: {0}")]
+    SendEmail(String),
+
     #[error("User not found")]
     UserNotFound,
+
+    #[error("Account is not scheduled for deletion")]
+    AccountNotDeleted,
+
+    #[error("The account can no longer be restored")]
+    RestorePeriodExpired,
+
+    #[error("Invalid or expired API key")]
+    InvalidApiKey,
+
+    #[error("API key not found")]
+    ApiKeyNotFound,
+
+    #[error("Session not found")]
+    SessionNotFound,
+
+    #[error("Invalid or expired refresh token")]
+    InvalidRefreshToken,
+
+    #[error("Refresh token was already used, the session has been revoked")]
+    RefreshTokenReuse,
 }
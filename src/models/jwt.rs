@@ -1,10 +1,55 @@
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
+
+use crate::models::role::UserRole;
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Claims {
     pub id: Uuid,
     pub public_id: i32,
-    pub user_type: String,
+    pub user_type: UserRole,
     pub email: String,
     pub exp: usize,
+    pub iat: usize,
+    /// `iat` in microseconds, so user-wide revocations can tell apart tokens issued in the same
+    /// second. Missing on tokens issued before it was added.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iat_micros: Option<i64>,
+    pub jti: Uuid,
+    /// Session the token was issued for. Tokens stop being accepted once it is revoked.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sid: Option<Uuid>,
+    /// Set when the caller authenticated with an API key instead of a JWT.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_key_id: Option<Uuid>,
+    /// Scopes granted to the user's role when the token was issued, narrowed down to the key's own
+    /// scopes for API keys.
+    #[serde(default)]
+    pub scopes: Vec<String>,
+    /// Set on tokens an admin got to act as the user. Follows the `act` claim of RFC 8693.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub act: Option<Actor>,
+    /// Whether the token is an impersonation one, so the frontend can show a banner.
+    #[serde(default)]
+    pub impersonated: bool,
+}
+
+/// The admin behind an impersonation token.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Actor {
+    pub sub: Uuid,
+    pub email: String,
+}
+
+/// Claims of the short-lived token handed out by `/user/login` when the account has MFA enabled.
+/// It only proves the password step and can't be used as an access token.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MfaPendingClaims {
+    pub sub: Uuid,
+    pub token_use: String,
+    /// Login method of the first step, recorded on the session once the second factor is given.
+    pub login_type: String,
+    pub exp: usize,
+    pub iat: usize,
+    pub jti: Uuid,
 }
@@ -0,0 +1,166 @@
+use std::io::Write;
+
+use crate::{models::error::ApiError, schema::login_throttles};
+use chrono::NaiveDateTime;
+use diesel::{
+    AsExpression, BoolExpressionMethods, ExpressionMethods, FromSqlRow, OptionalExtension,
+    QueryDsl,
+    deserialize::{self, FromSql},
+    pg::{Pg, PgValue},
+    prelude::{Insertable, Queryable},
+    serialize::{self, IsNull, Output, ToSql},
+    sql_types::Text,
+};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+
+/// What failed login attempts are counted against.
+#[derive(AsExpression, FromSqlRow, Debug, Clone, Copy, PartialEq, Eq)]
+#[diesel(sql_type = Text)]
+pub enum ThrottleScope {
+    Account,
+    Ip,
+}
+
+impl ThrottleScope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ThrottleScope::Account => "account",
+            ThrottleScope::Ip => "ip",
+        }
+    }
+}
+
+impl ToSql<Text, Pg> for ThrottleScope {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+        out.write_all(self.as_str().as_bytes())?;
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<Text, Pg> for ThrottleScope {
+    fn from_sql(bytes: PgValue<'_>) -> deserialize::Result<Self> {
+        match bytes.as_bytes() {
+            b"account" => Ok(ThrottleScope::Account),
+            b"ip" => Ok(ThrottleScope::Ip),
+            other => {
+                Err(format!("Unknown throttle scope: {}", String::from_utf8_lossy(other)).into())
+            }
+        }
+    }
+}
+
+#[derive(Queryable, Insertable, Debug, Clone)]
+#[diesel(table_name = login_throttles)]
+pub struct LoginThrottle {
+    pub scope: ThrottleScope,
+    pub subject: String,
+    pub failed_attempts: i32,
+    pub last_failure_date: NaiveDateTime,
+    pub locked_until: Option<NaiveDateTime>,
+}
+
+pub async fn find_login_throttle(
+    conn: &mut AsyncPgConnection,
+    scope_param: ThrottleScope,
+    subject_param: &str,
+) -> Result<Option<LoginThrottle>, ApiError> {
+    use crate::schema::login_throttles::dsl::*;
+
+    match login_throttles
+        .find((scope_param, subject_param))
+        .get_result(conn)
+        .await
+        .optional()
+    {
+        Ok(throttle) => Ok(throttle),
+        Err(e) => Err(ApiError::Database(e.to_string())),
+    }
+}
+
+/// Counts a failed attempt, returning the updated row.
+pub async fn record_login_failure(
+    conn: &mut AsyncPgConnection,
+    scope_param: ThrottleScope,
+    subject_param: &str,
+) -> Result<LoginThrottle, ApiError> {
+    use crate::schema::login_throttles::dsl::*;
+
+    let now = chrono::Utc::now().naive_utc();
+    let throttle = LoginThrottle {
+        scope: scope_param,
+        subject: subject_param.to_string(),
+        failed_attempts: 1,
+        last_failure_date: now,
+        locked_until: None,
+    };
+
+    match diesel::insert_into(login_throttles)
+        .values(&throttle)
+        .on_conflict((scope, subject))
+        .do_update()
+        .set((
+            failed_attempts.eq(failed_attempts + 1),
+            last_failure_date.eq(now),
+        ))
+        .get_result(conn)
+        .await
+    {
+        Ok(throttle) => Ok(throttle),
+        Err(e) => Err(ApiError::Database(e.to_string())),
+    }
+}
+
+pub async fn lock_login_throttle(
+    conn: &mut AsyncPgConnection,
+    scope_param: ThrottleScope,
+    subject_param: &str,
+    until: NaiveDateTime,
+) -> Result<(), ApiError> {
+    use crate::schema::login_throttles::dsl::*;
+
+    match diesel::update(login_throttles.find((scope_param, subject_param)))
+        .set(locked_until.eq(until))
+        .execute(conn)
+        .await
+    {
+        Ok(_) => Ok(()),
+        Err(e) => Err(ApiError::Database(e.to_string())),
+    }
+}
+
+pub async fn clear_login_throttle(
+    conn: &mut AsyncPgConnection,
+    scope_param: ThrottleScope,
+    subject_param: &str,
+) -> Result<(), ApiError> {
+    use crate::schema::login_throttles::dsl::*;
+
+    match diesel::delete(login_throttles.find((scope_param, subject_param)))
+        .execute(conn)
+        .await
+    {
+        Ok(_) => Ok(()),
+        Err(e) => Err(ApiError::Database(e.to_string())),
+    }
+}
+
+/// Forgets failures older than `before`, unless they still hold an active lock.
+pub async fn delete_stale_login_throttles(
+    conn: &mut AsyncPgConnection,
+    before: NaiveDateTime,
+) -> Result<(), ApiError> {
+    use crate::schema::login_throttles::dsl::*;
+
+    let now = chrono::Utc::now().naive_utc();
+    match diesel::delete(
+        login_throttles
+            .filter(last_failure_date.lt(before))
+            .filter(locked_until.is_null().or(locked_until.le(now))),
+    )
+    .execute(conn)
+    .await
+    {
+        Ok(_) => Ok(()),
+        Err(e) => Err(ApiError::Database(e.to_string())),
+    }
+}
@@ -0,0 +1,239 @@
+use crate::{
+    models::error::ApiError,
+    schema::{mfa_recovery_codes, user_mfa},
+};
+use chrono::NaiveDateTime;
+use diesel::{
+    BoolExpressionMethods, ExpressionMethods, OptionalExtension, QueryDsl, QueryResult,
+    prelude::{Insertable, Queryable},
+    upsert::excluded,
+};
+use diesel_async::{
+    AsyncConnection, AsyncPgConnection, RunQueryDsl, scoped_futures::ScopedFutureExt,
+};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+/// TOTP enrollment of a user. The secret is stored encrypted; `enabled_date` is only set once the
+/// user confirmed the enrollment with a first code.
+#[derive(Queryable, Insertable, Debug, Clone)]
+#[diesel(table_name = user_mfa)]
+pub struct UserMfa {
+    pub user_id: Uuid,
+    pub secret_ciphertext: Vec<u8>,
+    pub secret_nonce: Vec<u8>,
+    pub last_used_step: Option<i64>,
+    pub create_date: NaiveDateTime,
+    pub enabled_date: Option<NaiveDateTime>,
+}
+
+#[derive(Queryable, Insertable, Debug, Clone)]
+#[diesel(table_name = mfa_recovery_codes)]
+pub struct MfaRecoveryCode {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub code_hash: String,
+    pub create_date: NaiveDateTime,
+    pub used_date: Option<NaiveDateTime>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct TotpEnrollment {
+    pub secret: String,
+    pub otpauth_uri: String,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct ConfirmTotpInput {
+    pub code: String,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct RecoveryCodes {
+    pub recovery_codes: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct DisableTotpInput {
+    pub password: String,
+    /// A current TOTP code or an unused recovery code.
+    pub code: String,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct MfaLoginInput {
+    pub mfa_token: String,
+    /// A current TOTP code or an unused recovery code.
+    pub code: String,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct MfaChallenge {
+    pub mfa_required: bool,
+    pub mfa_token: String,
+    pub expires_in: i64,
+}
+
+/// Starts a new enrollment, replacing any previous unconfirmed one.
+pub async fn save_user_mfa(conn: &mut AsyncPgConnection, mfa: &UserMfa) -> Result<(), ApiError> {
+    use crate::schema::user_mfa::dsl::*;
+
+    match diesel::insert_into(user_mfa)
+        .values(mfa)
+        .on_conflict(user_id)
+        .do_update()
+        .set((
+            secret_ciphertext.eq(excluded(secret_ciphertext)),
+            secret_nonce.eq(excluded(secret_nonce)),
+            last_used_step.eq(excluded(last_used_step)),
+            create_date.eq(excluded(create_date)),
+            enabled_date.eq(excluded(enabled_date)),
+        ))
+        .execute(conn)
+        .await
+    {
+        Ok(_) => Ok(()),
+        Err(e) => Err(ApiError::Database(e.to_string())),
+    }
+}
+
+pub async fn find_user_mfa(
+    conn: &mut AsyncPgConnection,
+    user_id_param: &Uuid,
+) -> Result<Option<UserMfa>, ApiError> {
+    use crate::schema::user_mfa::dsl::*;
+
+    match user_mfa
+        .find(user_id_param)
+        .get_result(conn)
+        .await
+        .optional()
+    {
+        Ok(mfa) => Ok(mfa),
+        Err(e) => Err(ApiError::Database(e.to_string())),
+    }
+}
+
+/// Enables TOTP with the given recovery codes, replacing any previous ones. Both happen in one
+/// transaction, so TOTP is never left enabled without recovery codes.
+pub async fn enable_user_mfa(
+    conn: &mut AsyncPgConnection,
+    user_id_param: &Uuid,
+    codes: &[MfaRecoveryCode],
+) -> Result<(), ApiError> {
+    let result = conn
+        .transaction::<_, diesel::result::Error, _>(|conn| {
+            async move {
+                replace_recovery_codes(conn, user_id_param, codes).await?;
+
+                use crate::schema::user_mfa::dsl::*;
+                diesel::update(user_mfa.find(user_id_param))
+                    .set(enabled_date.eq(chrono::Utc::now().naive_utc()))
+                    .execute(conn)
+                    .await?;
+
+                Ok(())
+            }
+            .scope_boxed()
+        })
+        .await;
+
+    match result {
+        Ok(()) => Ok(()),
+        Err(e) => Err(ApiError::Database(e.to_string())),
+    }
+}
+
+/// Removes the TOTP enrollment together with the recovery codes.
+pub async fn delete_user_mfa(
+    conn: &mut AsyncPgConnection,
+    user_id_param: &Uuid,
+) -> Result<(), ApiError> {
+    let result = conn
+        .transaction::<_, diesel::result::Error, _>(|conn| {
+            async move {
+                {
+                    use crate::schema::mfa_recovery_codes::dsl::*;
+                    diesel::delete(mfa_recovery_codes.filter(user_id.eq(user_id_param)))
+                        .execute(conn)
+                        .await?;
+                }
+
+                use crate::schema::user_mfa::dsl::*;
+                diesel::delete(user_mfa.find(user_id_param))
+                    .execute(conn)
+                    .await?;
+
+                Ok(())
+            }
+            .scope_boxed()
+        })
+        .await;
+
+    match result {
+        Ok(()) => Ok(()),
+        Err(e) => Err(ApiError::Database(e.to_string())),
+    }
+}
+
+/// Records the time step of an accepted code, returning `false` when that step (or a later one)
+/// was already used, i.e. the code is being replayed.
+pub async fn mark_totp_step_used(
+    conn: &mut AsyncPgConnection,
+    user_id_param: &Uuid,
+    step: i64,
+) -> Result<bool, ApiError> {
+    use crate::schema::user_mfa::dsl::*;
+
+    match diesel::update(user_mfa.find(user_id_param))
+        .filter(last_used_step.is_null().or(last_used_step.lt(step)))
+        .set(last_used_step.eq(step))
+        .execute(conn)
+        .await
+    {
+        Ok(rows) => Ok(rows == 1),
+        Err(e) => Err(ApiError::Database(e.to_string())),
+    }
+}
+
+/// Replaces every recovery code of the user with the given ones. Meant to run inside a
+/// transaction, see [`enable_user_mfa`].
+async fn replace_recovery_codes(
+    conn: &mut AsyncPgConnection,
+    user_id_param: &Uuid,
+    codes: &[MfaRecoveryCode],
+) -> QueryResult<()> {
+    use crate::schema::mfa_recovery_codes::dsl::*;
+
+    diesel::delete(mfa_recovery_codes.filter(user_id.eq(user_id_param)))
+        .execute(conn)
+        .await?;
+    diesel::insert_into(mfa_recovery_codes)
+        .values(codes)
+        .execute(conn)
+        .await?;
+
+    Ok(())
+}
+
+/// Spends a recovery code, returning `false` when it doesn't exist or was already used.
+pub async fn use_recovery_code(
+    conn: &mut AsyncPgConnection,
+    user_id_param: &Uuid,
+    code_hash_param: &str,
+) -> Result<bool, ApiError> {
+    use crate::schema::mfa_recovery_codes::dsl::*;
+
+    match diesel::update(mfa_recovery_codes)
+        .filter(user_id.eq(user_id_param))
+        .filter(code_hash.eq(code_hash_param))
+        .filter(used_date.is_null())
+        .set(used_date.eq(chrono::Utc::now().naive_utc()))
+        .execute(conn)
+        .await
+    {
+        Ok(rows) => Ok(rows > 0),
+        Err(e) => Err(ApiError::Database(e.to_string())),
+    }
+}
@@ -1,3 +1,16 @@
+pub mod api_key;
+pub mod audit;
 pub mod error;
 pub mod jwt;
+pub mod login_throttle;
+pub mod mfa;
+pub mod oauth;
+pub mod pagination;
+pub mod permission;
+pub mod rate_limit;
+pub mod revocation;
+pub mod role;
+pub mod session;
 pub mod user;
+pub mod user_token;
+pub mod webauthn;
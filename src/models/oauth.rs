@@ -0,0 +1,131 @@
+use crate::{
+    models::error::ApiError,
+    schema::{oauth_identities, oidc_auth_requests},
+};
+use chrono::NaiveDateTime;
+use diesel::{
+    ExpressionMethods, OptionalExtension, QueryDsl,
+    prelude::{Insertable, Queryable},
+};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+/// Links an account at an external identity provider to a user.
+#[derive(Queryable, Insertable, Debug, Clone)]
+#[diesel(table_name = oauth_identities)]
+pub struct OAuthIdentity {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub provider: String,
+    pub subject: String,
+    pub create_date: NaiveDateTime,
+}
+
+/// A started authorization-code flow, kept until the provider redirects back with the `state`.
+#[derive(Queryable, Insertable, Debug, Clone)]
+#[diesel(table_name = oidc_auth_requests)]
+pub struct OidcAuthRequest {
+    pub state_hash: String,
+    pub provider: String,
+    pub code_verifier: String,
+    pub nonce: String,
+    pub create_date: NaiveDateTime,
+    pub expiration_date: NaiveDateTime,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct OidcAuthorization {
+    pub authorization_url: String,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct OidcCallbackInput {
+    pub code: String,
+    pub state: String,
+}
+
+pub async fn find_oauth_identity(
+    conn: &mut AsyncPgConnection,
+    provider_param: &str,
+    subject_param: &str,
+) -> Result<Option<OAuthIdentity>, ApiError> {
+    use crate::schema::oauth_identities::dsl::*;
+
+    match oauth_identities
+        .filter(provider.eq(provider_param))
+        .filter(subject.eq(subject_param))
+        .get_result(conn)
+        .await
+        .optional()
+    {
+        Ok(identity) => Ok(identity),
+        Err(e) => Err(ApiError::Database(e.to_string())),
+    }
+}
+
+pub async fn create_oauth_identity(
+    conn: &mut AsyncPgConnection,
+    identity: &OAuthIdentity,
+) -> Result<(), ApiError> {
+    use crate::schema::oauth_identities::dsl::*;
+
+    match diesel::insert_into(oauth_identities)
+        .values(identity)
+        .execute(conn)
+        .await
+    {
+        Ok(_) => Ok(()),
+        Err(e) => Err(ApiError::Database(e.to_string())),
+    }
+}
+
+pub async fn create_oidc_auth_request(
+    conn: &mut AsyncPgConnection,
+    request: &OidcAuthRequest,
+) -> Result<(), ApiError> {
+    use crate::schema::oidc_auth_requests::dsl::*;
+
+    match diesel::insert_into(oidc_auth_requests)
+        .values(request)
+        .execute(conn)
+        .await
+    {
+        Ok(_) => Ok(()),
+        Err(e) => Err(ApiError::Database(e.to_string())),
+    }
+}
+
+/// Removes and returns the request, so each `state` can only be redeemed once.
+pub async fn take_oidc_auth_request(
+    conn: &mut AsyncPgConnection,
+    state_hash_param: &str,
+) -> Result<Option<OidcAuthRequest>, ApiError> {
+    use crate::schema::oidc_auth_requests::dsl::*;
+
+    match diesel::delete(oidc_auth_requests.find(state_hash_param))
+        .get_result(conn)
+        .await
+        .optional()
+    {
+        Ok(request) => Ok(request),
+        Err(e) => Err(ApiError::Database(e.to_string())),
+    }
+}
+
+pub async fn delete_expired_oidc_auth_requests(
+    conn: &mut AsyncPgConnection,
+) -> Result<(), ApiError> {
+    use crate::schema::oidc_auth_requests::dsl::*;
+
+    match diesel::delete(
+        oidc_auth_requests.filter(expiration_date.le(chrono::Utc::now().naive_utc())),
+    )
+    .execute(conn)
+    .await
+    {
+        Ok(_) => Ok(()),
+        Err(e) => Err(ApiError::Database(e.to_string())),
+    }
+}
@@ -0,0 +1,136 @@
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use utoipa::{IntoParams, ToSchema};
+
+/// One page of a list. Offset pages carry `page` and `total`; cursor pages carry `next_cursor`
+/// instead, which is `null` on the last page.
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub page: Option<i64>,
+    pub per_page: i64,
+    pub total: Option<i64>,
+    pub next_cursor: Option<String>,
+}
+
+impl<T> Page<T> {
+    pub fn offset(items: Vec<T>, page: i64, per_page: i64, total: i64) -> Self {
+        Self {
+            items,
+            page: Some(page),
+            per_page,
+            total: Some(total),
+            next_cursor: None,
+        }
+    }
+
+    /// Builds a cursor page from up to `per_page + 1` rows; the extra row only tells that there
+    /// is a next page, whose cursor is the key of the last row kept.
+    pub fn cursor<K: Serialize>(mut items: Vec<T>, per_page: i64, key: impl Fn(&T) -> K) -> Self {
+        let has_next = items.len() as i64 > per_page;
+        items.truncate(per_page as usize);
+
+        let next_cursor = match has_next {
+            true => items.last().map(|item| encode_cursor(&key(item))),
+            false => None,
+        };
+
+        Self {
+            items,
+            page: None,
+            per_page,
+            total: None,
+            next_cursor,
+        }
+    }
+
+    pub fn map<U>(self, f: impl FnMut(T) -> U) -> Page<U> {
+        Page {
+            items: self.items.into_iter().map(f).collect(),
+            page: self.page,
+            per_page: self.per_page,
+            total: self.total,
+            next_cursor: self.next_cursor,
+        }
+    }
+}
+
+/// Cursors are the sort key of the last row of a page, as base64url encoded JSON. They aren't
+/// signed: a forged one only moves where the list starts.
+pub fn encode_cursor<K: Serialize>(key: &K) -> String {
+    URL_SAFE_NO_PAD.encode(serde_json::to_vec(key).unwrap_or_default())
+}
+
+pub fn decode_cursor<K: DeserializeOwned>(cursor: &str) -> Option<K> {
+    let bytes = URL_SAFE_NO_PAD.decode(cursor).ok()?;
+    serde_json::from_slice(&bytes).ok()
+}
+
+#[derive(Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct OffsetParams {
+    /// Starts at 1.
+    pub page: Option<i64>,
+    /// 20 by default, at most 100.
+    pub per_page: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct CursorParams {
+    /// `next_cursor` of the previous page; left out for the first one.
+    pub cursor: Option<String>,
+    /// 20 by default, at most 100.
+    pub per_page: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SortParams {
+    /// Field to sort by, prefixed with `-` for descending order, e.g. `-create_date`.
+    pub sort: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SortDirection {
+    Asc,
+    Desc,
+}
+
+/// The fields a list can be sorted by. Each model maps them to its own columns, so only the
+/// names listed here ever reach a query.
+pub trait SortField: Copy + Send + 'static {
+    /// Names accepted in `sort` and the field each one stands for.
+    const FIELDS: &'static [(&'static str, Self)];
+    /// Order used when `sort` is left out.
+    const DEFAULT: Sort<Self>;
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Sort<F> {
+    pub field: F,
+    pub direction: SortDirection,
+}
+
+impl<F: SortField> Sort<F> {
+    /// Parses `name` or `-name`; `None` when the name isn't whitelisted.
+    pub fn parse(value: &str) -> Option<Self> {
+        let value = value.trim();
+        let (name, direction) = match value.strip_prefix('-') {
+            Some(name) => (name, SortDirection::Desc),
+            None => (value, SortDirection::Asc),
+        };
+
+        F::FIELDS
+            .iter()
+            .find(|(field_name, _)| *field_name == name)
+            .map(|(_, field)| Self {
+                field: *field,
+                direction,
+            })
+    }
+
+    pub fn field_names() -> Vec<String> {
+        F::FIELDS.iter().map(|(name, _)| name.to_string()).collect()
+    }
+}
@@ -0,0 +1,30 @@
+use crate::models::{error::ApiError, role::UserRole};
+use diesel::{ExpressionMethods, QueryDsl};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+
+// Scopes seeded by the `create_permissions` migration. Roles are granted them through the
+// `role_permissions` table.
+pub const PROFILE_READ: &str = "profile:read";
+pub const PROFILE_WRITE: &str = "profile:write";
+pub const IMAGES_UPLOAD: &str = "images:upload";
+pub const USERS_READ: &str = "users:read";
+pub const USERS_WRITE: &str = "users:write";
+
+/// Scopes granted to the role, in alphabetical order.
+pub async fn list_role_scopes(
+    conn: &mut AsyncPgConnection,
+    role_param: UserRole,
+) -> Result<Vec<String>, ApiError> {
+    use crate::schema::role_permissions::dsl::*;
+
+    match role_permissions
+        .filter(role.eq(role_param))
+        .select(permission)
+        .order(permission.asc())
+        .load(conn)
+        .await
+    {
+        Ok(scopes) => Ok(scopes),
+        Err(e) => Err(ApiError::Database(e.to_string())),
+    }
+}
@@ -0,0 +1,83 @@
+use crate::{models::error::ApiError, schema::rate_limit_buckets};
+use chrono::NaiveDateTime;
+use diesel::{
+    ExpressionMethods, OptionalExtension, QueryDsl,
+    prelude::{Insertable, Queryable},
+    upsert::excluded,
+};
+use diesel_async::{
+    AsyncConnection, AsyncPgConnection, RunQueryDsl, scoped_futures::ScopedFutureExt,
+};
+
+#[derive(Queryable, Insertable, Debug, Clone)]
+#[diesel(table_name = rate_limit_buckets)]
+pub struct RateLimitBucket {
+    pub key: String,
+    pub tokens: f64,
+    pub update_date: NaiveDateTime,
+}
+
+/// Locks the bucket, lets `update` compute its new state from the stored one (`None` for a new
+/// key) and saves it, so concurrent instances can't both spend the same token. Whatever else
+/// `update` returns is passed through.
+pub async fn update_rate_limit_bucket<F, T>(
+    conn: &mut AsyncPgConnection,
+    key_param: &str,
+    update: F,
+) -> Result<T, ApiError>
+where
+    F: FnOnce(Option<RateLimitBucket>) -> (RateLimitBucket, T) + Send,
+    T: Send,
+{
+    use crate::schema::rate_limit_buckets::dsl::*;
+
+    let result = conn
+        .transaction::<_, diesel::result::Error, _>(|conn| {
+            async move {
+                let current = rate_limit_buckets
+                    .find(key_param)
+                    .for_update()
+                    .get_result::<RateLimitBucket>(conn)
+                    .await
+                    .optional()?;
+
+                let (bucket, output) = update(current);
+
+                diesel::insert_into(rate_limit_buckets)
+                    .values(&bucket)
+                    .on_conflict(key)
+                    .do_update()
+                    .set((
+                        tokens.eq(excluded(tokens)),
+                        update_date.eq(excluded(update_date)),
+                    ))
+                    .execute(conn)
+                    .await?;
+
+                Ok(output)
+            }
+            .scope_boxed()
+        })
+        .await;
+
+    match result {
+        Ok(output) => Ok(output),
+        Err(e) => Err(ApiError::Database(e.to_string())),
+    }
+}
+
+/// Removes buckets untouched since `before`; they would have refilled completely by now.
+pub async fn delete_idle_rate_limit_buckets(
+    conn: &mut AsyncPgConnection,
+    before: NaiveDateTime,
+) -> Result<(), ApiError> {
+    use crate::schema::rate_limit_buckets::dsl::*;
+
+    match diesel::delete(rate_limit_buckets.filter(update_date.lt(before)))
+        .execute(conn)
+        .await
+    {
+        Ok(_) => Ok(()),
+        Err(e) => Err(ApiError::Database(e.to_string())),
+    }
+}
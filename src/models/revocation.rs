@@ -0,0 +1,102 @@
+use crate::{
+    models::error::ApiError,
+    schema::{revoked_tokens, user_token_revocations},
+};
+use chrono::NaiveDateTime;
+use diesel::{
+    ExpressionMethods, QueryDsl,
+    prelude::{Insertable, Queryable},
+    upsert::excluded,
+};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use uuid::Uuid;
+
+#[derive(Queryable, Insertable, Debug, Clone)]
+#[diesel(table_name = revoked_tokens)]
+pub struct RevokedToken {
+    pub jti: Uuid,
+    pub user_id: Uuid,
+    pub expiration_date: NaiveDateTime,
+    pub revocation_date: NaiveDateTime,
+}
+
+#[derive(Queryable, Insertable, Debug, Clone)]
+#[diesel(table_name = user_token_revocations)]
+pub struct UserTokenRevocation {
+    pub user_id: Uuid,
+    pub revoked_before: NaiveDateTime,
+}
+
+pub async fn revoke_token(
+    conn: &mut AsyncPgConnection,
+    token: &RevokedToken,
+) -> Result<(), ApiError> {
+    use crate::schema::revoked_tokens::dsl::*;
+
+    match diesel::insert_into(revoked_tokens)
+        .values(token)
+        .on_conflict_do_nothing()
+        .execute(conn)
+        .await
+    {
+        Ok(_) => Ok(()),
+        Err(e) => Err(ApiError::Database(e.to_string())),
+    }
+}
+
+pub async fn revoke_user_tokens(
+    conn: &mut AsyncPgConnection,
+    revocation: &UserTokenRevocation,
+) -> Result<(), ApiError> {
+    use crate::schema::user_token_revocations::dsl::*;
+
+    match diesel::insert_into(user_token_revocations)
+        .values(revocation)
+        .on_conflict(user_id)
+        .do_update()
+        .set(revoked_before.eq(excluded(revoked_before)))
+        .execute(conn)
+        .await
+    {
+        Ok(_) => Ok(()),
+        Err(e) => Err(ApiError::Database(e.to_string())),
+    }
+}
+
+pub async fn list_active_revoked_tokens(
+    conn: &mut AsyncPgConnection,
+) -> Result<Vec<RevokedToken>, ApiError> {
+    use crate::schema::revoked_tokens::dsl::*;
+
+    match revoked_tokens
+        .filter(expiration_date.gt(chrono::Utc::now().naive_utc()))
+        .load(conn)
+        .await
+    {
+        Ok(tokens) => Ok(tokens),
+        Err(e) => Err(ApiError::Database(e.to_string())),
+    }
+}
+
+pub async fn delete_expired_revoked_tokens(conn: &mut AsyncPgConnection) -> Result<(), ApiError> {
+    use crate::schema::revoked_tokens::dsl::*;
+
+    match diesel::delete(revoked_tokens.filter(expiration_date.le(chrono::Utc::now().naive_utc())))
+        .execute(conn)
+        .await
+    {
+        Ok(_) => Ok(()),
+        Err(e) => Err(ApiError::Database(e.to_string())),
+    }
+}
+
+pub async fn list_user_token_revocations(
+    conn: &mut AsyncPgConnection,
+) -> Result<Vec<UserTokenRevocation>, ApiError> {
+    use crate::schema::user_token_revocations::dsl::*;
+
+    match user_token_revocations.load(conn).await {
+        Ok(revocations) => Ok(revocations),
+        Err(e) => Err(ApiError::Database(e.to_string())),
+    }
+}
@@ -0,0 +1,284 @@
+use crate::{
+    models::{error::ApiError, mfa::MfaChallenge},
+    schema::{refresh_tokens, sessions},
+};
+use chrono::NaiveDateTime;
+use diesel::{
+    ExpressionMethods, NullableExpressionMethods, QueryDsl,
+    prelude::{Insertable, Queryable},
+};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+#[derive(Queryable, Insertable, Debug, Clone)]
+#[diesel(table_name = sessions)]
+pub struct Session {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub create_date: NaiveDateTime,
+    pub expiration_date: NaiveDateTime,
+    pub revocation_date: Option<NaiveDateTime>,
+    /// How the user signed in: `password`, `magic_link` or the name of an OpenID Connect provider.
+    pub login_type: String,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    /// Last login or token refresh, so it lags real activity by at most an access token lifetime.
+    pub last_seen_date: NaiveDateTime,
+}
+
+#[derive(Queryable, Insertable, Debug, Clone)]
+#[diesel(table_name = refresh_tokens)]
+pub struct RefreshToken {
+    pub id: Uuid,
+    pub session_id: Uuid,
+    pub token_hash: String,
+    pub create_date: NaiveDateTime,
+    pub expiration_date: NaiveDateTime,
+    pub used_date: Option<NaiveDateTime>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct RefreshTokenInput {
+    pub refresh_token: String,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct LogoutInput {
+    pub refresh_token: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct TokenPair {
+    pub access_token: String,
+    pub refresh_token: String,
+    pub token_type: String,
+    pub expires_in: i64,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct ImpersonateInput {
+    /// Why the account is being accessed, kept in the audit trail.
+    pub reason: String,
+}
+
+/// Access token to act as another user. It can't be refreshed; a new one has to be requested once
+/// it expires.
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct ImpersonationToken {
+    pub access_token: String,
+    pub token_type: String,
+    pub expires_in: i64,
+    pub impersonated: bool,
+}
+
+/// A device the user is signed in on, as listed by `GET /user/sessions`.
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct SessionInfo {
+    pub id: Uuid,
+    pub login_type: String,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub create_date: NaiveDateTime,
+    pub last_seen_date: NaiveDateTime,
+    pub expiration_date: NaiveDateTime,
+    /// Whether this is the session of the token making the request.
+    pub current: bool,
+}
+
+impl SessionInfo {
+    pub fn new(session: Session, current_session_id: Option<Uuid>) -> Self {
+        Self {
+            current: current_session_id == Some(session.id),
+            id: session.id,
+            login_type: session.login_type,
+            ip_address: session.ip_address,
+            user_agent: session.user_agent,
+            create_date: session.create_date,
+            last_seen_date: session.last_seen_date,
+            expiration_date: session.expiration_date,
+        }
+    }
+}
+
+/// Answer of `/user/login`: the session tokens, or a challenge when the account has MFA enabled.
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(untagged)]
+pub enum LoginResponse {
+    Tokens(TokenPair),
+    MfaRequired(MfaChallenge),
+}
+
+pub async fn create_session(conn: &mut AsyncPgConnection, session: &Session) -> Result<(), String> {
+    use crate::schema::sessions::dsl::*;
+
+    match diesel::insert_into(sessions)
+        .values(session)
+        .execute(conn)
+        .await
+    {
+        Ok(_) => Ok(()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+pub async fn find_session_by_id(
+    conn: &mut AsyncPgConnection,
+    param: &Uuid,
+) -> Result<Session, String> {
+    use crate::schema::sessions::dsl::*;
+
+    match sessions.filter(id.eq(param)).get_result(conn).await {
+        Ok(session) => Ok(session),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Revokes the session, returning `false` when it was already revoked.
+pub async fn revoke_session(conn: &mut AsyncPgConnection, param: &Uuid) -> Result<bool, ApiError> {
+    use crate::schema::sessions::dsl::*;
+
+    match diesel::update(sessions)
+        .filter(id.eq(param))
+        .filter(revocation_date.is_null())
+        .set(revocation_date.eq(chrono::Utc::now().naive_utc()))
+        .execute(conn)
+        .await
+    {
+        Ok(rows) => Ok(rows == 1),
+        Err(e) => Err(ApiError::Database(e.to_string())),
+    }
+}
+
+/// Sessions of the user that can still be refreshed, most recently used first.
+pub async fn list_active_sessions(
+    conn: &mut AsyncPgConnection,
+    user_id_param: &Uuid,
+) -> Result<Vec<Session>, ApiError> {
+    use crate::schema::sessions::dsl::*;
+
+    match sessions
+        .filter(user_id.eq(user_id_param))
+        .filter(revocation_date.is_null())
+        .filter(expiration_date.gt(chrono::Utc::now().naive_utc()))
+        .order(last_seen_date.desc())
+        .load(conn)
+        .await
+    {
+        Ok(list) => Ok(list),
+        Err(e) => Err(ApiError::Database(e.to_string())),
+    }
+}
+
+/// Ids of the sessions revoked after `since`.
+pub async fn list_revoked_session_ids(
+    conn: &mut AsyncPgConnection,
+    since: NaiveDateTime,
+) -> Result<Vec<(Uuid, NaiveDateTime)>, ApiError> {
+    use crate::schema::sessions::dsl::*;
+
+    match sessions
+        .filter(revocation_date.gt(since))
+        .select((id, revocation_date.assume_not_null()))
+        .load(conn)
+        .await
+    {
+        Ok(list) => Ok(list),
+        Err(e) => Err(ApiError::Database(e.to_string())),
+    }
+}
+
+/// Records a refresh of the session from the given client.
+pub async fn touch_session(
+    conn: &mut AsyncPgConnection,
+    param: &Uuid,
+    ip_address_param: Option<&str>,
+    user_agent_param: Option<&str>,
+) -> Result<(), ApiError> {
+    use crate::schema::sessions::dsl::*;
+
+    match diesel::update(sessions)
+        .filter(id.eq(param))
+        .set((
+            last_seen_date.eq(chrono::Utc::now().naive_utc()),
+            ip_address.eq(ip_address_param),
+            user_agent.eq(user_agent_param),
+        ))
+        .execute(conn)
+        .await
+    {
+        Ok(_) => Ok(()),
+        Err(e) => Err(ApiError::Database(e.to_string())),
+    }
+}
+
+pub async fn revoke_user_sessions(
+    conn: &mut AsyncPgConnection,
+    param: &Uuid,
+) -> Result<(), ApiError> {
+    use crate::schema::sessions::dsl::*;
+
+    match diesel::update(sessions)
+        .filter(user_id.eq(param))
+        .filter(revocation_date.is_null())
+        .set(revocation_date.eq(chrono::Utc::now().naive_utc()))
+        .execute(conn)
+        .await
+    {
+        Ok(_) => Ok(()),
+        Err(e) => Err(ApiError::Database(e.to_string())),
+    }
+}
+
+pub async fn create_refresh_token(
+    conn: &mut AsyncPgConnection,
+    token: &RefreshToken,
+) -> Result<(), String> {
+    use crate::schema::refresh_tokens::dsl::*;
+
+    match diesel::insert_into(refresh_tokens)
+        .values(token)
+        .execute(conn)
+        .await
+    {
+        Ok(_) => Ok(()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+pub async fn find_refresh_token_by_hash(
+    conn: &mut AsyncPgConnection,
+    param: &str,
+) -> Result<RefreshToken, String> {
+    use crate::schema::refresh_tokens::dsl::*;
+
+    match refresh_tokens
+        .filter(token_hash.eq(param))
+        .get_result(conn)
+        .await
+    {
+        Ok(token) => Ok(token),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Marks the refresh token as used, returning `false` when it had already been used before.
+/// The check and the update happen in a single statement so concurrent refreshes can't both win.
+pub async fn mark_refresh_token_used(
+    conn: &mut AsyncPgConnection,
+    param: &Uuid,
+) -> Result<bool, ApiError> {
+    use crate::schema::refresh_tokens::dsl::*;
+
+    match diesel::update(refresh_tokens)
+        .filter(id.eq(param))
+        .filter(used_date.is_null())
+        .set(used_date.eq(chrono::Utc::now().naive_utc()))
+        .execute(conn)
+        .await
+    {
+        Ok(rows) => Ok(rows == 1),
+        Err(e) => Err(ApiError::Database(e.to_string())),
+    }
+}
@@ -1,43 +1,201 @@
 use crate::{
-    controllers::utils::{format_document, password_hash, random_public_id},
-    models::error::ApiError,
+    controllers::utils::{format_document, mask_document, random_public_id},
+    models::{
+        error::ApiError,
+        pagination::{Sort, SortDirection, SortField},
+        role::UserRole,
+    },
     schema::users,
 };
 use chrono::{NaiveDate, NaiveDateTime};
 use diesel::{
-    ExpressionMethods, QueryDsl,
+    BoolExpressionMethods, ExpressionMethods, NullableExpressionMethods, OptionalExtension,
+    PgTextExpressionMethods, QueryDsl, define_sql_function,
+    pg::Pg,
     prelude::{AsChangeset, Insertable, Queryable},
+    result::{DatabaseErrorKind, Error as DieselError},
+    sql_types::Text,
 };
 use diesel_async::{AsyncPgConnection, RunQueryDsl};
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 use std::str::FromStr;
-use utoipa::ToSchema;
+use utoipa::{IntoParams, ToSchema};
 use uuid::Uuid;
 use validator::ValidateEmail;
 
-#[derive(Queryable, Insertable, AsChangeset, Serialize, Deserialize, Debug, Clone)]
+/// `login_type` of accounts registered with a password and of the sessions they open with it.
+/// Accounts created through a social login hold the provider name instead.
+pub const PASSWORD_LOGIN_TYPE: &str = "password";
+/// Session `login_type` of logins through an e-mailed link.
+pub const MAGIC_LINK_LOGIN_TYPE: &str = "magic_link";
+/// Session `login_type` of logins with a WebAuthn credential.
+pub const PASSKEY_LOGIN_TYPE: &str = "passkey";
+
+// Sizes of the `name` and `email` columns.
+const MAX_NAME_LENGTH: usize = 128;
+const MAX_EMAIL_LENGTH: usize = 64;
+
+/// Row of the `users` table. It holds the password hash, so it is never serialized; answers use
+/// [`UserResponse`] instead.
+#[derive(Queryable, Insertable, AsChangeset, Debug, Clone)]
 #[diesel(table_name = users)]
 pub struct User {
     pub id: Uuid,
     pub public_id: i32,
     pub name: String,
     pub email: String,
-    pub document: String,
+    pub document: Option<String>,
     pub password: String,
-    pub birthdate: NaiveDate,
+    pub birthdate: Option<NaiveDate>,
     pub login_type: String,
-    pub user_type: String,
+    pub user_type: UserRole,
     pub is_active: bool,
     pub create_date: NaiveDateTime,
     pub update_date: NaiveDateTime,
     pub deletion_date: Option<NaiveDateTime>,
+    pub email_verified: bool,
+    /// New e-mail waiting to be confirmed through the link sent to it.
+    pub pending_email: Option<String>,
+}
+
+/// What a user may see of an account: the `users` row without the password hash and with the
+/// document masked.
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct UserResponse {
+    pub id: Uuid,
+    pub public_id: i32,
+    pub name: String,
+    pub email: String,
+    pub document: Option<String>,
+    pub birthdate: Option<NaiveDate>,
+    pub login_type: String,
+    pub user_type: UserRole,
+    pub is_active: bool,
+    pub email_verified: bool,
+    pub pending_email: Option<String>,
+    pub create_date: NaiveDateTime,
+    pub update_date: NaiveDateTime,
+}
+
+impl From<User> for UserResponse {
+    fn from(input: User) -> Self {
+        Self {
+            id: input.id,
+            public_id: input.public_id,
+            name: input.name,
+            email: input.email,
+            document: input.document.as_deref().map(mask_document),
+            birthdate: input.birthdate,
+            login_type: input.login_type,
+            user_type: input.user_type,
+            is_active: input.is_active,
+            email_verified: input.email_verified,
+            pending_email: input.pending_email,
+            create_date: input.create_date,
+            update_date: input.update_date,
+        }
+    }
+}
+
+/// An account as admins see it, including whether it is scheduled for deletion.
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct AdminUserResponse {
+    pub id: Uuid,
+    pub public_id: i32,
+    pub name: String,
+    pub email: String,
+    pub document: Option<String>,
+    pub birthdate: Option<NaiveDate>,
+    pub login_type: String,
+    pub user_type: UserRole,
+    pub is_active: bool,
+    pub email_verified: bool,
+    pub create_date: NaiveDateTime,
+    pub update_date: NaiveDateTime,
+    pub deletion_date: Option<NaiveDateTime>,
+}
+
+impl From<User> for AdminUserResponse {
+    fn from(input: User) -> Self {
+        Self {
+            id: input.id,
+            public_id: input.public_id,
+            name: input.name,
+            email: input.email,
+            document: input.document.as_deref().map(mask_document),
+            birthdate: input.birthdate,
+            login_type: input.login_type,
+            user_type: input.user_type,
+            is_active: input.is_active,
+            email_verified: input.email_verified,
+            create_date: input.create_date,
+            update_date: input.update_date,
+            deletion_date: input.deletion_date,
+        }
+    }
+}
+
+/// Filters of `GET /admin/users`. Every filter is optional and they all have to match.
+#[derive(Serialize, Deserialize, ToSchema, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AdminUserFilter {
+    pub user_type: Option<UserRole>,
+    pub login_type: Option<String>,
+    pub is_active: Option<bool>,
+    /// Only accounts scheduled for deletion, or only the others.
+    pub deleted: Option<bool>,
+    /// Accounts created on this day or later.
+    pub created_from: Option<NaiveDate>,
+    /// Accounts created on this day or earlier.
+    pub created_to: Option<NaiveDate>,
+    /// Part of the name or e-mail, case insensitive.
+    pub search: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct ChangeRoleInput {
+    pub user_type: UserRole,
+}
+
+/// Filters of [`list_users`]. `created_to` is exclusive and `search` is a `ILIKE` pattern.
+#[derive(Default)]
+pub struct UserFilter {
+    pub user_type: Option<UserRole>,
+    pub login_type: Option<String>,
+    pub is_active: Option<bool>,
+    pub deleted: Option<bool>,
+    pub created_from: Option<NaiveDateTime>,
+    pub created_to: Option<NaiveDateTime>,
+    pub search: Option<String>,
+}
+
+/// Sort fields of [`list_users`]. Ties are broken by `id`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UserSortField {
+    CreateDate,
+    Name,
+    Email,
+    PublicId,
+}
+
+impl SortField for UserSortField {
+    const FIELDS: &'static [(&'static str, Self)] = &[
+        ("create_date", Self::CreateDate),
+        ("name", Self::Name),
+        ("email", Self::Email),
+        ("public_id", Self::PublicId),
+    ];
+    const DEFAULT: Sort<Self> = Sort {
+        field: Self::CreateDate,
+        direction: SortDirection::Desc,
+    };
 }
 
 pub struct UserAuthInfo {
     pub id: Uuid,
     pub public_id: i32,
     pub email: String,
-    pub user_type: String,
+    pub user_type: UserRole,
 }
 
 impl From<User> for UserAuthInfo {
@@ -58,27 +216,103 @@ pub struct RegisterUser {
     pub document: String,
     pub password: String,
     pub birthdate: String,
-    pub login_type: String,
-    pub user_type: String,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct ChangePasswordInput {
+    pub current_password: String,
+    pub new_password: String,
+    /// Signs out every other session; a fresh token pair is returned for the current client.
+    #[serde(default)]
+    pub logout_other_sessions: bool,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct DeleteAccountInput {
+    pub password: String,
+}
+
+/// Body of `PATCH /user/update`, a JSON Merge Patch: fields left out are kept and `null` clears
+/// `document` or `birthdate`. A new `email` only replaces the current one once confirmed, and needs
+/// `current_password`.
+#[derive(Serialize, Deserialize, ToSchema)]
 pub struct UpdateUser {
-    pub name: String,
-    pub email: String,
-    pub document: String,
-    pub birthdate: NaiveDate,
+    #[serde(default, deserialize_with = "deserialize_patch")]
+    #[schema(value_type = Option<String>)]
+    pub name: Option<Option<String>>,
+    #[serde(default, deserialize_with = "deserialize_patch")]
+    #[schema(value_type = Option<String>)]
+    pub email: Option<Option<String>>,
+    #[serde(default, deserialize_with = "deserialize_patch")]
+    #[schema(value_type = Option<String>)]
+    pub document: Option<Option<String>>,
+    #[serde(default, deserialize_with = "deserialize_patch")]
+    #[schema(value_type = Option<NaiveDate>)]
+    pub birthdate: Option<Option<NaiveDate>>,
+    #[serde(default)]
+    pub current_password: Option<String>,
+}
+
+/// Tells a field sent as `null` (`Some(None)`) apart from one left out (`None`).
+fn deserialize_patch<'de, T, D>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+impl UpdateUser {
+    /// Runs the registration checks on the fields being changed and normalizes them.
+    pub fn parse_fields(&mut self) -> Result<(), String> {
+        match &mut self.name {
+            Some(None) => return Err(ApiError::InvalidData.to_string()),
+            Some(Some(name)) => {
+                *name = name.trim().to_string();
+                if name.is_empty() || name.chars().count() > MAX_NAME_LENGTH {
+                    return Err(ApiError::InvalidData.to_string());
+                }
+            }
+            None => {}
+        }
+
+        match &mut self.email {
+            Some(None) => return Err(ApiError::InvalidData.to_string()),
+            Some(Some(email)) => {
+                *email = email.trim().to_string();
+                if email.len() > MAX_EMAIL_LENGTH || !email.validate_email() {
+                    return Err(ApiError::InvalidEmail.to_string());
+                }
+            }
+            None => {}
+        }
+
+        if let Some(Some(document)) = &mut self.document {
+            *document = format_document(document)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Columns written by [`update_user_data`]; `None` leaves a column as it is.
+#[derive(AsChangeset)]
+#[diesel(table_name = users)]
+pub struct UserChanges {
+    pub name: Option<String>,
+    pub document: Option<Option<String>>,
+    pub birthdate: Option<Option<NaiveDate>>,
+    pub pending_email: Option<Option<String>>,
+    pub update_date: NaiveDateTime,
 }
 
 impl RegisterUser {
-    pub fn validate_fields(self: &Self) -> bool {
+    pub fn validate_fields(&self) -> bool {
         if self.document.trim().is_empty()
             || self.name.trim().is_empty()
             || self.email.trim().is_empty()
-            || self.password.trim().is_empty()
+            || self.password.is_empty()
             || self.birthdate.to_string().trim().is_empty()
-            || self.login_type.trim().is_empty()
-            || self.user_type.trim().is_empty()
             || !self.email.validate_email()
         {
             return false;
@@ -86,16 +320,10 @@ impl RegisterUser {
         true
     }
 
-    pub fn parse_fields(self: &mut Self) -> Result<(), String> {
+    pub fn parse_fields(&mut self) -> Result<(), String> {
         self.email = self.email.trim().to_string();
         self.name = self.name.trim().to_string();
-        self.password = password_hash(self.password.trim());
-        self.user_type = self.user_type.trim().to_string();
-        self.login_type = self.login_type.trim().to_string();
-        self.document = match format_document(&self.document) {
-            Ok(document) => document,
-            Err(e) => return Err(e),
-        };
+        self.document = format_document(&self.document)?;
         self.birthdate = self.birthdate.trim().to_string();
 
         Ok(())
@@ -112,15 +340,17 @@ impl From<RegisterUser> for User {
             public_id: random_public_id(),
             name: input.name,
             email: input.email,
-            document: input.document,
+            document: Some(input.document),
             password: input.password,
-            birthdate,
-            login_type: input.login_type,
-            user_type: input.user_type,
+            birthdate: Some(birthdate),
+            login_type: PASSWORD_LOGIN_TYPE.to_string(),
+            user_type: UserRole::Customer,
             is_active: true,
             create_date: chrono::Utc::now().naive_utc(),
             update_date: chrono::Utc::now().naive_utc(),
             deletion_date: None,
+            email_verified: false,
+            pending_email: None,
         }
     }
 }
@@ -132,8 +362,8 @@ pub struct LoginUser {
 }
 
 impl LoginUser {
-    pub fn validate_fields(self: &Self) -> Result<(), String> {
-        if self.email.trim().is_empty() || self.password.trim().is_empty() {
+    pub fn validate_fields(&self) -> Result<(), String> {
+        if self.email.trim().is_empty() || self.password.is_empty() {
             return Err(ApiError::InvalidData.to_string());
         }
         if !self.email.validate_email() {
@@ -142,9 +372,8 @@ impl LoginUser {
         Ok(())
     }
 
-    pub fn parse_fields(self: &mut Self) {
+    pub fn parse_fields(&mut self) {
         self.email = self.email.trim().to_string();
-        self.password = self.password.trim().to_string();
     }
 }
 
@@ -157,10 +386,18 @@ pub async fn register_user(conn: &mut AsyncPgConnection, user: &User) -> Result<
     }
 }
 
+define_sql_function!(fn lower(x: Text) -> Text);
+
+/// Finds the account with the e-mail, ignoring case: addresses are stored as typed but unique
+/// regardless of case.
 pub async fn find_user_by_email(conn: &mut AsyncPgConnection, param: &str) -> Result<User, String> {
     use crate::schema::users::dsl::*;
 
-    match users.filter(email.eq(param)).get_result(conn).await {
+    match users
+        .filter(lower(email).eq(lower(param)))
+        .get_result(conn)
+        .await
+    {
         Ok(user) => Ok(user),
         Err(e) => Err(e.to_string()),
     }
@@ -199,20 +436,169 @@ pub async fn find_user_by_public_id(
     }
 }
 
-pub async fn update_user_data(
+fn filtered_users(filter: &UserFilter) -> users::BoxedQuery<'_, Pg> {
+    use crate::schema::users::dsl::*;
+
+    let mut query = users.into_boxed();
+
+    if let Some(role) = filter.user_type {
+        query = query.filter(user_type.eq(role));
+    }
+    if let Some(login_type_param) = &filter.login_type {
+        query = query.filter(login_type.eq(login_type_param));
+    }
+    if let Some(is_active_param) = filter.is_active {
+        query = query.filter(is_active.eq(is_active_param));
+    }
+    match filter.deleted {
+        Some(true) => query = query.filter(deletion_date.is_not_null()),
+        Some(false) => query = query.filter(deletion_date.is_null()),
+        None => {}
+    }
+    if let Some(from) = filter.created_from {
+        query = query.filter(create_date.ge(from));
+    }
+    if let Some(to) = filter.created_to {
+        query = query.filter(create_date.lt(to));
+    }
+    if let Some(pattern) = &filter.search {
+        query = query.filter(name.ilike(pattern).or(email.ilike(pattern)));
+    }
+
+    query
+}
+
+/// A page of the accounts matching the filter and how many match in total.
+pub async fn list_users(
+    conn: &mut AsyncPgConnection,
+    filter: &UserFilter,
+    sort: Sort<UserSortField>,
+    limit: i64,
+    offset: i64,
+) -> Result<(Vec<User>, i64), ApiError> {
+    use crate::schema::users::dsl::*;
+
+    let total = match filtered_users(filter).count().get_result(conn).await {
+        Ok(total) => total,
+        Err(e) => return Err(ApiError::Database(e.to_string())),
+    };
+
+    let query = filtered_users(filter);
+    let query = match (sort.field, sort.direction) {
+        (UserSortField::CreateDate, SortDirection::Asc) => query.order(create_date.asc()),
+        (UserSortField::CreateDate, SortDirection::Desc) => query.order(create_date.desc()),
+        (UserSortField::Name, SortDirection::Asc) => query.order(name.asc()),
+        (UserSortField::Name, SortDirection::Desc) => query.order(name.desc()),
+        (UserSortField::Email, SortDirection::Asc) => query.order(email.asc()),
+        (UserSortField::Email, SortDirection::Desc) => query.order(email.desc()),
+        (UserSortField::PublicId, SortDirection::Asc) => query.order(public_id.asc()),
+        (UserSortField::PublicId, SortDirection::Desc) => query.order(public_id.desc()),
+    };
+
+    match query
+        .then_order_by(id.asc())
+        .limit(limit)
+        .offset(offset)
+        .load(conn)
+        .await
+    {
+        Ok(list) => Ok((list, total)),
+        Err(e) => Err(ApiError::Database(e.to_string())),
+    }
+}
+
+pub async fn set_active(
+    conn: &mut AsyncPgConnection,
+    id_param: &Uuid,
+    active: bool,
+) -> Result<(), ApiError> {
+    use crate::schema::users::dsl::*;
+
+    match diesel::update(users)
+        .filter(id.eq(id_param))
+        .set((
+            is_active.eq(active),
+            update_date.eq(chrono::Utc::now().naive_utc()),
+        ))
+        .execute(conn)
+        .await
+    {
+        Ok(_) => Ok(()),
+        Err(e) => Err(ApiError::Database(e.to_string())),
+    }
+}
+
+pub async fn set_user_type(
+    conn: &mut AsyncPgConnection,
+    id_param: &Uuid,
+    role: UserRole,
+) -> Result<(), ApiError> {
+    use crate::schema::users::dsl::*;
+
+    match diesel::update(users)
+        .filter(id.eq(id_param))
+        .set((
+            user_type.eq(role),
+            update_date.eq(chrono::Utc::now().naive_utc()),
+        ))
+        .execute(conn)
+        .await
+    {
+        Ok(_) => Ok(()),
+        Err(e) => Err(ApiError::Database(e.to_string())),
+    }
+}
+
+/// Schedules the account for deletion, or cancels it when `date` is `None`.
+pub async fn set_deletion_date(
+    conn: &mut AsyncPgConnection,
+    id_param: &Uuid,
+    date: Option<NaiveDateTime>,
+) -> Result<(), ApiError> {
+    use crate::schema::users::dsl::*;
+
+    match diesel::update(users)
+        .filter(id.eq(id_param))
+        .set((
+            deletion_date.eq(date),
+            update_date.eq(chrono::Utc::now().naive_utc()),
+        ))
+        .execute(conn)
+        .await
+    {
+        Ok(_) => Ok(()),
+        Err(e) => Err(ApiError::Database(e.to_string())),
+    }
+}
+
+/// Deletes the accounts scheduled for deletion up to `cutoff`, returning how many were removed.
+/// Their sessions, tokens, credentials and keys go with them through the foreign keys.
+pub async fn purge_deleted_users(
+    conn: &mut AsyncPgConnection,
+    cutoff: NaiveDateTime,
+) -> Result<usize, ApiError> {
+    use crate::schema::users::dsl::*;
+
+    match diesel::delete(users.filter(deletion_date.le(cutoff)))
+        .execute(conn)
+        .await
+    {
+        Ok(rows) => Ok(rows),
+        Err(e) => Err(ApiError::Database(e.to_string())),
+    }
+}
+
+pub async fn set_email_verified(
     conn: &mut AsyncPgConnection,
     id_param: &Uuid,
-    data: &UpdateUser,
 ) -> Result<(), ApiError> {
     use crate::schema::users::dsl::*;
 
     match diesel::update(users)
         .filter(id.eq(id_param))
         .set((
-            name.eq(&data.name),
-            email.eq(&data.email),
-            document.eq(&data.document),
-            birthdate.eq(data.birthdate),
+            email_verified.eq(true),
+            update_date.eq(chrono::Utc::now().naive_utc()),
         ))
         .execute(conn)
         .await
@@ -221,3 +607,76 @@ pub async fn update_user_data(
         Err(e) => Err(ApiError::Database(e.to_string())),
     }
 }
+
+pub async fn update_password(
+    conn: &mut AsyncPgConnection,
+    id_param: &Uuid,
+    password_param: &str,
+) -> Result<(), ApiError> {
+    use crate::schema::users::dsl::*;
+
+    match diesel::update(users)
+        .filter(id.eq(id_param))
+        .set((
+            password.eq(password_param),
+            update_date.eq(chrono::Utc::now().naive_utc()),
+        ))
+        .execute(conn)
+        .await
+    {
+        Ok(_) => Ok(()),
+        Err(e) => Err(ApiError::Database(e.to_string())),
+    }
+}
+
+/// Fails with [`ApiError::DocumentInUse`] when another account has the document.
+pub async fn update_user_data(
+    conn: &mut AsyncPgConnection,
+    id_param: &Uuid,
+    changes: &UserChanges,
+) -> Result<User, ApiError> {
+    use crate::schema::users::dsl::*;
+
+    match diesel::update(users)
+        .filter(id.eq(id_param))
+        .set(changes)
+        .get_result(conn)
+        .await
+    {
+        Ok(user) => Ok(user),
+        Err(DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {
+            Err(ApiError::DocumentInUse)
+        }
+        Err(e) => Err(ApiError::Database(e.to_string())),
+    }
+}
+
+/// Makes the pending e-mail the account's e-mail, already verified. Returns `None` when no change
+/// is pending, and fails with [`ApiError::EmailInUse`] when another account took the address
+/// meanwhile.
+pub async fn confirm_pending_email(
+    conn: &mut AsyncPgConnection,
+    id_param: &Uuid,
+) -> Result<Option<User>, ApiError> {
+    use crate::schema::users::dsl::*;
+
+    match diesel::update(users)
+        .filter(id.eq(id_param))
+        .filter(pending_email.is_not_null())
+        .set((
+            email.eq(pending_email.assume_not_null()),
+            pending_email.eq(None::<String>),
+            email_verified.eq(true),
+            update_date.eq(chrono::Utc::now().naive_utc()),
+        ))
+        .get_result(conn)
+        .await
+        .optional()
+    {
+        Ok(user) => Ok(user),
+        Err(DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {
+            Err(ApiError::EmailInUse)
+        }
+        Err(e) => Err(ApiError::Database(e.to_string())),
+    }
+}
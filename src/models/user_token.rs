@@ -0,0 +1,175 @@
+use std::io::Write;
+
+use crate::{models::error::ApiError, schema::user_tokens};
+use chrono::NaiveDateTime;
+use diesel::{
+    AsExpression, ExpressionMethods, FromSqlRow, QueryDsl,
+    deserialize::{self, FromSql},
+    pg::{Pg, PgValue},
+    prelude::{Insertable, Queryable},
+    serialize::{self, IsNull, Output, ToSql},
+    sql_types::Text,
+};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+/// What a single-use token sent to the user by e-mail can be exchanged for.
+#[derive(AsExpression, FromSqlRow, Debug, Clone, Copy, PartialEq, Eq)]
+#[diesel(sql_type = Text)]
+pub enum TokenPurpose {
+    EmailVerification,
+    PasswordReset,
+    MagicLink,
+    EmailChange,
+}
+
+impl TokenPurpose {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TokenPurpose::EmailVerification => "email_verification",
+            TokenPurpose::PasswordReset => "password_reset",
+            TokenPurpose::MagicLink => "magic_link",
+            TokenPurpose::EmailChange => "email_change",
+        }
+    }
+}
+
+impl ToSql<Text, Pg> for TokenPurpose {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+        out.write_all(self.as_str().as_bytes())?;
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<Text, Pg> for TokenPurpose {
+    fn from_sql(bytes: PgValue<'_>) -> deserialize::Result<Self> {
+        match bytes.as_bytes() {
+            b"email_verification" => Ok(TokenPurpose::EmailVerification),
+            b"password_reset" => Ok(TokenPurpose::PasswordReset),
+            b"magic_link" => Ok(TokenPurpose::MagicLink),
+            b"email_change" => Ok(TokenPurpose::EmailChange),
+            other => {
+                Err(format!("Unknown token purpose: {}", String::from_utf8_lossy(other)).into())
+            }
+        }
+    }
+}
+
+#[derive(Queryable, Insertable, Debug, Clone)]
+#[diesel(table_name = user_tokens)]
+pub struct UserToken {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub purpose: TokenPurpose,
+    pub token_hash: String,
+    pub create_date: NaiveDateTime,
+    pub expiration_date: NaiveDateTime,
+    pub used_date: Option<NaiveDateTime>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct VerifyEmailInput {
+    pub token: String,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct ResendVerificationInput {
+    pub email: String,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct ForgotPasswordInput {
+    pub email: String,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct ResetPasswordInput {
+    pub token: String,
+    pub password: String,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct MagicLinkInput {
+    pub email: String,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct ConsumeMagicLinkInput {
+    pub token: String,
+}
+
+pub async fn create_user_token(
+    conn: &mut AsyncPgConnection,
+    token: &UserToken,
+) -> Result<(), ApiError> {
+    use crate::schema::user_tokens::dsl::*;
+
+    match diesel::insert_into(user_tokens)
+        .values(token)
+        .execute(conn)
+        .await
+    {
+        Ok(_) => Ok(()),
+        Err(e) => Err(ApiError::Database(e.to_string())),
+    }
+}
+
+pub async fn find_user_token_by_hash(
+    conn: &mut AsyncPgConnection,
+    purpose_param: TokenPurpose,
+    param: &str,
+) -> Result<UserToken, String> {
+    use crate::schema::user_tokens::dsl::*;
+
+    match user_tokens
+        .filter(purpose.eq(purpose_param))
+        .filter(token_hash.eq(param))
+        .get_result(conn)
+        .await
+    {
+        Ok(token) => Ok(token),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Marks the token as used, returning `false` when it had already been used.
+pub async fn mark_user_token_used(
+    conn: &mut AsyncPgConnection,
+    param: &Uuid,
+) -> Result<bool, ApiError> {
+    use crate::schema::user_tokens::dsl::*;
+
+    match diesel::update(user_tokens)
+        .filter(id.eq(param))
+        .filter(used_date.is_null())
+        .set(used_date.eq(chrono::Utc::now().naive_utc()))
+        .execute(conn)
+        .await
+    {
+        Ok(rows) => Ok(rows == 1),
+        Err(e) => Err(ApiError::Database(e.to_string())),
+    }
+}
+
+/// Invalidates every pending token the user has for the given purpose.
+pub async fn invalidate_user_tokens(
+    conn: &mut AsyncPgConnection,
+    user_id_param: &Uuid,
+    purpose_param: TokenPurpose,
+) -> Result<(), ApiError> {
+    use crate::schema::user_tokens::dsl::*;
+
+    match diesel::update(user_tokens)
+        .filter(user_id.eq(user_id_param))
+        .filter(purpose.eq(purpose_param))
+        .filter(used_date.is_null())
+        .set(used_date.eq(chrono::Utc::now().naive_utc()))
+        .execute(conn)
+        .await
+    {
+        Ok(_) => Ok(()),
+        Err(e) => Err(ApiError::Database(e.to_string())),
+    }
+}
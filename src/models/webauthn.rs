@@ -0,0 +1,310 @@
+use crate::{
+    models::error::ApiError,
+    schema::{webauthn_challenges, webauthn_credentials},
+};
+use chrono::NaiveDateTime;
+use diesel::{
+    ExpressionMethods, OptionalExtension, QueryDsl,
+    prelude::{Insertable, Queryable},
+};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+/// A passkey registered by a user. `public_key` holds the COSE key reported by the authenticator.
+#[derive(Queryable, Insertable, Debug, Clone)]
+#[diesel(table_name = webauthn_credentials)]
+pub struct WebauthnCredential {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub credential_id: Vec<u8>,
+    pub public_key: Vec<u8>,
+    pub sign_count: i64,
+    pub name: String,
+    pub create_date: NaiveDateTime,
+    pub last_used_date: Option<NaiveDateTime>,
+}
+
+/// A challenge handed to the browser, kept until the signed answer comes back.
+#[derive(Queryable, Insertable, Debug, Clone)]
+#[diesel(table_name = webauthn_challenges)]
+pub struct WebauthnChallenge {
+    pub challenge_hash: String,
+    pub ceremony: String,
+    pub user_id: Option<Uuid>,
+    pub create_date: NaiveDateTime,
+    pub expiration_date: NaiveDateTime,
+}
+
+// The ceremony types below follow the WebAuthn JSON serialization, so they can be passed straight
+// to `PublicKeyCredential.parseCreationOptionsFromJSON` and read from `credential.toJSON()`.
+// Binary fields are base64url encoded without padding.
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct RelyingParty {
+    pub id: String,
+    pub name: String,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct WebauthnUser {
+    pub id: String,
+    pub name: String,
+    pub display_name: String,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct CredentialParameters {
+    #[serde(rename = "type")]
+    pub credential_type: String,
+    pub alg: i64,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct CredentialDescriptor {
+    #[serde(rename = "type")]
+    pub credential_type: String,
+    pub id: String,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthenticatorSelection {
+    pub resident_key: String,
+    pub require_resident_key: bool,
+    pub user_verification: String,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PublicKeyCredentialCreationOptions {
+    pub rp: RelyingParty,
+    pub user: WebauthnUser,
+    pub challenge: String,
+    pub pub_key_cred_params: Vec<CredentialParameters>,
+    pub timeout: i64,
+    pub exclude_credentials: Vec<CredentialDescriptor>,
+    pub authenticator_selection: AuthenticatorSelection,
+    pub attestation: String,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PublicKeyCredentialRequestOptions {
+    pub challenge: String,
+    pub timeout: i64,
+    pub rp_id: String,
+    pub allow_credentials: Vec<CredentialDescriptor>,
+    pub user_verification: String,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AttestationResponse {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    pub attestation_object: String,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RegistrationCredential {
+    pub id: String,
+    pub raw_id: String,
+    #[serde(rename = "type")]
+    pub credential_type: String,
+    pub response: AttestationResponse,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AssertionResponse {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    pub authenticator_data: String,
+    pub signature: String,
+    pub user_handle: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthenticationCredential {
+    pub id: String,
+    pub raw_id: String,
+    #[serde(rename = "type")]
+    pub credential_type: String,
+    pub response: AssertionResponse,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct RegisterPasskeyInput {
+    /// Label shown in the passkey list, e.g. "Work laptop".
+    pub name: Option<String>,
+    pub credential: RegistrationCredential,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct PasskeyInfo {
+    pub id: Uuid,
+    pub name: String,
+    pub create_date: NaiveDateTime,
+    pub last_used_date: Option<NaiveDateTime>,
+}
+
+impl From<WebauthnCredential> for PasskeyInfo {
+    fn from(input: WebauthnCredential) -> Self {
+        Self {
+            id: input.id,
+            name: input.name,
+            create_date: input.create_date,
+            last_used_date: input.last_used_date,
+        }
+    }
+}
+
+pub async fn create_webauthn_credential(
+    conn: &mut AsyncPgConnection,
+    credential: &WebauthnCredential,
+) -> Result<(), ApiError> {
+    use crate::schema::webauthn_credentials::dsl::*;
+
+    match diesel::insert_into(webauthn_credentials)
+        .values(credential)
+        .execute(conn)
+        .await
+    {
+        Ok(_) => Ok(()),
+        Err(e) => Err(ApiError::Database(e.to_string())),
+    }
+}
+
+pub async fn find_webauthn_credential(
+    conn: &mut AsyncPgConnection,
+    credential_id_param: &[u8],
+) -> Result<Option<WebauthnCredential>, ApiError> {
+    use crate::schema::webauthn_credentials::dsl::*;
+
+    match webauthn_credentials
+        .filter(credential_id.eq(credential_id_param))
+        .get_result(conn)
+        .await
+        .optional()
+    {
+        Ok(credential) => Ok(credential),
+        Err(e) => Err(ApiError::Database(e.to_string())),
+    }
+}
+
+pub async fn list_webauthn_credentials(
+    conn: &mut AsyncPgConnection,
+    user_id_param: &Uuid,
+) -> Result<Vec<WebauthnCredential>, ApiError> {
+    use crate::schema::webauthn_credentials::dsl::*;
+
+    match webauthn_credentials
+        .filter(user_id.eq(user_id_param))
+        .order(create_date.asc())
+        .load(conn)
+        .await
+    {
+        Ok(credentials) => Ok(credentials),
+        Err(e) => Err(ApiError::Database(e.to_string())),
+    }
+}
+
+/// Stores the signature counter of a successful login, as long as no concurrent login with the
+/// same credential already moved it past `previous_count`. Returns `false` in that case.
+pub async fn update_webauthn_sign_count(
+    conn: &mut AsyncPgConnection,
+    id_param: &Uuid,
+    previous_count: i64,
+    sign_count_param: i64,
+) -> Result<bool, ApiError> {
+    use crate::schema::webauthn_credentials::dsl::*;
+
+    match diesel::update(webauthn_credentials)
+        .filter(id.eq(id_param))
+        .filter(sign_count.eq(previous_count))
+        .set((
+            sign_count.eq(sign_count_param),
+            last_used_date.eq(chrono::Utc::now().naive_utc()),
+        ))
+        .execute(conn)
+        .await
+    {
+        Ok(rows) => Ok(rows == 1),
+        Err(e) => Err(ApiError::Database(e.to_string())),
+    }
+}
+
+/// Deletes one of the user's credentials, returning `false` when it doesn't exist.
+pub async fn delete_webauthn_credential(
+    conn: &mut AsyncPgConnection,
+    user_id_param: &Uuid,
+    id_param: &Uuid,
+) -> Result<bool, ApiError> {
+    use crate::schema::webauthn_credentials::dsl::*;
+
+    match diesel::delete(
+        webauthn_credentials
+            .filter(id.eq(id_param))
+            .filter(user_id.eq(user_id_param)),
+    )
+    .execute(conn)
+    .await
+    {
+        Ok(rows) => Ok(rows == 1),
+        Err(e) => Err(ApiError::Database(e.to_string())),
+    }
+}
+
+pub async fn create_webauthn_challenge(
+    conn: &mut AsyncPgConnection,
+    challenge: &WebauthnChallenge,
+) -> Result<(), ApiError> {
+    use crate::schema::webauthn_challenges::dsl::*;
+
+    match diesel::insert_into(webauthn_challenges)
+        .values(challenge)
+        .execute(conn)
+        .await
+    {
+        Ok(_) => Ok(()),
+        Err(e) => Err(ApiError::Database(e.to_string())),
+    }
+}
+
+/// Removes and returns the challenge, so each one can only be answered once.
+pub async fn take_webauthn_challenge(
+    conn: &mut AsyncPgConnection,
+    challenge_hash_param: &str,
+) -> Result<Option<WebauthnChallenge>, ApiError> {
+    use crate::schema::webauthn_challenges::dsl::*;
+
+    match diesel::delete(webauthn_challenges.find(challenge_hash_param))
+        .get_result(conn)
+        .await
+        .optional()
+    {
+        Ok(challenge) => Ok(challenge),
+        Err(e) => Err(ApiError::Database(e.to_string())),
+    }
+}
+
+pub async fn delete_expired_webauthn_challenges(
+    conn: &mut AsyncPgConnection,
+) -> Result<(), ApiError> {
+    use crate::schema::webauthn_challenges::dsl::*;
+
+    match diesel::delete(
+        webauthn_challenges.filter(expiration_date.le(chrono::Utc::now().naive_utc())),
+    )
+    .execute(conn)
+    .await
+    {
+        Ok(_) => Ok(()),
+        Err(e) => Err(ApiError::Database(e.to_string())),
+    }
+}
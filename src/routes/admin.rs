@@ -0,0 +1,49 @@
+use axum::{
+    middleware,
+    routing::{get, patch, post},
+};
+use diesel_async::{AsyncPgConnection, pooled_connection::deadpool::Pool};
+use utoipa_axum::router::OpenApiRouter;
+
+use crate::{
+    controllers::{
+        admin::{
+            api_activate_user, api_change_user_role, api_deactivate_user, api_force_password_reset,
+            api_get_user, api_impersonate_user, api_list_audit_events, api_list_users,
+            api_restore_user, api_unlock_user,
+        },
+        authorization::{require_role, require_scope},
+        jwt::jwt_auth,
+    },
+    models::{
+        permission::{USERS_READ, USERS_WRITE},
+        role::UserRole,
+    },
+};
+
+pub fn admin_routes(pool: Pool<AsyncPgConnection>) -> OpenApiRouter<Pool<AsyncPgConnection>> {
+    let read_routes = OpenApiRouter::new()
+        .route("/users", get(api_list_users))
+        .route("/users/{id}", get(api_get_user))
+        .route("/audit-events", get(api_list_audit_events))
+        .route_layer(middleware::from_fn_with_state(USERS_READ, require_scope));
+
+    let write_routes = OpenApiRouter::new()
+        .route("/users/{id}/unlock", post(api_unlock_user))
+        .route("/users/{id}/impersonate", post(api_impersonate_user))
+        .route("/users/{id}/activate", post(api_activate_user))
+        .route("/users/{id}/deactivate", post(api_deactivate_user))
+        .route("/users/{id}/reset-password", post(api_force_password_reset))
+        .route("/users/{id}/role", patch(api_change_user_role))
+        .route("/users/{id}/restore", post(api_restore_user))
+        .route_layer(middleware::from_fn_with_state(USERS_WRITE, require_scope));
+
+    OpenApiRouter::new()
+        .merge(read_routes)
+        .merge(write_routes)
+        .route_layer(middleware::from_fn_with_state(
+            UserRole::Admin,
+            require_role,
+        ))
+        .route_layer(middleware::from_fn_with_state(pool, jwt_auth))
+}
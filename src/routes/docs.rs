@@ -1,20 +1,163 @@
 use utoipa::{
-    OpenApi,
-    openapi::{self, Contact},
+    Modify, OpenApi,
+    openapi::{
+        self, Contact,
+        security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme},
+    },
 };
 
-use crate::models::user::{LoginUser, RegisterUser};
+use crate::models::{
+    api_key::{ApiKeyInfo, CreateApiKeyInput, CreatedApiKey},
+    audit::AuditEventInfo,
+    mfa::{
+        ConfirmTotpInput, DisableTotpInput, MfaChallenge, MfaLoginInput, RecoveryCodes,
+        TotpEnrollment,
+    },
+    oauth::{OidcAuthorization, OidcCallbackInput},
+    session::{
+        ImpersonateInput, ImpersonationToken, LoginResponse, LogoutInput, RefreshTokenInput,
+        SessionInfo, TokenPair,
+    },
+    user::{
+        AdminUserFilter, AdminUserResponse, ChangePasswordInput, ChangeRoleInput,
+        DeleteAccountInput, LoginUser, RegisterUser, UpdateUser, UserResponse,
+    },
+    user_token::{
+        ConsumeMagicLinkInput, ForgotPasswordInput, MagicLinkInput, ResendVerificationInput,
+        ResetPasswordInput, VerifyEmailInput,
+    },
+    webauthn::{
+        AssertionResponse, AttestationResponse, AuthenticationCredential, AuthenticatorSelection,
+        CredentialDescriptor, CredentialParameters, PasskeyInfo,
+        PublicKeyCredentialCreationOptions, PublicKeyCredentialRequestOptions,
+        RegisterPasskeyInput, RegistrationCredential, RelyingParty, WebauthnUser,
+    },
+};
 
 #[derive(OpenApi)]
 #[openapi(
     paths(
         crate::controllers::user::api_register_user,
         crate::controllers::user::api_login_user,
+        crate::controllers::user::api_refresh_token,
+        crate::controllers::user::api_logout_user,
+        crate::controllers::user::api_logout_all,
+        crate::controllers::session::api_list_sessions,
+        crate::controllers::session::api_revoke_session,
+        crate::controllers::user::api_verify_email,
+        crate::controllers::user::api_resend_verification,
+        crate::controllers::user::api_forgot_password,
+        crate::controllers::user::api_reset_password,
+        crate::controllers::user::api_change_password,
+        crate::controllers::user::api_get_current_user,
+        crate::controllers::user::api_update_user_data,
+        crate::controllers::user::api_confirm_email_change,
+        crate::controllers::account::api_delete_account,
+        crate::controllers::account::api_restore_account,
+        crate::controllers::user::api_request_magic_link,
+        crate::controllers::user::api_consume_magic_link,
+        crate::controllers::mfa::api_login_mfa,
+        crate::controllers::mfa::api_enroll_totp,
+        crate::controllers::mfa::api_confirm_totp,
+        crate::controllers::mfa::api_disable_totp,
+        crate::controllers::webauthn::api_webauthn_register_options,
+        crate::controllers::webauthn::api_webauthn_register,
+        crate::controllers::webauthn::api_list_passkeys,
+        crate::controllers::webauthn::api_delete_passkey,
+        crate::controllers::webauthn::api_webauthn_login_options,
+        crate::controllers::webauthn::api_webauthn_login,
+        crate::controllers::api_key::api_create_api_key,
+        crate::controllers::api_key::api_list_api_keys,
+        crate::controllers::api_key::api_revoke_api_key,
+        crate::controllers::oidc::api_oidc_authorize,
+        crate::controllers::oidc::api_oidc_callback,
+        crate::controllers::admin::api_list_users,
+        crate::controllers::admin::api_get_user,
+        crate::controllers::admin::api_activate_user,
+        crate::controllers::admin::api_deactivate_user,
+        crate::controllers::admin::api_force_password_reset,
+        crate::controllers::admin::api_change_user_role,
+        crate::controllers::admin::api_restore_user,
+        crate::controllers::admin::api_unlock_user,
+        crate::controllers::admin::api_impersonate_user,
+        crate::controllers::admin::api_list_audit_events,
     ),
-    components(schemas(RegisterUser, LoginUser))
+    components(schemas(
+        RegisterUser,
+        LoginUser,
+        RefreshTokenInput,
+        LogoutInput,
+        TokenPair,
+        SessionInfo,
+        VerifyEmailInput,
+        ResendVerificationInput,
+        ForgotPasswordInput,
+        ResetPasswordInput,
+        ChangePasswordInput,
+        UserResponse,
+        UpdateUser,
+        DeleteAccountInput,
+        MagicLinkInput,
+        ConsumeMagicLinkInput,
+        LoginResponse,
+        MfaChallenge,
+        MfaLoginInput,
+        TotpEnrollment,
+        ConfirmTotpInput,
+        RecoveryCodes,
+        DisableTotpInput,
+        PublicKeyCredentialCreationOptions,
+        PublicKeyCredentialRequestOptions,
+        RelyingParty,
+        WebauthnUser,
+        CredentialParameters,
+        CredentialDescriptor,
+        AuthenticatorSelection,
+        RegisterPasskeyInput,
+        RegistrationCredential,
+        AttestationResponse,
+        AuthenticationCredential,
+        AssertionResponse,
+        PasskeyInfo,
+        CreateApiKeyInput,
+        CreatedApiKey,
+        ApiKeyInfo,
+        OidcAuthorization,
+        OidcCallbackInput,
+        ImpersonateInput,
+        ImpersonationToken,
+        AdminUserResponse,
+        AdminUserFilter,
+        ChangeRoleInput,
+        AuditEventInfo
+    )),
+    modifiers(&SecurityAddon)
 )]
 pub struct ApiDoc;
 
+/// Declares the two ways of authenticating. Operations list the scopes they need under either
+/// scheme in their `security` attribute.
+struct SecurityAddon;
+
+impl Modify for SecurityAddon {
+    fn modify(&self, openapi: &mut openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "bearer_auth",
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .bearer_format("JWT")
+                    .build(),
+            ),
+        );
+        components.add_security_scheme(
+            "api_key",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new("X-API-Key"))),
+        );
+    }
+}
+
 pub fn get_api_docs() -> openapi::OpenApi {
     let mut docs = ApiDoc::openapi();
     let mut contact = Contact::new();
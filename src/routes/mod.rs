@@ -1,12 +1,20 @@
-use crate::controllers::jwt::jwt_auth;
+use crate::controllers::account::spawn_account_purge_job;
+use crate::controllers::authorization::{require_role, require_scope};
+use crate::controllers::jwt::{identify_caller, jwt_auth};
+use crate::controllers::rate_limit::{RateLimiter, rate_limit};
 use crate::controllers::utils::get_database_url_from_env;
 use crate::models::error::ApiError;
+use crate::models::permission::USERS_READ;
+use crate::models::role::UserRole;
+use crate::routes::admin::admin_routes;
 use crate::routes::docs::get_api_docs;
+use crate::routes::oidc::oidc_routes;
 use crate::routes::user::user_routes;
+use crate::routes::well_known::well_known_routes;
 use axum::{Json, Router};
 use axum::{
     extract::DefaultBodyLimit,
-    http::HeaderValue,
+    http::{HeaderName, HeaderValue},
     middleware,
     routing::{get, get_service},
 };
@@ -20,19 +28,28 @@ use hyper::StatusCode;
 use hyper::header::{AUTHORIZATION, CONTENT_TYPE};
 use rustls::ClientConfig;
 use rustls_platform_verifier::ConfigVerifierExt;
+use std::sync::Arc;
 use tower_http::cors::{Any, CorsLayer};
 use tower_http::services::ServeDir;
 use utoipa_axum::router::OpenApiRouter;
 use utoipa_swagger_ui::SwaggerUi;
 
+pub mod admin;
 pub mod docs;
+pub mod oidc;
 pub mod user;
+pub mod well_known;
 
 pub async fn print_protected_route()
 -> Result<(StatusCode, Json<String>), (StatusCode, Json<ApiError>)> {
     Ok((StatusCode::OK, Json("Protected route!".to_string())))
 }
 
+pub async fn print_admin_route() -> Result<(StatusCode, Json<String>), (StatusCode, Json<ApiError>)>
+{
+    Ok((StatusCode::OK, Json("Admin route!".to_string())))
+}
+
 #[axum::debug_handler]
 pub async fn print_common_route() -> Result<(StatusCode, Json<String>), (StatusCode, Json<ApiError>)>
 {
@@ -40,15 +57,22 @@ pub async fn print_common_route() -> Result<(StatusCode, Json<String>), (StatusC
 }
 
 pub fn protected_routes(pool: Pool<AsyncPgConnection>) -> OpenApiRouter<Pool<AsyncPgConnection>> {
-    let protected_routes = OpenApiRouter::new()
+    let admin_routes = OpenApiRouter::new()
+        .route("/admin", get(print_admin_route))
+        .route_layer(middleware::from_fn_with_state(USERS_READ, require_scope))
+        .route_layer(middleware::from_fn_with_state(
+            UserRole::Admin,
+            require_role,
+        ));
+
+    OpenApiRouter::new()
         .route("/protected", get(print_protected_route))
+        .merge(admin_routes)
         .layer(middleware::from_fn_with_state(pool.clone(), jwt_auth))
-        .with_state(pool);
-
-    protected_routes
+        .with_state(pool)
 }
 
-pub fn establish_connection(config: &str) -> BoxFuture<ConnectionResult<AsyncPgConnection>> {
+pub fn establish_connection(config: &str) -> BoxFuture<'_, ConnectionResult<AsyncPgConnection>> {
     let fut = async {
         let rustls_config = ClientConfig::with_platform_verifier();
         let tls = tokio_postgres_rustls::MakeRustlsConnect::new(rustls_config.unwrap());
@@ -61,36 +85,54 @@ pub fn establish_connection(config: &str) -> BoxFuture<ConnectionResult<AsyncPgC
     fut.boxed()
 }
 
-pub async fn init_routes() -> Router {
-    let db_url = get_database_url_from_env().ok();
-
+pub fn create_pool(db_url: String) -> Pool<AsyncPgConnection> {
     let mut config = ManagerConfig::default();
     config.custom_setup = Box::new(establish_connection);
 
-    if let Some(db_url) = db_url {
-        let mgr =
-            AsyncDieselConnectionManager::<AsyncPgConnection>::new_with_config(db_url, config);
-        let pool = Pool::builder(mgr).max_size(10).build().unwrap();
+    let mgr = AsyncDieselConnectionManager::<AsyncPgConnection>::new_with_config(db_url, config);
+    Pool::builder(mgr).max_size(10).build().unwrap()
+}
 
-        let app: OpenApiRouter<_> = OpenApiRouter::new()
-            .route("/common", get(print_common_route))
-            .nest_service("/images", get_service(ServeDir::new("./images")));
+pub async fn init_routes() -> Router {
+    let db_url = get_database_url_from_env().ok();
 
-        return Router::new()
-            .nest("/api", app.into())
-            .nest("/api/user", user_routes().await.into())
-            .nest("/api", protected_routes(pool.clone()).into())
-            .merge(SwaggerUi::new("/docs").url("/api-docs/openapi.json", get_api_docs()))
-            .with_state(pool)
-            .layer(DefaultBodyLimit::max(1024 * 1024 * 100))
-            .layer(
-                CorsLayer::new()
-                    .allow_origin(vec![
-                        "http://localhost:3000".parse::<HeaderValue>().unwrap(),
-                    ])
-                    .allow_methods(Any)
-                    .allow_headers(vec![AUTHORIZATION, CONTENT_TYPE]),
-            );
+    if let Some(db_url) = db_url {
+        let pool = create_pool(db_url);
+        spawn_account_purge_job(pool.clone());
+        return app(pool).await;
     }
     Router::new()
 }
+
+pub async fn app(pool: Pool<AsyncPgConnection>) -> Router {
+    let limiter = Arc::new(RateLimiter::from_env(pool.clone()).unwrap());
+
+    let app: OpenApiRouter<_> = OpenApiRouter::new()
+        .route("/common", get(print_common_route))
+        .nest_service("/images", get_service(ServeDir::new("./images")));
+
+    Router::new()
+        .nest("/api", app.into())
+        .nest("/api/user", user_routes(pool.clone()).await.into())
+        .nest("/api", protected_routes(pool.clone()).into())
+        .nest("/api/admin", admin_routes(pool.clone()).into())
+        .nest("/api/auth/oidc", oidc_routes().into())
+        .nest("/.well-known", well_known_routes())
+        .merge(SwaggerUi::new("/docs").url("/api-docs/openapi.json", get_api_docs()))
+        .with_state(pool.clone())
+        .layer(middleware::from_fn_with_state(limiter, rate_limit))
+        .layer(middleware::from_fn_with_state(pool, identify_caller))
+        .layer(DefaultBodyLimit::max(1024 * 1024 * 100))
+        .layer(
+            CorsLayer::new()
+                .allow_origin(vec![
+                    "http://localhost:3000".parse::<HeaderValue>().unwrap(),
+                ])
+                .allow_methods(Any)
+                .allow_headers(vec![
+                    AUTHORIZATION,
+                    CONTENT_TYPE,
+                    HeaderName::from_static("x-api-key"),
+                ]),
+        )
+}
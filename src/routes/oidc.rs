@@ -0,0 +1,11 @@
+use axum::routing::{get, post};
+use diesel_async::{AsyncPgConnection, pooled_connection::deadpool::Pool};
+use utoipa_axum::router::OpenApiRouter;
+
+use crate::controllers::oidc::{api_oidc_authorize, api_oidc_callback};
+
+pub fn oidc_routes() -> OpenApiRouter<Pool<AsyncPgConnection>> {
+    OpenApiRouter::new()
+        .route("/{provider}/authorize", get(api_oidc_authorize))
+        .route("/{provider}/callback", post(api_oidc_callback))
+}
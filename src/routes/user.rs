@@ -1,14 +1,88 @@
-use axum::routing::{patch, post};
+use axum::{
+    middleware,
+    routing::{delete, get, patch, post},
+};
 use diesel_async::{AsyncPgConnection, pooled_connection::deadpool::Pool};
 use utoipa_axum::router::OpenApiRouter;
 
-use crate::controllers::user::{api_login_user, api_register_user, api_update_user_data};
+use crate::controllers::{
+    account::{api_delete_account, api_restore_account},
+    api_key::{api_create_api_key, api_list_api_keys, api_revoke_api_key},
+    authorization::{forbid_api_keys, forbid_impersonation, require_scope},
+    jwt::jwt_auth,
+    mfa::{api_confirm_totp, api_disable_totp, api_enroll_totp, api_login_mfa},
+    session::{api_list_sessions, api_revoke_session},
+    user::{
+        api_change_password, api_confirm_email_change, api_consume_magic_link, api_forgot_password,
+        api_get_current_user, api_login_user, api_logout_all, api_logout_user, api_refresh_token,
+        api_register_user, api_request_magic_link, api_resend_verification, api_reset_password,
+        api_update_user_data, api_verify_email,
+    },
+    webauthn::{
+        api_delete_passkey, api_list_passkeys, api_webauthn_login, api_webauthn_login_options,
+        api_webauthn_register, api_webauthn_register_options,
+    },
+};
+use crate::models::permission::{PROFILE_READ, PROFILE_WRITE};
 
-pub async fn user_routes() -> OpenApiRouter<Pool<AsyncPgConnection>> {
-    let routes = OpenApiRouter::new()
+pub async fn user_routes(pool: Pool<AsyncPgConnection>) -> OpenApiRouter<Pool<AsyncPgConnection>> {
+    let profile_read_routes = OpenApiRouter::new()
+        .route("/webauthn/credentials", get(api_list_passkeys))
+        .route("/api-keys", get(api_list_api_keys))
+        .route("/sessions", get(api_list_sessions))
+        .route("/me", get(api_get_current_user))
+        .route_layer(middleware::from_fn_with_state(PROFILE_READ, require_scope));
+
+    let profile_write_routes = OpenApiRouter::new()
+        .route("/update", patch(api_update_user_data))
+        .route_layer(middleware::from_fn_with_state(PROFILE_WRITE, require_scope));
+
+    let account_security_routes = OpenApiRouter::new()
+        .route("/password", patch(api_change_password))
+        .route("/mfa/totp/enroll", post(api_enroll_totp))
+        .route("/mfa/totp/confirm", post(api_confirm_totp))
+        .route("/mfa/totp/disable", post(api_disable_totp))
+        .route(
+            "/webauthn/register/options",
+            post(api_webauthn_register_options),
+        )
+        .route("/webauthn/register", post(api_webauthn_register))
+        .route("/webauthn/credentials/{id}", delete(api_delete_passkey))
+        .route("/api-keys", post(api_create_api_key))
+        .route("/api-keys/{id}", delete(api_revoke_api_key))
+        .route("/sessions/{id}", delete(api_revoke_session))
+        .route("/me", delete(api_delete_account))
+        .route_layer(middleware::from_fn_with_state(PROFILE_WRITE, require_scope))
+        .route_layer(middleware::from_fn(forbid_api_keys));
+
+    // Only the account owner may change how the account is secured or sign it out everywhere.
+    let owner_only_routes = OpenApiRouter::new()
+        .route("/logout-all", post(api_logout_all))
+        .merge(account_security_routes)
+        .route_layer(middleware::from_fn(forbid_impersonation));
+
+    // Signing out needs no scope, so a token can always end its own session.
+    let authenticated_routes = OpenApiRouter::new()
+        .route("/logout", post(api_logout_user))
+        .merge(profile_read_routes)
+        .merge(profile_write_routes)
+        .merge(owner_only_routes)
+        .route_layer(middleware::from_fn_with_state(pool, jwt_auth));
+
+    OpenApiRouter::new()
         .route("/register", post(api_register_user))
         .route("/login", post(api_login_user))
-        .route("/update", patch(api_update_user_data));
-
-    routes
+        .route("/login/mfa", post(api_login_mfa))
+        .route("/magic-link", post(api_request_magic_link))
+        .route("/magic-link/consume", post(api_consume_magic_link))
+        .route("/webauthn/login/options", post(api_webauthn_login_options))
+        .route("/webauthn/login", post(api_webauthn_login))
+        .route("/refresh", post(api_refresh_token))
+        .route("/verify-email", post(api_verify_email))
+        .route("/confirm-email", post(api_confirm_email_change))
+        .route("/resend-verification", post(api_resend_verification))
+        .route("/forgot-password", post(api_forgot_password))
+        .route("/reset-password", post(api_reset_password))
+        .route("/restore", post(api_restore_account))
+        .merge(authenticated_routes)
 }
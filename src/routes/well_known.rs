@@ -0,0 +1,8 @@
+use axum::{Router, routing::get};
+use diesel_async::{AsyncPgConnection, pooled_connection::deadpool::Pool};
+
+use crate::controllers::jwt::api_jwks;
+
+pub fn well_known_routes() -> Router<Pool<AsyncPgConnection>> {
+    Router::new().route("/jwks.json", get(api_jwks))
+}
@@ -1,5 +1,182 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    api_keys (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        #[max_length = 64]
+        name -> Varchar,
+        #[max_length = 16]
+        prefix -> Varchar,
+        #[max_length = 64]
+        key_hash -> Varchar,
+        scopes -> Nullable<Text>,
+        create_date -> Timestamp,
+        expiration_date -> Nullable<Timestamp>,
+        last_used_date -> Nullable<Timestamp>,
+        revocation_date -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    audit_events (id) {
+        id -> Uuid,
+        actor_id -> Nullable<Uuid>,
+        target_user_id -> Nullable<Uuid>,
+        #[max_length = 64]
+        action -> Varchar,
+        details -> Nullable<Jsonb>,
+        create_date -> Timestamp,
+    }
+}
+
+diesel::table! {
+    login_throttles (scope, subject) {
+        #[max_length = 16]
+        scope -> Varchar,
+        #[max_length = 255]
+        subject -> Varchar,
+        failed_attempts -> Int4,
+        last_failure_date -> Timestamp,
+        locked_until -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    mfa_recovery_codes (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        #[max_length = 64]
+        code_hash -> Varchar,
+        create_date -> Timestamp,
+        used_date -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    oauth_identities (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        #[max_length = 16]
+        provider -> Varchar,
+        #[max_length = 255]
+        subject -> Varchar,
+        create_date -> Timestamp,
+    }
+}
+
+diesel::table! {
+    oidc_auth_requests (state_hash) {
+        #[max_length = 64]
+        state_hash -> Varchar,
+        #[max_length = 16]
+        provider -> Varchar,
+        #[max_length = 128]
+        code_verifier -> Varchar,
+        #[max_length = 64]
+        nonce -> Varchar,
+        create_date -> Timestamp,
+        expiration_date -> Timestamp,
+    }
+}
+
+diesel::table! {
+    permissions (name) {
+        #[max_length = 64]
+        name -> Varchar,
+        #[max_length = 255]
+        description -> Varchar,
+    }
+}
+
+diesel::table! {
+    rate_limit_buckets (key) {
+        #[max_length = 255]
+        key -> Varchar,
+        tokens -> Float8,
+        update_date -> Timestamp,
+    }
+}
+
+diesel::table! {
+    refresh_tokens (id) {
+        id -> Uuid,
+        session_id -> Uuid,
+        #[max_length = 64]
+        token_hash -> Varchar,
+        create_date -> Timestamp,
+        expiration_date -> Timestamp,
+        used_date -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    revoked_tokens (jti) {
+        jti -> Uuid,
+        user_id -> Uuid,
+        expiration_date -> Timestamp,
+        revocation_date -> Timestamp,
+    }
+}
+
+diesel::table! {
+    role_permissions (role, permission) {
+        #[max_length = 16]
+        role -> Varchar,
+        #[max_length = 64]
+        permission -> Varchar,
+    }
+}
+
+diesel::table! {
+    sessions (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        create_date -> Timestamp,
+        expiration_date -> Timestamp,
+        revocation_date -> Nullable<Timestamp>,
+        #[max_length = 16]
+        login_type -> Varchar,
+        #[max_length = 45]
+        ip_address -> Nullable<Varchar>,
+        #[max_length = 512]
+        user_agent -> Nullable<Varchar>,
+        last_seen_date -> Timestamp,
+    }
+}
+
+diesel::table! {
+    user_mfa (user_id) {
+        user_id -> Uuid,
+        secret_ciphertext -> Bytea,
+        secret_nonce -> Bytea,
+        last_used_step -> Nullable<Int8>,
+        create_date -> Timestamp,
+        enabled_date -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    user_token_revocations (user_id) {
+        user_id -> Uuid,
+        revoked_before -> Timestamp,
+    }
+}
+
+diesel::table! {
+    user_tokens (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        #[max_length = 32]
+        purpose -> Varchar,
+        #[max_length = 64]
+        token_hash -> Varchar,
+        create_date -> Timestamp,
+        expiration_date -> Timestamp,
+        used_date -> Nullable<Timestamp>,
+    }
+}
+
 diesel::table! {
     users (id) {
         id -> Uuid,
@@ -9,10 +186,10 @@ diesel::table! {
         #[max_length = 64]
         email -> Varchar,
         #[max_length = 32]
-        document -> Varchar,
+        document -> Nullable<Varchar>,
         #[max_length = 128]
         password -> Varchar,
-        birthdate -> Date,
+        birthdate -> Nullable<Date>,
         #[max_length = 16]
         login_type -> Varchar,
         #[max_length = 16]
@@ -21,5 +198,68 @@ diesel::table! {
         create_date -> Timestamp,
         update_date -> Timestamp,
         deletion_date -> Nullable<Timestamp>,
+        email_verified -> Bool,
+        #[max_length = 64]
+        pending_email -> Nullable<Varchar>,
     }
 }
+
+diesel::table! {
+    webauthn_challenges (challenge_hash) {
+        #[max_length = 64]
+        challenge_hash -> Varchar,
+        #[max_length = 16]
+        ceremony -> Varchar,
+        user_id -> Nullable<Uuid>,
+        create_date -> Timestamp,
+        expiration_date -> Timestamp,
+    }
+}
+
+diesel::table! {
+    webauthn_credentials (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        credential_id -> Bytea,
+        public_key -> Bytea,
+        sign_count -> Int8,
+        #[max_length = 64]
+        name -> Varchar,
+        create_date -> Timestamp,
+        last_used_date -> Nullable<Timestamp>,
+    }
+}
+
+diesel::joinable!(api_keys -> users (user_id));
+diesel::joinable!(mfa_recovery_codes -> users (user_id));
+diesel::joinable!(oauth_identities -> users (user_id));
+diesel::joinable!(refresh_tokens -> sessions (session_id));
+diesel::joinable!(revoked_tokens -> users (user_id));
+diesel::joinable!(role_permissions -> permissions (permission));
+diesel::joinable!(sessions -> users (user_id));
+diesel::joinable!(user_mfa -> users (user_id));
+diesel::joinable!(user_token_revocations -> users (user_id));
+diesel::joinable!(user_tokens -> users (user_id));
+diesel::joinable!(webauthn_challenges -> users (user_id));
+diesel::joinable!(webauthn_credentials -> users (user_id));
+
+diesel::allow_tables_to_appear_in_same_query!(
+    api_keys,
+    audit_events,
+    login_throttles,
+    mfa_recovery_codes,
+    oauth_identities,
+    oidc_auth_requests,
+    permissions,
+    rate_limit_buckets,
+    refresh_tokens,
+    revoked_tokens,
+    role_permissions,
+    sessions,
+    user_mfa,
+    user_token_revocations,
+    user_tokens,
+    users,
+    webauthn_challenges,
+    webauthn_credentials,
+);
@@ -0,0 +1,134 @@
+use axum::http::Method;
+use diesel::{ExpressionMethods, QueryDsl};
+use diesel_async::RunQueryDsl;
+use hyper::StatusCode;
+use serde_json::{Value, json};
+use uuid::Uuid;
+
+use crate::{
+    controllers::account::{get_deletion_grace_days, purge_deleted_accounts},
+    models::{self, user::User},
+    tests::{TEST_PASSWORD, TestApp, test_app},
+};
+
+async fn delete_account(app: &TestApp, tokens: &Value) -> StatusCode {
+    let (status, _) = app
+        .request(
+            Method::DELETE,
+            "/api/user/me",
+            tokens["access_token"].as_str(),
+            Some(json!({ "password": TEST_PASSWORD })),
+        )
+        .await;
+    status
+}
+
+async fn restore_account(app: &TestApp, user: &User) -> StatusCode {
+    let (status, _) = app
+        .request(
+            Method::POST,
+            "/api/user/restore",
+            None,
+            Some(json!({ "email": user.email, "password": TEST_PASSWORD })),
+        )
+        .await;
+    status
+}
+
+/// Moves the deletion of the account `days` into the past.
+async fn backdate_deletion(app: &TestApp, user_id: Uuid, days: i64) {
+    use crate::schema::users::dsl::{deletion_date, id, users};
+
+    let conn = &mut app.pool.get().await.unwrap();
+    diesel::update(users.filter(id.eq(user_id)))
+        .set(deletion_date.eq(chrono::Utc::now().naive_utc() - chrono::Duration::days(days)))
+        .execute(conn)
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_DATABASE_URL"]
+async fn deleting_signs_the_account_out_and_blocks_logins() {
+    let app = test_app().await;
+    let user = app.create_user(TEST_PASSWORD).await;
+    let tokens = app.login(&user.email, TEST_PASSWORD).await;
+
+    let (status, _) = app
+        .request(
+            Method::DELETE,
+            "/api/user/me",
+            tokens["access_token"].as_str(),
+            Some(json!({ "password": "Wrong-Password-123" })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    assert_eq!(delete_account(&app, &tokens).await, StatusCode::NO_CONTENT);
+
+    let (status, _) = app
+        .request(
+            Method::GET,
+            "/api/user/me",
+            tokens["access_token"].as_str(),
+            None,
+        )
+        .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, _) = app
+        .request(
+            Method::POST,
+            "/api/user/login",
+            None,
+            Some(json!({ "email": user.email, "password": TEST_PASSWORD })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_DATABASE_URL"]
+async fn restores_within_the_grace_period() {
+    let app = test_app().await;
+    let user = app.create_user(TEST_PASSWORD).await;
+    let tokens = app.login(&user.email, TEST_PASSWORD).await;
+
+    assert_eq!(delete_account(&app, &tokens).await, StatusCode::NO_CONTENT);
+    backdate_deletion(&app, user.id, get_deletion_grace_days() - 1).await;
+
+    assert_eq!(restore_account(&app, &user).await, StatusCode::OK);
+    app.login(&user.email, TEST_PASSWORD).await;
+
+    assert_eq!(restore_account(&app, &user).await, StatusCode::BAD_REQUEST);
+}
+
+/// Both in one test, as the purge would otherwise race the expired account of the other.
+#[tokio::test]
+#[ignore = "needs TEST_DATABASE_URL"]
+async fn expired_accounts_cannot_be_restored_and_are_purged() {
+    let app = test_app().await;
+    let expired = app.create_user(TEST_PASSWORD).await;
+    let recent = app.create_user(TEST_PASSWORD).await;
+
+    for user in [&expired, &recent] {
+        let tokens = app.login(&user.email, TEST_PASSWORD).await;
+        assert_eq!(delete_account(&app, &tokens).await, StatusCode::NO_CONTENT);
+    }
+    backdate_deletion(&app, expired.id, get_deletion_grace_days() + 1).await;
+
+    assert_eq!(restore_account(&app, &expired).await, StatusCode::GONE);
+
+    assert!(purge_deleted_accounts(&app.pool).await.unwrap() >= 1);
+
+    let conn = &mut app.pool.get().await.unwrap();
+    assert!(
+        models::user::find_user_by_id(conn, &expired.id)
+            .await
+            .is_err()
+    );
+    let recent = models::user::find_user_by_id(conn, &recent.id)
+        .await
+        .unwrap();
+    assert!(recent.deletion_date.is_some());
+}
@@ -0,0 +1,239 @@
+use axum::http::Method;
+use diesel::{ExpressionMethods, QueryDsl};
+use diesel_async::RunQueryDsl;
+use hyper::StatusCode;
+use serde_json::{Value, json};
+use uuid::Uuid;
+
+use crate::tests::{TEST_PASSWORD, TestApp, test_app};
+
+/// Signs a new user in and creates a key with `scopes` (all of the role's when `null`). Returns
+/// the access token and the created key.
+async fn create_key(app: &TestApp, scopes: Value) -> (String, Value) {
+    let user = app.create_user(TEST_PASSWORD).await;
+    let tokens = app.login(&user.email, TEST_PASSWORD).await;
+    let token = tokens["access_token"].as_str().unwrap().to_string();
+
+    let (status, body) = app
+        .request(
+            Method::POST,
+            "/api/user/api-keys",
+            Some(&token),
+            Some(json!({ "name": "CI", "scopes": scopes })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::CREATED, "{}", body);
+    (token, body)
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_DATABASE_URL"]
+async fn rejects_an_expiration_out_of_range() {
+    let app = test_app().await;
+    let user = app.create_user(TEST_PASSWORD).await;
+    let tokens = app.login(&user.email, TEST_PASSWORD).await;
+    let token = tokens["access_token"].as_str().unwrap();
+
+    for expires_in_days in [0, 3651, i64::MAX] {
+        let (status, _) = app
+            .request(
+                Method::POST,
+                "/api/user/api-keys",
+                Some(token),
+                Some(json!({ "name": "CI", "expires_in_days": expires_in_days })),
+            )
+            .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", expires_in_days);
+    }
+
+    let (status, body) = app
+        .request(
+            Method::POST,
+            "/api/user/api-keys",
+            Some(token),
+            Some(json!({ "name": "CI", "expires_in_days": 3650 })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::CREATED, "{}", body);
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_DATABASE_URL"]
+async fn keys_are_refused_by_the_account_security_routes() {
+    let app = test_app().await;
+    let (_, body) = create_key(&app, json!(["profile:read", "profile:write"])).await;
+    let key = body["key"].as_str().unwrap();
+    let key_id = body["id"].as_str().unwrap();
+
+    let id = Uuid::new_v4();
+    for (method, uri) in [
+        (Method::PATCH, "/api/user/password".to_string()),
+        (Method::POST, "/api/user/mfa/totp/enroll".to_string()),
+        (Method::POST, "/api/user/mfa/totp/confirm".to_string()),
+        (Method::POST, "/api/user/mfa/totp/disable".to_string()),
+        (
+            Method::POST,
+            "/api/user/webauthn/register/options".to_string(),
+        ),
+        (Method::POST, "/api/user/webauthn/register".to_string()),
+        (
+            Method::DELETE,
+            format!("/api/user/webauthn/credentials/{}", id),
+        ),
+        (Method::POST, "/api/user/api-keys".to_string()),
+        (Method::DELETE, format!("/api/user/api-keys/{}", key_id)),
+        (Method::DELETE, format!("/api/user/sessions/{}", id)),
+        (Method::DELETE, "/api/user/me".to_string()),
+    ] {
+        let (status, _) = app
+            .request_with_api_key(method.clone(), &uri, key, Some(json!({})))
+            .await;
+        assert_eq!(status, StatusCode::FORBIDDEN, "{} {}", method, uri);
+    }
+
+    let (status, body) = app
+        .request_with_api_key(Method::GET, "/api/user/me", key, None)
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_DATABASE_URL"]
+async fn authenticates_as_its_owner() {
+    let app = test_app().await;
+    let (token, key) = create_key(&app, Value::Null).await;
+
+    let (_, owner) = app
+        .request(Method::GET, "/api/user/me", Some(&token), None)
+        .await;
+    let (status, body) = app
+        .request_with_api_key(
+            Method::GET,
+            "/api/user/me",
+            key["key"].as_str().unwrap(),
+            None,
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["email"], owner["email"]);
+
+    let (status, _) = app
+        .request_with_api_key(
+            Method::GET,
+            "/api/user/me",
+            "rbt_000000000000_invalid",
+            None,
+        )
+        .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_DATABASE_URL"]
+async fn is_limited_to_its_scopes() {
+    let app = test_app().await;
+    let (_, key) = create_key(&app, json!(["profile:read"])).await;
+    let key = key["key"].as_str().unwrap();
+
+    let (status, _) = app
+        .request_with_api_key(Method::GET, "/api/user/me", key, None)
+        .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, _) = app
+        .request_with_api_key(
+            Method::PATCH,
+            "/api/user/update",
+            key,
+            Some(json!({ "name": "Renamed User" })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_DATABASE_URL"]
+async fn stops_working_once_revoked() {
+    let app = test_app().await;
+    let (token, key) = create_key(&app, Value::Null).await;
+
+    let (status, _) = app
+        .request(
+            Method::DELETE,
+            &format!("/api/user/api-keys/{}", key["id"].as_str().unwrap()),
+            Some(&token),
+            None,
+        )
+        .await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+
+    let (status, _) = app
+        .request_with_api_key(
+            Method::GET,
+            "/api/user/me",
+            key["key"].as_str().unwrap(),
+            None,
+        )
+        .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_DATABASE_URL"]
+async fn stops_working_once_expired() {
+    use crate::schema::api_keys::dsl::{api_keys, expiration_date, id};
+
+    let app = test_app().await;
+    let (_, key) = create_key(&app, Value::Null).await;
+
+    let key_id: Uuid = key["id"].as_str().unwrap().parse().unwrap();
+    let conn = &mut app.pool.get().await.unwrap();
+    diesel::update(api_keys.filter(id.eq(key_id)))
+        .set(expiration_date.eq(chrono::Utc::now().naive_utc() - chrono::Duration::minutes(1)))
+        .execute(conn)
+        .await
+        .unwrap();
+
+    let (status, _) = app
+        .request_with_api_key(
+            Method::GET,
+            "/api/user/me",
+            key["key"].as_str().unwrap(),
+            None,
+        )
+        .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_DATABASE_URL"]
+async fn cannot_manage_keys() {
+    let app = test_app().await;
+    let (_, key) = create_key(&app, Value::Null).await;
+    let secret = key["key"].as_str().unwrap();
+
+    let (status, _) = app
+        .request_with_api_key(Method::GET, "/api/user/api-keys", secret, None)
+        .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let (status, _) = app
+        .request_with_api_key(
+            Method::POST,
+            "/api/user/api-keys",
+            secret,
+            Some(json!({ "name": "Another" })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let (status, _) = app
+        .request_with_api_key(
+            Method::DELETE,
+            &format!("/api/user/api-keys/{}", key["id"].as_str().unwrap()),
+            secret,
+            None,
+        )
+        .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+}
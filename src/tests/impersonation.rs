@@ -0,0 +1,161 @@
+use axum::http::Method;
+use diesel::{ExpressionMethods, QueryDsl};
+use diesel_async::RunQueryDsl;
+use hyper::StatusCode;
+use serde_json::{Value, json};
+use uuid::Uuid;
+
+use crate::{
+    models::{
+        audit::{IMPERSONATION_ENDED, IMPERSONATION_STARTED},
+        role::UserRole,
+        user::User,
+    },
+    tests::{TEST_PASSWORD, TestApp, test_app},
+};
+
+const REASON: &str = "Support ticket 1234";
+
+/// Signs a new admin in and returns their id and access token.
+async fn signed_in_admin(app: &TestApp) -> (Uuid, String) {
+    let admin = app
+        .create_user_with_role(TEST_PASSWORD, UserRole::Admin)
+        .await;
+    let tokens = app.login(&admin.email, TEST_PASSWORD).await;
+    (
+        admin.id,
+        tokens["access_token"].as_str().unwrap().to_string(),
+    )
+}
+
+async fn impersonate(app: &TestApp, token: &str, user: &User, reason: &str) -> (StatusCode, Value) {
+    app.request(
+        Method::POST,
+        &format!("/api/admin/users/{}/impersonate", user.id),
+        Some(token),
+        Some(json!({ "reason": reason })),
+    )
+    .await
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_DATABASE_URL"]
+async fn impersonation_tokens_are_refused_by_the_owner_only_routes() {
+    let app = test_app().await;
+    let (_, admin_token) = signed_in_admin(&app).await;
+    let user = app.create_user(TEST_PASSWORD).await;
+
+    let (status, body) = impersonate(&app, &admin_token, &user, REASON).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["impersonated"], true);
+    let token = body["access_token"].as_str().unwrap();
+
+    let (status, body) = app
+        .request(Method::GET, "/api/user/me", Some(token), None)
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["email"], user.email);
+
+    for (method, uri) in [
+        (Method::PATCH, "/api/user/password"),
+        (Method::POST, "/api/user/mfa/totp/enroll"),
+        (Method::POST, "/api/user/webauthn/register/options"),
+        (Method::POST, "/api/user/api-keys"),
+        (Method::POST, "/api/user/logout-all"),
+        (Method::DELETE, "/api/user/me"),
+    ] {
+        let (status, _) = app
+            .request(method.clone(), uri, Some(token), Some(json!({})))
+            .await;
+        assert_eq!(status, StatusCode::FORBIDDEN, "{} {}", method, uri);
+    }
+
+    let (status, _) = app
+        .request(
+            Method::PATCH,
+            "/api/user/update",
+            Some(token),
+            Some(json!({
+                "email": format!("{}@example.com", Uuid::new_v4().simple()),
+                "current_password": TEST_PASSWORD,
+            })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_DATABASE_URL"]
+async fn admins_cannot_be_impersonated() {
+    let app = test_app().await;
+    let (admin_id, admin_token) = signed_in_admin(&app).await;
+    let other_admin = app
+        .create_user_with_role(TEST_PASSWORD, UserRole::Admin)
+        .await;
+
+    let (status, _) = impersonate(&app, &admin_token, &other_admin, REASON).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let (status, _) = app
+        .request(
+            Method::POST,
+            &format!("/api/admin/users/{}/impersonate", admin_id),
+            Some(&admin_token),
+            Some(json!({ "reason": REASON })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_DATABASE_URL"]
+async fn a_reason_is_required() {
+    let app = test_app().await;
+    let (_, admin_token) = signed_in_admin(&app).await;
+    let user = app.create_user(TEST_PASSWORD).await;
+
+    for reason in ["", "   "] {
+        let (status, _) = impersonate(&app, &admin_token, &user, reason).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{:?}", reason);
+    }
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_DATABASE_URL"]
+async fn starting_and_ending_are_audited() {
+    use crate::schema::audit_events::dsl::{
+        action, actor_id, audit_events, create_date, details, target_user_id,
+    };
+
+    let app = test_app().await;
+    let (admin_id, admin_token) = signed_in_admin(&app).await;
+    let user = app.create_user(TEST_PASSWORD).await;
+
+    let (status, body) = impersonate(&app, &admin_token, &user, REASON).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let token = body["access_token"].as_str().unwrap();
+
+    let (status, _) = app
+        .request(Method::POST, "/api/user/logout", Some(token), None)
+        .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let conn = &mut app.pool.get().await.unwrap();
+    let events: Vec<(Option<Uuid>, String, Option<Value>)> = audit_events
+        .filter(target_user_id.eq(user.id))
+        .order(create_date.asc())
+        .select((actor_id, action, details))
+        .load(conn)
+        .await
+        .unwrap();
+
+    assert_eq!(events.len(), 2, "{:?}", events);
+    let (started_by, started, started_details) = &events[0];
+    assert_eq!(*started_by, Some(admin_id));
+    assert_eq!(started, IMPERSONATION_STARTED);
+    assert_eq!(started_details.as_ref().unwrap()["reason"], REASON);
+
+    let (ended_by, ended, _) = &events[1];
+    assert_eq!(*ended_by, Some(admin_id));
+    assert_eq!(ended, IMPERSONATION_ENDED);
+}
@@ -0,0 +1,191 @@
+use axum::http::Method;
+use diesel::{ExpressionMethods, QueryDsl};
+use diesel_async::RunQueryDsl;
+use hyper::StatusCode;
+use serde_json::{Value, json};
+use totp_rs::TOTP;
+
+use crate::{
+    models::user::User,
+    tests::{TEST_PASSWORD, TestApp, test_app},
+};
+
+const TOTP_STEP_SECONDS: u64 = 30;
+
+fn current_step() -> u64 {
+    chrono::Utc::now().timestamp() as u64 / TOTP_STEP_SECONDS
+}
+
+fn code_at(totp: &TOTP, step: u64) -> String {
+    totp.generate(step * TOTP_STEP_SECONDS)
+}
+
+/// Enrolls and confirms TOTP with the code of `step`. Returns the authenticator and the recovery
+/// codes.
+async fn enable_totp(app: &TestApp, user: &User, step: u64) -> (TOTP, Vec<String>) {
+    let tokens = app.login(&user.email, TEST_PASSWORD).await;
+    let token = tokens["access_token"].as_str().unwrap();
+
+    let (status, enrollment) = app
+        .request(Method::POST, "/api/user/mfa/totp/enroll", Some(token), None)
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", enrollment);
+    let totp = TOTP::from_url(enrollment["otpauth_uri"].as_str().unwrap()).unwrap();
+
+    let (status, body) = app
+        .request(
+            Method::POST,
+            "/api/user/mfa/totp/confirm",
+            Some(token),
+            Some(json!({ "code": code_at(&totp, step) })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let recovery_codes = serde_json::from_value(body["recovery_codes"].clone()).unwrap();
+
+    (totp, recovery_codes)
+}
+
+/// Signs in with the password, which only gives the token for the second step.
+async fn start_login(app: &TestApp, user: &User) -> String {
+    let (status, body) = app
+        .request(
+            Method::POST,
+            "/api/user/login",
+            None,
+            Some(json!({ "email": user.email, "password": TEST_PASSWORD })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["mfa_required"], true, "{}", body);
+    assert!(body["access_token"].is_null());
+    body["mfa_token"].as_str().unwrap().to_string()
+}
+
+async fn login_mfa(app: &TestApp, user: &User, code: &str) -> (StatusCode, Value) {
+    let mfa_token = start_login(app, user).await;
+    app.request(
+        Method::POST,
+        "/api/user/login/mfa",
+        None,
+        Some(json!({ "mfa_token": mfa_token, "code": code })),
+    )
+    .await
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_DATABASE_URL"]
+async fn logs_in_with_a_totp_code_once() {
+    let app = test_app().await;
+    let user = app.create_user(TEST_PASSWORD).await;
+    let step = current_step();
+    let (totp, _) = enable_totp(&app, &user, step).await;
+
+    // Already spent confirming the enrollment.
+    let (status, _) = login_mfa(&app, &user, &code_at(&totp, step)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let code = code_at(&totp, step + 1);
+    let (status, body) = login_mfa(&app, &user, &code).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert!(body["access_token"].is_string());
+
+    let (status, _) = login_mfa(&app, &user, &code).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_DATABASE_URL"]
+async fn rejects_a_wrong_code_or_mfa_token() {
+    let app = test_app().await;
+    let user = app.create_user(TEST_PASSWORD).await;
+    let step = current_step();
+    let (totp, _) = enable_totp(&app, &user, step).await;
+
+    let wrong = format!(
+        "{:06}",
+        (code_at(&totp, step + 1).parse::<u32>().unwrap() + 1) % 1_000_000
+    );
+    let (status, _) = login_mfa(&app, &user, &wrong).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, _) = app
+        .request(
+            Method::POST,
+            "/api/user/login/mfa",
+            None,
+            Some(json!({ "mfa_token": "not-a-token", "code": code_at(&totp, step + 1) })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_DATABASE_URL"]
+async fn recovery_codes_can_be_used_once() {
+    let app = test_app().await;
+    let user = app.create_user(TEST_PASSWORD).await;
+    let (_, recovery_codes) = enable_totp(&app, &user, current_step()).await;
+    assert_eq!(recovery_codes.len(), 10);
+
+    let (status, body) = login_mfa(&app, &user, &recovery_codes[0]).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+
+    let (status, _) = login_mfa(&app, &user, &recovery_codes[0]).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    // Typed without the dash and in capitals.
+    let code = recovery_codes[1].replace('-', "").to_uppercase();
+    let (status, body) = login_mfa(&app, &user, &code).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_DATABASE_URL"]
+async fn disabling_needs_the_password_and_a_code() {
+    use crate::schema::mfa_recovery_codes::dsl::{mfa_recovery_codes, user_id};
+
+    let app = test_app().await;
+    let user = app.create_user(TEST_PASSWORD).await;
+    let (_, recovery_codes) = enable_totp(&app, &user, current_step()).await;
+
+    let mfa_token = start_login(&app, &user).await;
+    let (status, tokens) = app
+        .request(
+            Method::POST,
+            "/api/user/login/mfa",
+            None,
+            Some(json!({ "mfa_token": mfa_token, "code": recovery_codes[0] })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", tokens);
+    let token = tokens["access_token"].as_str().unwrap();
+
+    let disable = |password: &'static str, code: String| {
+        app.request(
+            Method::POST,
+            "/api/user/mfa/totp/disable",
+            Some(token),
+            Some(json!({ "password": password, "code": code })),
+        )
+    };
+
+    let (status, _) = disable("Wrong-Password-123", recovery_codes[1].clone()).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, _) = disable(TEST_PASSWORD, recovery_codes[0].clone()).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, body) = disable(TEST_PASSWORD, recovery_codes[1].clone()).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+
+    let conn = &mut app.pool.get().await.unwrap();
+    let remaining: i64 = mfa_recovery_codes
+        .filter(user_id.eq(user.id))
+        .count()
+        .get_result(conn)
+        .await
+        .unwrap();
+    assert_eq!(remaining, 0);
+
+    let tokens = app.login(&user.email, TEST_PASSWORD).await;
+    assert!(tokens["access_token"].is_string());
+}
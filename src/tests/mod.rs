@@ -0,0 +1,198 @@
+//! End-to-end tests that drive the router against a real database. They need `TEST_DATABASE_URL`
+//! pointing to a disposable PostgreSQL database, which gets migrated on the first run:
+//!
+//! ```sh
+//! TEST_DATABASE_URL=postgres://postgres@localhost/rbt_test cargo test -- --include-ignored
+//! ```
+
+mod account;
+mod api_key;
+mod impersonation;
+mod mfa;
+mod oidc;
+mod password;
+mod rate_limit;
+mod session;
+mod user;
+mod webauthn;
+
+use std::{
+    env,
+    net::{Ipv4Addr, SocketAddr},
+    sync::Once,
+};
+
+use axum::{
+    Router,
+    body::{Body, to_bytes},
+    extract::ConnectInfo,
+    http::{Method, Request},
+};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use diesel::{Connection, PgConnection};
+use diesel_async::{AsyncPgConnection, pooled_connection::deadpool::Pool};
+use diesel_migrations::{EmbeddedMigrations, MigrationHarness, embed_migrations};
+use hyper::StatusCode;
+use rand::Rng;
+use serde_json::{Value, json};
+use tower::ServiceExt;
+use uuid::Uuid;
+
+use crate::{
+    controllers::{hasher::hash_password, utils::random_public_id},
+    models::{
+        self,
+        role::UserRole,
+        user::{PASSWORD_LOGIN_TYPE, User},
+    },
+    routes::{app, create_pool},
+};
+
+const MIGRATIONS: EmbeddedMigrations = embed_migrations!();
+static SETUP: Once = Once::new();
+
+pub const TEST_PASSWORD: &str = "Quiet-Harbor-Lantern-42";
+
+pub struct TestApp {
+    pub app: Router,
+    pub pool: Pool<AsyncPgConnection>,
+    /// Requests come from this address, so each test gets its own IP rate limit buckets.
+    pub ip: Ipv4Addr,
+}
+
+fn database_url() -> String {
+    let db_url = env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL must be set");
+
+    SETUP.call_once(|| {
+        // SAFETY: runs once, before any test reads the environment through the app.
+        unsafe {
+            env::set_var("DATABASE_URL", &db_url);
+            env::set_var("JWT_SECRET", "test-jwt-secret");
+            env::set_var("FRONTEND_URL", "http://localhost:3000");
+            env::set_var("MFA_ENCRYPTION_KEY", STANDARD.encode([7u8; 32]));
+        }
+
+        let mut conn =
+            PgConnection::establish(&db_url).expect("Can't connect to TEST_DATABASE_URL");
+        conn.run_pending_migrations(MIGRATIONS)
+            .expect("Failed to migrate the test database");
+    });
+
+    db_url
+}
+
+pub async fn test_app() -> TestApp {
+    let pool = create_pool(database_url());
+    let ip = Ipv4Addr::from(rand::thread_rng().r#gen::<u32>() | 0x0a00_0000);
+
+    TestApp {
+        app: app(pool.clone()).await,
+        pool,
+        ip,
+    }
+}
+
+impl TestApp {
+    /// Sends a JSON request, authenticated with `token` when given, and returns the status and
+    /// the JSON body (`Value::Null` when empty).
+    pub async fn request(
+        &self,
+        method: Method,
+        uri: &str,
+        token: Option<&str>,
+        body: Option<Value>,
+    ) -> (StatusCode, Value) {
+        let authorization = token.map(|token| format!("Bearer {}", token));
+        self.send(method, uri, authorization, body).await
+    }
+
+    /// Like [`TestApp::request`], authenticated with an API key instead.
+    pub async fn request_with_api_key(
+        &self,
+        method: Method,
+        uri: &str,
+        key: &str,
+        body: Option<Value>,
+    ) -> (StatusCode, Value) {
+        let authorization = Some(format!("ApiKey {}", key));
+        self.send(method, uri, authorization, body).await
+    }
+
+    async fn send(
+        &self,
+        method: Method,
+        uri: &str,
+        authorization: Option<String>,
+        body: Option<Value>,
+    ) -> (StatusCode, Value) {
+        let mut builder = Request::builder().method(method).uri(uri);
+        if let Some(authorization) = authorization {
+            builder = builder.header("Authorization", authorization);
+        }
+        let body = match body {
+            Some(body) => {
+                builder = builder.header("Content-Type", "application/json");
+                Body::from(body.to_string())
+            }
+            None => Body::empty(),
+        };
+
+        let mut request = builder.body(body).unwrap();
+        request
+            .extensions_mut()
+            .insert(ConnectInfo(SocketAddr::from((self.ip, 40000))));
+
+        let response = self.app.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body = serde_json::from_slice(&bytes).unwrap_or(Value::Null);
+
+        (status, body)
+    }
+
+    /// Inserts an active, verified password account.
+    pub async fn create_user(&self, password: &str) -> User {
+        self.create_user_with_role(password, UserRole::Customer)
+            .await
+    }
+
+    /// Like [`TestApp::create_user`], with the given role.
+    pub async fn create_user_with_role(&self, password: &str, role: UserRole) -> User {
+        let now = chrono::Utc::now().naive_utc();
+        let user = User {
+            id: Uuid::new_v4(),
+            public_id: random_public_id(),
+            name: "Test User".to_string(),
+            email: format!("{}@example.com", Uuid::new_v4().simple()),
+            document: None,
+            password: hash_password(password).await.unwrap(),
+            birthdate: None,
+            login_type: PASSWORD_LOGIN_TYPE.to_string(),
+            user_type: role,
+            is_active: true,
+            create_date: now,
+            update_date: now,
+            deletion_date: None,
+            email_verified: true,
+            pending_email: None,
+        };
+
+        let conn = &mut self.pool.get().await.unwrap();
+        models::user::register_user(conn, &user).await.unwrap();
+        user
+    }
+
+    /// Signs in with a password and returns the token pair.
+    pub async fn login(&self, email: &str, password: &str) -> Value {
+        let (status, body) = self
+            .request(
+                Method::POST,
+                "/api/user/login",
+                None,
+                Some(json!({ "email": email, "password": password })),
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        body
+    }
+}
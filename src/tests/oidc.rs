@@ -0,0 +1,311 @@
+use std::{
+    collections::HashMap,
+    env,
+    sync::{LazyLock, Mutex},
+};
+
+use axum::{
+    Form, Json, Router,
+    extract::State,
+    http::Method,
+    routing::{get, post},
+};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use diesel::ExpressionMethods;
+use diesel_async::RunQueryDsl;
+use hyper::StatusCode;
+use jsonwebtoken::{EncodingKey, Header};
+use p256::{ecdsa::SigningKey, pkcs8::EncodePrivateKey};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::{
+    models,
+    tests::{TEST_PASSWORD, TestApp, test_app},
+};
+
+const PROVIDER: &str = "mock";
+const CLIENT_ID: &str = "test-client";
+const REDIRECT_URI: &str = "http://localhost:3000/auth/callback";
+const KEY_ID: &str = "mock-key";
+
+/// What the user agreed to at the provider, redeemed by the code.
+struct Grant {
+    client_id: String,
+    redirect_uri: String,
+    code_challenge: String,
+    nonce: String,
+    /// Merged over the standard claims, so a test can also override e.g. `aud`.
+    claims: Value,
+}
+
+/// A local OpenID provider serving discovery, its JWKS and a token endpoint that checks PKCE.
+struct MockProvider {
+    issuer: String,
+    key: SigningKey,
+    grants: Mutex<HashMap<String, Grant>>,
+}
+
+/// Started once on its own runtime, as the providers are read once per process.
+static MOCK_PROVIDER: LazyLock<MockProvider> = LazyLock::new(|| {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.set_nonblocking(true).unwrap();
+    let issuer = format!("http://{}", listener.local_addr().unwrap());
+
+    // SAFETY: runs once, before any test reaches the OIDC endpoints that read these.
+    unsafe {
+        env::set_var("OIDC_PROVIDERS", PROVIDER);
+        env::set_var("OIDC_MOCK_ISSUER", &issuer);
+        env::set_var("OIDC_MOCK_CLIENT_ID", CLIENT_ID);
+        env::set_var("OIDC_MOCK_REDIRECT_URI", REDIRECT_URI);
+    }
+
+    std::thread::spawn(move || {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async move {
+            let router = Router::new()
+                .route("/.well-known/openid-configuration", get(discovery))
+                .route("/jwks", get(jwks))
+                .route("/token", post(token))
+                .with_state(&*MOCK_PROVIDER);
+            let listener = tokio::net::TcpListener::from_std(listener).unwrap();
+            axum::serve(listener, router).await.unwrap();
+        });
+    });
+
+    MockProvider {
+        issuer,
+        key: SigningKey::random(&mut rand::thread_rng()),
+        grants: Mutex::new(HashMap::new()),
+    }
+});
+
+async fn discovery(State(provider): State<&'static MockProvider>) -> Json<Value> {
+    Json(json!({
+        "issuer": provider.issuer,
+        "authorization_endpoint": format!("{}/authorize", provider.issuer),
+        "token_endpoint": format!("{}/token", provider.issuer),
+        "jwks_uri": format!("{}/jwks", provider.issuer),
+    }))
+}
+
+async fn jwks(State(provider): State<&'static MockProvider>) -> Json<Value> {
+    let point = provider.key.verifying_key().to_encoded_point(false);
+    Json(json!({
+        "keys": [{
+            "kty": "EC",
+            "crv": "P-256",
+            "alg": "ES256",
+            "use": "sig",
+            "kid": KEY_ID,
+            "x": URL_SAFE_NO_PAD.encode(point.x().unwrap()),
+            "y": URL_SAFE_NO_PAD.encode(point.y().unwrap()),
+        }]
+    }))
+}
+
+#[derive(Deserialize)]
+struct TokenRequest {
+    grant_type: String,
+    code: String,
+    redirect_uri: String,
+    client_id: String,
+    code_verifier: String,
+}
+
+async fn token(
+    State(provider): State<&'static MockProvider>,
+    Form(request): Form<TokenRequest>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    let invalid_grant = || {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "error": "invalid_grant" })),
+        )
+    };
+
+    let grant = provider
+        .grants
+        .lock()
+        .unwrap()
+        .remove(&request.code)
+        .ok_or_else(invalid_grant)?;
+
+    let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(request.code_verifier.as_bytes()));
+    if request.grant_type != "authorization_code"
+        || request.client_id != grant.client_id
+        || request.redirect_uri != grant.redirect_uri
+        || challenge != grant.code_challenge
+    {
+        return Err(invalid_grant());
+    }
+
+    let now = chrono::Utc::now().timestamp();
+    let mut claims = json!({
+        "iss": provider.issuer,
+        "aud": grant.client_id,
+        "iat": now,
+        "exp": now + 300,
+        "nonce": grant.nonce,
+    });
+    claims
+        .as_object_mut()
+        .unwrap()
+        .extend(grant.claims.as_object().unwrap().clone());
+
+    let mut header = Header::new(jsonwebtoken::Algorithm::ES256);
+    header.kid = Some(KEY_ID.to_string());
+    let key = EncodingKey::from_ec_der(provider.key.to_pkcs8_der().unwrap().as_bytes());
+    let id_token = jsonwebtoken::encode(&header, &claims, &key).unwrap();
+
+    Ok(Json(json!({
+        "access_token": "provider-access-token",
+        "token_type": "Bearer",
+        "id_token": id_token,
+    })))
+}
+
+/// Starts a sign in and plays the user consenting at the provider. Returns the callback body.
+async fn authorize(app: &TestApp, claims: Value) -> Value {
+    let provider = &*MOCK_PROVIDER;
+
+    let (status, body) = app
+        .request(
+            Method::GET,
+            &format!("/api/auth/oidc/{}/authorize", PROVIDER),
+            None,
+            None,
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+
+    let url = reqwest::Url::parse(body["authorization_url"].as_str().unwrap()).unwrap();
+    assert!(url.as_str().starts_with(&provider.issuer));
+    let params: HashMap<String, String> = url.query_pairs().into_owned().collect();
+    assert_eq!(params["code_challenge_method"], "S256");
+
+    let code = Uuid::new_v4().to_string();
+    provider.grants.lock().unwrap().insert(
+        code.clone(),
+        Grant {
+            client_id: params["client_id"].clone(),
+            redirect_uri: params["redirect_uri"].clone(),
+            code_challenge: params["code_challenge"].clone(),
+            nonce: params["nonce"].clone(),
+            claims,
+        },
+    );
+
+    json!({ "code": code, "state": params["state"] })
+}
+
+async fn callback(app: &TestApp, body: Value) -> (StatusCode, Value) {
+    app.request(
+        Method::POST,
+        &format!("/api/auth/oidc/{}/callback", PROVIDER),
+        None,
+        Some(body),
+    )
+    .await
+}
+
+fn identity(email: &str) -> (String, Value) {
+    let subject = Uuid::new_v4().to_string();
+    let claims = json!({
+        "sub": subject,
+        "email": email,
+        "email_verified": true,
+        "name": "Provider User",
+    });
+    (subject, claims)
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_DATABASE_URL"]
+async fn signs_in_a_new_user_through_the_provider() {
+    let app = test_app().await;
+    let email = format!("{}@example.com", Uuid::new_v4().simple());
+    let (subject, claims) = identity(&email);
+
+    let body = authorize(&app, claims).await;
+    let (status, body) = callback(&app, body).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert!(body["access_token"].is_string());
+
+    let conn = &mut app.pool.get().await.unwrap();
+    let user = models::user::find_user_by_email(conn, &email)
+        .await
+        .unwrap();
+    assert_eq!(user.login_type, PROVIDER);
+    let identity = models::oauth::find_oauth_identity(conn, PROVIDER, &subject)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(identity.user_id, user.id);
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_DATABASE_URL"]
+async fn links_an_existing_account_whose_email_differs_in_case() {
+    use crate::schema::users::dsl::{email, id, users};
+
+    let app = test_app().await;
+    let user = app.create_user(TEST_PASSWORD).await;
+    let stored_email = format!("Mixed.{}@Example.com", Uuid::new_v4().simple());
+    let conn = &mut app.pool.get().await.unwrap();
+    diesel::update(users)
+        .filter(id.eq(user.id))
+        .set(email.eq(&stored_email))
+        .execute(conn)
+        .await
+        .unwrap();
+
+    let (subject, claims) = identity(&stored_email.to_lowercase());
+    let body = authorize(&app, claims).await;
+    let (status, body) = callback(&app, body).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+
+    let identity = models::oauth::find_oauth_identity(conn, PROVIDER, &subject)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(identity.user_id, user.id);
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_DATABASE_URL"]
+async fn rejects_id_tokens_with_a_wrong_nonce_audience_or_expiration() {
+    let app = test_app().await;
+
+    for overrides in [
+        json!({ "nonce": "another-nonce" }),
+        json!({ "aud": "another-client" }),
+        json!({ "exp": chrono::Utc::now().timestamp() - 3600 }),
+    ] {
+        let (_, mut claims) = identity(&format!("{}@example.com", Uuid::new_v4().simple()));
+        claims
+            .as_object_mut()
+            .unwrap()
+            .extend(overrides.as_object().unwrap().clone());
+
+        let body = authorize(&app, claims).await;
+        let (status, _) = callback(&app, body).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED, "{}", overrides);
+    }
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_DATABASE_URL"]
+async fn rejects_a_replayed_state() {
+    let app = test_app().await;
+    let (_, claims) = identity(&format!("{}@example.com", Uuid::new_v4().simple()));
+
+    let body = authorize(&app, claims).await;
+    let (status, _) = callback(&app, body.clone()).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, _) = callback(&app, body).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
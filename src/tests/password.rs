@@ -0,0 +1,98 @@
+use axum::http::Method;
+use hyper::StatusCode;
+use serde_json::json;
+
+use crate::{
+    controllers::hasher::verify_password,
+    models,
+    tests::{TEST_PASSWORD, test_app},
+};
+
+#[tokio::test]
+#[ignore = "needs TEST_DATABASE_URL"]
+async fn change_password_returns_a_usable_token_pair() {
+    let app = test_app().await;
+    let user = app.create_user(TEST_PASSWORD).await;
+    let tokens = app.login(&user.email, TEST_PASSWORD).await;
+    let old_token = tokens["access_token"].as_str().unwrap();
+
+    let (status, body) = app
+        .request(
+            Method::PATCH,
+            "/api/user/password",
+            Some(old_token),
+            Some(json!({
+                "current_password": TEST_PASSWORD,
+                "new_password": "Brisk-Meadow-Compass-77",
+                "logout_other_sessions": true,
+            })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+
+    let new_token = body["access_token"].as_str().unwrap();
+    let (status, body) = app
+        .request(Method::GET, "/api/user/me", Some(new_token), None)
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["email"], user.email.as_str());
+
+    let (status, _) = app
+        .request(Method::GET, "/api/user/me", Some(old_token), None)
+        .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, _) = app
+        .request(
+            Method::POST,
+            "/api/user/refresh",
+            None,
+            Some(json!({ "refresh_token": tokens["refresh_token"] })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_DATABASE_URL"]
+async fn login_with_surrounding_whitespace_keeps_a_legacy_password_working() {
+    let app = test_app().await;
+    let user = app.create_user(TEST_PASSWORD).await;
+    let conn = &mut app.pool.get().await.unwrap();
+    let legacy_hash = pwhash::bcrypt::hash(TEST_PASSWORD).unwrap();
+    models::user::update_password(conn, &user.id, &legacy_hash)
+        .await
+        .unwrap();
+
+    app.login(&user.email, &format!(" {} ", TEST_PASSWORD))
+        .await;
+    app.login(&user.email, TEST_PASSWORD).await;
+
+    let stored = models::user::find_user_by_id(conn, &user.id).await.unwrap();
+    assert!(stored.password.starts_with("$argon2"));
+    assert!(
+        verify_password(TEST_PASSWORD, &stored.password)
+            .await
+            .unwrap()
+            .valid
+    );
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_DATABASE_URL"]
+async fn login_with_surrounding_whitespace_does_not_replace_the_password() {
+    let app = test_app().await;
+    let user = app.create_user(TEST_PASSWORD).await;
+
+    let (status, _) = app
+        .request(
+            Method::POST,
+            "/api/user/login",
+            None,
+            Some(json!({ "email": user.email, "password": format!(" {} ", TEST_PASSWORD) })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    app.login(&user.email, TEST_PASSWORD).await;
+}
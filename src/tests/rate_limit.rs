@@ -0,0 +1,55 @@
+use axum::http::Method;
+use hyper::StatusCode;
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::tests::{TEST_PASSWORD, test_app};
+
+#[tokio::test]
+#[ignore = "needs TEST_DATABASE_URL"]
+async fn limits_profile_updates_only_when_they_change_the_email() {
+    let app = test_app().await;
+    let user = app.create_user(TEST_PASSWORD).await;
+    let tokens = app.login(&user.email, TEST_PASSWORD).await;
+    let token = tokens["access_token"].as_str().unwrap();
+
+    for i in 0..5 {
+        let (status, body) = app
+            .request(
+                Method::PATCH,
+                "/api/user/update",
+                Some(token),
+                Some(json!({ "name": format!("Renamed User {}", i) })),
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+    }
+
+    let change_email = || {
+        app.request(
+            Method::PATCH,
+            "/api/user/update",
+            Some(token),
+            Some(json!({
+                "email": format!("{}@example.com", Uuid::new_v4().simple()),
+                "current_password": TEST_PASSWORD,
+            })),
+        )
+    };
+    for _ in 0..3 {
+        let (status, body) = change_email().await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+    }
+    let (status, _) = change_email().await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+
+    let (status, body) = app
+        .request(
+            Method::PATCH,
+            "/api/user/update",
+            Some(token),
+            Some(json!({ "name": "Renamed Again" })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+}
@@ -0,0 +1,60 @@
+use axum::http::Method;
+use hyper::StatusCode;
+use serde_json::{Value, json};
+
+use crate::tests::{TEST_PASSWORD, TestApp, test_app};
+
+async fn refresh(app: &TestApp, tokens: &Value) -> (StatusCode, Value) {
+    app.request(
+        Method::POST,
+        "/api/user/refresh",
+        None,
+        Some(json!({ "refresh_token": tokens["refresh_token"] })),
+    )
+    .await
+}
+
+async fn current_user_status(app: &TestApp, tokens: &Value) -> StatusCode {
+    let token = tokens["access_token"].as_str().unwrap();
+    let (status, _) = app
+        .request(Method::GET, "/api/user/me", Some(token), None)
+        .await;
+    status
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_DATABASE_URL"]
+async fn refresh_rotates_the_token_pair() {
+    let app = test_app().await;
+    let user = app.create_user(TEST_PASSWORD).await;
+    let first = app.login(&user.email, TEST_PASSWORD).await;
+
+    let (status, second) = refresh(&app, &first).await;
+    assert_eq!(status, StatusCode::OK, "{}", second);
+    assert_ne!(second["refresh_token"], first["refresh_token"]);
+    assert_eq!(current_user_status(&app, &second).await, StatusCode::OK);
+
+    let (status, third) = refresh(&app, &second).await;
+    assert_eq!(status, StatusCode::OK, "{}", third);
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_DATABASE_URL"]
+async fn replaying_a_refresh_token_revokes_the_session() {
+    let app = test_app().await;
+    let user = app.create_user(TEST_PASSWORD).await;
+    let first = app.login(&user.email, TEST_PASSWORD).await;
+
+    let (status, second) = refresh(&app, &first).await;
+    assert_eq!(status, StatusCode::OK, "{}", second);
+
+    let (status, _) = refresh(&app, &first).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    assert_eq!(
+        current_user_status(&app, &second).await,
+        StatusCode::UNAUTHORIZED
+    );
+    let (status, _) = refresh(&app, &second).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
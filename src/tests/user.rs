@@ -0,0 +1,124 @@
+use axum::http::Method;
+use hyper::StatusCode;
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::tests::{TEST_PASSWORD, test_app};
+
+#[tokio::test]
+#[ignore = "needs TEST_DATABASE_URL"]
+async fn changes_only_the_case_of_the_own_email() {
+    let app = test_app().await;
+    let user = app.create_user(TEST_PASSWORD).await;
+    let tokens = app.login(&user.email, TEST_PASSWORD).await;
+    let token = tokens["access_token"].as_str().unwrap();
+
+    let new_email = user.email.to_uppercase();
+    let (status, body) = app
+        .request(
+            Method::PATCH,
+            "/api/user/update",
+            Some(token),
+            Some(json!({ "email": new_email, "current_password": TEST_PASSWORD })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["pending_email"], new_email);
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_DATABASE_URL"]
+async fn rejects_the_email_of_another_account_in_any_case() {
+    let app = test_app().await;
+    let user = app.create_user(TEST_PASSWORD).await;
+    let other = app.create_user(TEST_PASSWORD).await;
+    let tokens = app.login(&user.email, TEST_PASSWORD).await;
+    let token = tokens["access_token"].as_str().unwrap();
+
+    let (status, _) = app
+        .request(
+            Method::PATCH,
+            "/api/user/update",
+            Some(token),
+            Some(json!({
+                "email": other.email.to_uppercase(),
+                "current_password": TEST_PASSWORD,
+            })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_DATABASE_URL"]
+async fn email_changes_need_the_current_password() {
+    let app = test_app().await;
+    let user = app.create_user(TEST_PASSWORD).await;
+    let tokens = app.login(&user.email, TEST_PASSWORD).await;
+    let token = tokens["access_token"].as_str().unwrap();
+    let new_email = format!("{}@example.com", Uuid::new_v4().simple());
+
+    for (password, expected) in [
+        (None, StatusCode::BAD_REQUEST),
+        (Some("Wrong-Password-123"), StatusCode::FORBIDDEN),
+    ] {
+        let (status, _) = app
+            .request(
+                Method::PATCH,
+                "/api/user/update",
+                Some(token),
+                Some(json!({ "email": new_email, "current_password": password })),
+            )
+            .await;
+        assert_eq!(status, expected, "{:?}", password);
+    }
+
+    let (status, body) = app
+        .request(Method::GET, "/api/user/me", Some(token), None)
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert!(body["pending_email"].is_null());
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_DATABASE_URL"]
+async fn api_keys_can_edit_the_profile_but_not_the_email() {
+    let app = test_app().await;
+    let user = app.create_user(TEST_PASSWORD).await;
+    let tokens = app.login(&user.email, TEST_PASSWORD).await;
+    let token = tokens["access_token"].as_str().unwrap();
+
+    let (status, body) = app
+        .request(
+            Method::POST,
+            "/api/user/api-keys",
+            Some(token),
+            Some(json!({ "name": "CI" })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::CREATED, "{}", body);
+    let key = body["key"].as_str().unwrap();
+
+    let (status, body) = app
+        .request_with_api_key(
+            Method::PATCH,
+            "/api/user/update",
+            key,
+            Some(json!({ "name": "Renamed User" })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+
+    let (status, _) = app
+        .request_with_api_key(
+            Method::PATCH,
+            "/api/user/update",
+            key,
+            Some(json!({
+                "email": format!("{}@example.com", Uuid::new_v4().simple()),
+                "current_password": TEST_PASSWORD,
+            })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+}
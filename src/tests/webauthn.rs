@@ -0,0 +1,365 @@
+use axum::http::Method;
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use ciborium::Value as Cbor;
+use hyper::StatusCode;
+use p256::ecdsa::{Signature, SigningKey, signature::Signer};
+use rand::Rng;
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+
+use crate::tests::{TEST_PASSWORD, TestApp, test_app};
+
+/// `FRONTEND_URL` of the test environment, which the relying party defaults to.
+const ORIGIN: &str = "http://localhost:3000";
+const RP_ID: &str = "localhost";
+
+const FLAG_USER_PRESENT: u8 = 0x01;
+const FLAG_USER_VERIFIED: u8 = 0x04;
+const FLAG_ATTESTED_CREDENTIAL_DATA: u8 = 0x40;
+
+/// What the authenticator puts in its answer; the default is what a browser on the frontend
+/// would send after a fingerprint or PIN.
+struct Answer {
+    origin: &'static str,
+    rp_id: &'static str,
+    flags: u8,
+}
+
+impl Default for Answer {
+    fn default() -> Self {
+        Self {
+            origin: ORIGIN,
+            rp_id: RP_ID,
+            flags: FLAG_USER_PRESENT | FLAG_USER_VERIFIED,
+        }
+    }
+}
+
+/// An ES256 passkey kept in memory, answering ceremonies like a platform authenticator would.
+struct SoftwareAuthenticator {
+    key: SigningKey,
+    credential_id: Vec<u8>,
+    user_handle: Option<String>,
+    sign_count: u32,
+}
+
+impl SoftwareAuthenticator {
+    fn new() -> Self {
+        Self {
+            key: SigningKey::random(&mut rand::thread_rng()),
+            credential_id: rand::thread_rng().r#gen::<[u8; 16]>().to_vec(),
+            user_handle: None,
+            sign_count: 0,
+        }
+    }
+
+    fn cose_key(&self) -> Vec<u8> {
+        let point = self.key.verifying_key().to_encoded_point(false);
+        let key = Cbor::Map(vec![
+            (1.into(), 2.into()),
+            (3.into(), (-7).into()),
+            ((-1).into(), 1.into()),
+            ((-2).into(), Cbor::Bytes(point.x().unwrap().to_vec())),
+            ((-3).into(), Cbor::Bytes(point.y().unwrap().to_vec())),
+        ]);
+
+        let mut bytes = Vec::new();
+        ciborium::into_writer(&key, &mut bytes).unwrap();
+        bytes
+    }
+
+    fn authenticator_data(&self, answer: &Answer, attested: bool) -> Vec<u8> {
+        let mut data = Sha256::digest(answer.rp_id.as_bytes()).to_vec();
+        data.push(if attested {
+            answer.flags | FLAG_ATTESTED_CREDENTIAL_DATA
+        } else {
+            answer.flags
+        });
+        data.extend_from_slice(&self.sign_count.to_be_bytes());
+
+        if attested {
+            data.extend_from_slice(&[0; 16]);
+            data.extend_from_slice(&(self.credential_id.len() as u16).to_be_bytes());
+            data.extend_from_slice(&self.credential_id);
+            data.extend_from_slice(&self.cose_key());
+        }
+        data
+    }
+
+    /// Answer to `navigator.credentials.create()`, with a `none` attestation.
+    fn attest(&mut self, options: &Value, answer: &Answer) -> Value {
+        self.user_handle = options["user"]["id"].as_str().map(str::to_string);
+
+        let client_data = client_data("webauthn.create", options, answer);
+        let attestation = Cbor::Map(vec![
+            ("fmt".into(), "none".into()),
+            ("attStmt".into(), Cbor::Map(Vec::new())),
+            (
+                "authData".into(),
+                Cbor::Bytes(self.authenticator_data(answer, true)),
+            ),
+        ]);
+        let mut attestation_object = Vec::new();
+        ciborium::into_writer(&attestation, &mut attestation_object).unwrap();
+
+        let id = URL_SAFE_NO_PAD.encode(&self.credential_id);
+        json!({
+            "name": "Test key",
+            "credential": {
+                "id": id,
+                "rawId": id,
+                "type": "public-key",
+                "response": {
+                    "clientDataJSON": URL_SAFE_NO_PAD.encode(client_data),
+                    "attestationObject": URL_SAFE_NO_PAD.encode(attestation_object),
+                },
+            },
+        })
+    }
+
+    /// Answer to `navigator.credentials.get()`, signed with `key`.
+    fn assert_with(&self, key: &SigningKey, options: &Value, answer: &Answer) -> Value {
+        let client_data = client_data("webauthn.get", options, answer);
+        let authenticator_data = self.authenticator_data(answer, false);
+
+        let mut signed = authenticator_data.clone();
+        signed.extend_from_slice(&Sha256::digest(&client_data));
+        let signature: Signature = key.sign(&signed);
+
+        let id = URL_SAFE_NO_PAD.encode(&self.credential_id);
+        json!({
+            "id": id,
+            "rawId": id,
+            "type": "public-key",
+            "response": {
+                "clientDataJSON": URL_SAFE_NO_PAD.encode(client_data),
+                "authenticatorData": URL_SAFE_NO_PAD.encode(authenticator_data),
+                "signature": URL_SAFE_NO_PAD.encode(signature.to_der().as_bytes()),
+                "userHandle": self.user_handle,
+            },
+        })
+    }
+
+    fn assert(&mut self, options: &Value, answer: &Answer) -> Value {
+        self.sign_count += 1;
+        self.assert_with(&self.key, options, answer)
+    }
+}
+
+fn client_data(ceremony_type: &str, options: &Value, answer: &Answer) -> Vec<u8> {
+    json!({
+        "type": ceremony_type,
+        "challenge": options["challenge"],
+        "origin": answer.origin,
+        "crossOrigin": false,
+    })
+    .to_string()
+    .into_bytes()
+}
+
+async fn signed_in(app: &TestApp) -> String {
+    let user = app.create_user(TEST_PASSWORD).await;
+    let tokens = app.login(&user.email, TEST_PASSWORD).await;
+    tokens["access_token"].as_str().unwrap().to_string()
+}
+
+async fn register(
+    app: &TestApp,
+    token: &str,
+    authenticator: &mut SoftwareAuthenticator,
+    answer: &Answer,
+) -> (StatusCode, Value) {
+    let (status, options) = app
+        .request(
+            Method::POST,
+            "/api/user/webauthn/register/options",
+            Some(token),
+            None,
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", options);
+
+    let body = authenticator.attest(&options, answer);
+    app.request(
+        Method::POST,
+        "/api/user/webauthn/register",
+        Some(token),
+        Some(body),
+    )
+    .await
+}
+
+async fn registered(app: &TestApp) -> SoftwareAuthenticator {
+    let token = signed_in(app).await;
+    let mut authenticator = SoftwareAuthenticator::new();
+
+    let (status, body) = register(app, &token, &mut authenticator, &Answer::default()).await;
+    assert_eq!(status, StatusCode::CREATED, "{}", body);
+    authenticator
+}
+
+async fn login_options(app: &TestApp) -> Value {
+    let (status, options) = app
+        .request(Method::POST, "/api/user/webauthn/login/options", None, None)
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", options);
+    options
+}
+
+async fn login(app: &TestApp, body: Value) -> (StatusCode, Value) {
+    app.request(Method::POST, "/api/user/webauthn/login", None, Some(body))
+        .await
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_DATABASE_URL"]
+async fn registers_a_passkey_and_logs_in_with_it() {
+    let app = test_app().await;
+    let mut authenticator = registered(&app).await;
+
+    for _ in 0..2 {
+        let options = login_options(&app).await;
+        let (status, body) = login(&app, authenticator.assert(&options, &Answer::default())).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert!(body["access_token"].is_string());
+    }
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_DATABASE_URL"]
+async fn rejects_a_registration_from_another_origin() {
+    let app = test_app().await;
+    let token = signed_in(&app).await;
+    let answer = Answer {
+        origin: "https://evil.example.com",
+        ..Answer::default()
+    };
+
+    let (status, _) = register(&app, &token, &mut SoftwareAuthenticator::new(), &answer).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_DATABASE_URL"]
+async fn rejects_a_registration_for_another_relying_party() {
+    let app = test_app().await;
+    let token = signed_in(&app).await;
+    let answer = Answer {
+        rp_id: "evil.example.com",
+        ..Answer::default()
+    };
+
+    let (status, _) = register(&app, &token, &mut SoftwareAuthenticator::new(), &answer).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_DATABASE_URL"]
+async fn rejects_a_registration_without_user_verification() {
+    let app = test_app().await;
+    let token = signed_in(&app).await;
+    let answer = Answer {
+        flags: FLAG_USER_PRESENT,
+        ..Answer::default()
+    };
+
+    let (status, _) = register(&app, &token, &mut SoftwareAuthenticator::new(), &answer).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_DATABASE_URL"]
+async fn rejects_a_login_from_another_origin() {
+    let app = test_app().await;
+    let mut authenticator = registered(&app).await;
+    let answer = Answer {
+        origin: "https://evil.example.com",
+        ..Answer::default()
+    };
+
+    let options = login_options(&app).await;
+    let (status, _) = login(&app, authenticator.assert(&options, &answer)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_DATABASE_URL"]
+async fn rejects_a_login_for_another_relying_party() {
+    let app = test_app().await;
+    let mut authenticator = registered(&app).await;
+    let answer = Answer {
+        rp_id: "evil.example.com",
+        ..Answer::default()
+    };
+
+    let options = login_options(&app).await;
+    let (status, _) = login(&app, authenticator.assert(&options, &answer)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_DATABASE_URL"]
+async fn rejects_a_login_without_user_presence_or_verification() {
+    let app = test_app().await;
+    let mut authenticator = registered(&app).await;
+
+    for flags in [FLAG_USER_PRESENT, FLAG_USER_VERIFIED] {
+        let answer = Answer {
+            flags,
+            ..Answer::default()
+        };
+        let options = login_options(&app).await;
+        let (status, _) = login(&app, authenticator.assert(&options, &answer)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_DATABASE_URL"]
+async fn rejects_a_login_with_a_bad_signature() {
+    let app = test_app().await;
+    let mut authenticator = registered(&app).await;
+    authenticator.sign_count += 1;
+
+    let options = login_options(&app).await;
+    let other_key = SigningKey::random(&mut rand::thread_rng());
+    let body = authenticator.assert_with(&other_key, &options, &Answer::default());
+
+    let (status, _) = login(&app, body).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_DATABASE_URL"]
+async fn rejects_a_replayed_login() {
+    let app = test_app().await;
+    let mut authenticator = registered(&app).await;
+
+    let options = login_options(&app).await;
+    let body = authenticator.assert(&options, &Answer::default());
+    let (status, _) = login(&app, body.clone()).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, _) = login(&app, body).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+#[ignore = "needs TEST_DATABASE_URL"]
+async fn rejects_a_login_whose_signature_counter_does_not_increase() {
+    let app = test_app().await;
+    let mut authenticator = registered(&app).await;
+    authenticator.sign_count = 5;
+
+    let options = login_options(&app).await;
+    let (status, _) = login(&app, authenticator.assert(&options, &Answer::default())).await;
+    assert_eq!(status, StatusCode::OK);
+
+    for sign_count in [6, 3] {
+        authenticator.sign_count = sign_count;
+        let options = login_options(&app).await;
+        let body = authenticator.assert_with(&authenticator.key, &options, &Answer::default());
+        let (status, _) = login(&app, body).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+}